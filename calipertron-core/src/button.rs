@@ -0,0 +1,103 @@
+//! Telling short, long and double presses of the button apart.
+//!
+//! The button is polled every few ms. `ButtonGestures` debounces it and reports each complete gesture;
+//! what a gesture does is up to the saved `schema::ButtonBindings`.
+
+/// The button has to read the same for this long before a press or release counts.
+pub const DEBOUNCE_MS: u64 = 20;
+/// Presses held at least this long are long presses, reported as soon as the time is up.
+pub const LONG_PRESS_MS: u64 = 800;
+/// A second press starting within this long of the first release makes a double press,
+/// so short presses are only reported once it has passed.
+pub const DOUBLE_PRESS_MS: u64 = 300;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Gesture {
+    Short,
+    Long,
+    Double,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum State {
+    Idle,
+    /// First press, down since `since_ms`.
+    Pressed {
+        since_ms: u64,
+    },
+    /// Released after a short press at `at_ms`, waiting to see if a second press follows.
+    Released {
+        at_ms: u64,
+    },
+    /// Gesture already reported (or ignored), waiting for the button to come back up.
+    WaitRelease,
+}
+
+pub struct ButtonGestures {
+    /// Debounced level.
+    pressed: bool,
+    /// Level last read, and when it last changed.
+    raw: bool,
+    raw_since_ms: u64,
+    state: State,
+}
+
+impl Default for ButtonGestures {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ButtonGestures {
+    pub const fn new() -> Self {
+        ButtonGestures {
+            pressed: false,
+            raw: false,
+            raw_since_ms: 0,
+            state: State::Idle,
+        }
+    }
+
+    /// Feed in the button level (`true` while pressed) read at `now_ms`; returns a gesture once it's complete.
+    pub fn update(&mut self, now_ms: u64, raw: bool) -> Option<Gesture> {
+        if raw != self.raw {
+            self.raw = raw;
+            self.raw_since_ms = now_ms;
+        }
+
+        let was_pressed = self.pressed;
+        if self.raw != self.pressed && now_ms - self.raw_since_ms >= DEBOUNCE_MS {
+            self.pressed = self.raw;
+        }
+        let pressed_now = self.pressed && !was_pressed;
+        let released_now = !self.pressed && was_pressed;
+
+        let (state, gesture) = match self.state {
+            State::Idle if pressed_now => (State::Pressed { since_ms: now_ms }, None),
+
+            State::Pressed { .. } if released_now => (State::Released { at_ms: now_ms }, None),
+            State::Pressed { since_ms } if now_ms - since_ms >= LONG_PRESS_MS => {
+                (State::WaitRelease, Some(Gesture::Long))
+            }
+
+            State::Released { .. } if pressed_now => (State::WaitRelease, Some(Gesture::Double)),
+            State::Released { at_ms } if now_ms - at_ms >= DOUBLE_PRESS_MS => {
+                (State::Idle, Some(Gesture::Short))
+            }
+
+            State::WaitRelease if released_now => (State::Idle, None),
+
+            state => (state, None),
+        };
+        self.state = state;
+        gesture
+    }
+
+    /// The button is already down and this press shouldn't count, e.g. because it woke the chip up.
+    pub fn ignore_press(&mut self, now_ms: u64) {
+        self.pressed = true;
+        self.raw = true;
+        self.raw_since_ms = now_ms;
+        self.state = State::WaitRelease;
+    }
+}
@@ -0,0 +1,33 @@
+//! The synchronous serial output of cheap digital calipers, as read by hobby DROs and Arduino sketches.
+//!
+//! A frame is two 24-bit words sent least significant bit first, each bit on one clock pulse:
+//! the absolute position (since power on), then the relative position (what the display shows, from the last zero).
+//! Positions are two's complement in units of 1/20480 inch.
+
+#[cfg_attr(any(test, feature = "std"), allow(unused_imports))]
+use num_traits::Float;
+
+pub const COUNTS_PER_INCH: f32 = 20480.;
+pub const WORD_BITS: u32 = 24;
+pub const FRAME_BITS: u32 = 2 * WORD_BITS;
+
+const WORD_MASK: u32 = (1 << WORD_BITS) - 1;
+const MAX_COUNTS: i32 = (1 << (WORD_BITS - 1)) - 1;
+
+/// `position_mm` in the protocol's units, saturating at the 24-bit range (about +-10 m).
+pub fn to_counts(position_mm: f32) -> i32 {
+    let counts = (position_mm / 25.4 * COUNTS_PER_INCH).round();
+    // `as` saturates and maps NaN to 0
+    (counts as i32).clamp(-MAX_COUNTS - 1, MAX_COUNTS)
+}
+
+/// The 48 frame bits, first to send in bit 0.
+pub fn frame(absolute_mm: f32, relative_mm: f32) -> u64 {
+    let word = |mm| (to_counts(mm) as u32 & WORD_MASK) as u64;
+    word(absolute_mm) | word(relative_mm) << WORD_BITS
+}
+
+/// Frame bits in the order they go out on DATA.
+pub fn bits(frame: u64) -> impl Iterator<Item = bool> {
+    (0..FRAME_BITS).map(move |bit| frame & (1 << bit) != 0)
+}
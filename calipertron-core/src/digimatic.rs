@@ -0,0 +1,56 @@
+//! Mitutoyo Digimatic data output, as read by SPC data collectors and DRO boxes.
+//!
+//! When the collector pulls REQ low, the instrument clocks out a 13 nibble frame on CK and DATA, least significant bit of each nibble first:
+//!
+//! | 0xF 0xF 0xF 0xF | sign (0 = +, 8 = -) | 6 BCD digits, most significant first | decimal point (digits after it) | unit (0 = mm, 1 = inch) |
+
+#[cfg_attr(any(test, feature = "std"), allow(unused_imports))]
+use num_traits::Float;
+
+pub const FRAME_NIBBLES: usize = 13;
+pub type Frame = [u8; FRAME_NIBBLES];
+
+const DIGITS: usize = 6;
+// 10^DIGITS
+const DIGIT_LIMIT: u32 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Millimeter,
+    Inch,
+}
+
+/// Frame for `value` (already in `unit`) with `decimals` digits after the decimal point.
+/// `None` if it doesn't fit in 6 digits, or `decimals` is more than 5.
+pub fn frame(value: f32, decimals: u8, unit: Unit) -> Option<Frame> {
+    if decimals as usize >= DIGITS || !value.is_finite() {
+        return None;
+    }
+
+    let scaled = (value.abs() * 10f32.powi(decimals as i32)).round();
+    if scaled >= DIGIT_LIMIT as f32 {
+        return None;
+    }
+    let mut count = scaled as u32;
+
+    let mut frame = [0xF; FRAME_NIBBLES];
+    // rounds to zero shouldn't show as -0
+    frame[4] = if value < 0. && count != 0 { 8 } else { 0 };
+    for digit in frame[5..5 + DIGITS].iter_mut().rev() {
+        *digit = (count % 10) as u8;
+        count /= 10;
+    }
+    frame[11] = decimals;
+    frame[12] = match unit {
+        Unit::Millimeter => 0,
+        Unit::Inch => 1,
+    };
+    Some(frame)
+}
+
+/// The 52 bits of `frame` in the order they go out on DATA.
+pub fn bits(frame: &Frame) -> impl Iterator<Item = bool> + '_ {
+    frame
+        .iter()
+        .flat_map(|nibble| (0..4).map(move |bit| nibble & (1 << bit) != 0))
+}
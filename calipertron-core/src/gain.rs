@@ -0,0 +1,69 @@
+//! Automatic gain control for the amplifier in front of the pickup.
+//!
+//! `AutoGain` picks the gain from the amplitude of each capture, one step at a time.
+//! The gain steps themselves belong to the amplifier (see `schema::Gain`), so they come in through `GainStep`.
+
+/// Largest 12-bit ADC reading.
+const ADC_FULL_SCALE: u16 = 4095;
+/// Readings this close to either rail count as clipped.
+const CLIP_MARGIN: u16 = 16;
+/// Step down when a capture spans more than this much of the ADC range...
+const MAX_SPAN: f32 = 0.9;
+/// ...and step up when the next gain would still keep it under this much.
+const TARGET_SPAN: f32 = 0.5;
+
+/// An amplifier gain setting, in a fixed ladder of steps.
+pub trait GainStep: Copy {
+    /// Amplification at this step.
+    fn factor(&self) -> u8;
+    /// Next step up, if there is one.
+    fn higher(&self) -> Option<Self>;
+    /// Next step down, if there is one.
+    fn lower(&self) -> Option<Self>;
+}
+
+/// Whether any reading in the capture is at (or within `CLIP_MARGIN` of) either ADC rail.
+pub fn clipped(samples: &[u16]) -> bool {
+    samples
+        .iter()
+        .any(|&s| s <= CLIP_MARGIN || s >= ADC_FULL_SCALE - CLIP_MARGIN)
+}
+
+/// Picks the amplifier gain from the amplitude of each capture, one step at a time.
+///
+/// Adjacent gains should be at most 2x apart, so a capture that steps down can't immediately qualify for stepping back up.
+#[derive(Debug, Clone)]
+pub struct AutoGain<G> {
+    pub gain: G,
+}
+
+impl<G: GainStep> AutoGain<G> {
+    pub fn new(gain: G) -> Self {
+        AutoGain { gain }
+    }
+
+    /// Look at a capture taken at the current gain, returning the new gain if it should change.
+    pub fn update(&mut self, samples: &[u16]) -> Option<G> {
+        let (min, max) = samples.iter().fold((u16::MAX, u16::MIN), |(min, max), &s| {
+            (min.min(s), max.max(s))
+        });
+        if min > max {
+            return None;
+        }
+
+        let clipped = min <= CLIP_MARGIN || max >= ADC_FULL_SCALE - CLIP_MARGIN;
+        let span = (max - min) as f32 / ADC_FULL_SCALE as f32;
+
+        let new_gain = if clipped || span > MAX_SPAN {
+            self.gain.lower()
+        } else {
+            self.gain.higher().filter(|higher| {
+                let ratio = higher.factor() as f32 / self.gain.factor() as f32;
+                span * ratio < TARGET_SPAN
+            })
+        }?;
+
+        self.gain = new_gain;
+        Some(new_gain)
+    }
+}
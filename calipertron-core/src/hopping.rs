@@ -0,0 +1,118 @@
+//! Combining captures taken at several drive frequencies into one phase.
+//!
+//! Narrowband interference near one drive frequency throws that frequency's phase around while the others stay put.
+//! Each frequency's phase is compared with the combined phase: the steady part of the difference (the front end's phase lag
+//! at that frequency) is learned and taken out, and the spread around it decides how much that frequency counts.
+//! With only two frequencies there's no telling which one is off, so both count equally; use three or more.
+
+use core::f32::consts::PI;
+
+#[cfg_attr(any(test, feature = "std"), allow(unused_imports))]
+use num_traits::Float;
+
+/// Spread (radians²) every frequency starts with, so they count equally until they've been compared a few times.
+const INITIAL_VARIANCE: f32 = 0.01;
+/// Floor on the spread, so one very steady frequency can't drown out the rest.
+const MIN_VARIANCE: f32 = 1e-4;
+/// How quickly the spread follows new deviations, per update.
+const VARIANCE_RATE: f32 = 0.1;
+/// How quickly the learned offsets move, per update; slow, so interference doesn't get learned as offset.
+const OFFSET_RATE: f32 = 0.02;
+
+#[derive(Debug, Clone, Copy)]
+struct Channel {
+    offset: f32,
+    variance: f32,
+}
+
+impl Channel {
+    const NEW: Channel = Channel {
+        offset: 0.,
+        variance: INITIAL_VARIANCE,
+    };
+
+    fn weight(&self) -> f32 {
+        1. / self.variance.max(MIN_VARIANCE)
+    }
+}
+
+/// Combines the phases measured at up to `N` drive frequencies, weighted by how well each has agreed with the rest.
+pub struct PhaseCombiner<const N: usize> {
+    channels: [Channel; N],
+    /// Frequencies used in the last update.
+    used: usize,
+}
+
+impl<const N: usize> Default for PhaseCombiner<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> PhaseCombiner<N> {
+    pub const fn new() -> Self {
+        PhaseCombiner {
+            channels: [Channel::NEW; N],
+            used: 0,
+        }
+    }
+
+    /// Combine one phase per drive frequency (in [-PI, PI], always in the same order) into a single phase in [-PI, PI].
+    /// Phases past the first `N` are ignored.
+    pub fn update(&mut self, phases: &[f32]) -> f32 {
+        let used = phases.len().min(N);
+        let channels = &mut self.channels[..used];
+        self.used = used;
+
+        let (mut sum_sine, mut sum_cosine) = (0., 0.);
+        for (channel, &phase) in channels.iter().zip(phases) {
+            let weight = channel.weight();
+            sum_sine += weight * (phase - channel.offset).sin();
+            sum_cosine += weight * (phase - channel.offset).cos();
+        }
+        let combined = sum_sine.atan2(sum_cosine);
+
+        for (channel, &phase) in channels.iter_mut().zip(phases) {
+            let deviation = wrap(phase - channel.offset - combined);
+            channel.offset = wrap(channel.offset + OFFSET_RATE * deviation);
+            channel.variance += VARIANCE_RATE * (deviation * deviation - channel.variance);
+        }
+
+        // Offsets are only known relative to each other; keep their weighted mean at zero so they can't drift together.
+        let total_weight: f32 = channels.iter().map(Channel::weight).sum();
+        let mean_offset = channels
+            .iter()
+            .map(|c| c.weight() * c.offset)
+            .sum::<f32>()
+            / total_weight;
+        for channel in channels.iter_mut() {
+            channel.offset = wrap(channel.offset - mean_offset);
+        }
+
+        combined
+    }
+
+    /// Fraction (0 to 1) of the next combined phase that will come from frequency `index`.
+    pub fn share(&self, index: usize) -> f32 {
+        let channels = &self.channels[..self.used];
+        match channels.get(index) {
+            Some(channel) => channel.weight() / channels.iter().map(Channel::weight).sum::<f32>(),
+            None => 0.,
+        }
+    }
+
+    /// Learned phase lag (radians) of frequency `index` relative to the others.
+    pub fn offset(&self, index: usize) -> f32 {
+        self.channels.get(index).map_or(0., |c| c.offset)
+    }
+
+    /// Forget what's been learned, e.g. when the set of frequencies changes.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// `angle` moved into [-PI, PI).
+fn wrap(angle: f32) -> f32 {
+    angle - 2. * PI * ((angle + PI) / (2. * PI)).floor()
+}
@@ -2,7 +2,20 @@
 
 use core::f32::consts::PI;
 
+#[cfg_attr(any(test, feature = "std"), allow(unused_imports))]
 use num_traits::Float;
+
+pub mod button;
+pub mod chinese_caliper;
+pub mod digimatic;
+pub mod gain;
+pub mod hopping;
+pub mod measure;
+pub mod power;
+pub mod quadrature;
+pub mod readout;
+pub mod units;
+
 pub struct PhaseAccumulator {
     pub unwrapped_phase: f32,
     last_phase: f32,
@@ -35,3 +48,32 @@ impl PhaseAccumulator {
         }
     }
 }
+
+/// Correct a wrapped phase in [-PI, PI] with a table of offsets sampled at evenly spaced points across one cycle (starting at -PI), linearly interpolating between them.
+pub fn correct_phase(phase: f32, table: &[f32]) -> f32 {
+    if table.is_empty() {
+        return phase;
+    }
+
+    let n = table.len();
+    let position = (phase + PI) / (2.0 * PI) * n as f32;
+    let idx = (position.floor() as usize).min(n - 1);
+    let frac = position - idx as f32;
+
+    let correction = table[idx] * (1.0 - frac) + table[(idx + 1) % n] * frac;
+    phase + correction
+}
+
+/// The part of `position_mm` within one scale pitch, in [-pitch_mm / 2, pitch_mm / 2).
+/// `PhaseAccumulator` starts from zero at every power-up, so whole pitches of a zero offset don't survive a reset; only this part does,
+/// and only if the slider hasn't moved by more than half a pitch while the power was off.
+pub fn within_pitch(position_mm: f32, pitch_mm: f32) -> f32 {
+    position_mm - pitch_mm * (position_mm / pitch_mm + 0.5).floor()
+}
+
+/// Split 32-bit words read from the ADC1 data register in dual-ADC mode into samples, in the order they were taken.
+/// In fast interleaved mode ADC2 converts first and ADC1 (the master) 7 ADC clocks later (RM0008 section 11.9.5),
+/// and ADC2's result is in the high half of the word, so that half comes first.
+pub fn unpack_dual_adc(words: &[u32]) -> impl Iterator<Item = u16> + '_ {
+    words.iter().flat_map(|&w| [(w >> 16) as u16, w as u16])
+}
@@ -0,0 +1,65 @@
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+
+#[cfg_attr(any(test, feature = "std"), allow(unused_imports))]
+use num_traits::Float;
+
+/// Drives the emitter waveform onto the scale.
+#[allow(async_fn_in_trait)]
+pub trait Emitter {
+    /// Start emitting from the beginning of the waveform.
+    fn start(&mut self);
+
+    /// Stop at the end of the current waveform cycle, returning once the outputs are idle.
+    async fn stop(&mut self);
+}
+
+/// Reads the pickup signal.
+#[allow(async_fn_in_trait)]
+pub trait Sampler {
+    /// Fill `buf` with consecutive samples.
+    ///
+    /// Implementations must be armed by the time the future is first polled (i.e. before their first `.await`),
+    /// since sampling may be triggered by the emitter and `capture` only starts it after that.
+    async fn capture(&mut self, buf: &mut [u16]);
+}
+
+/// Take one capture of the pickup signal while the emitter is running.
+pub async fn capture(emitter: &mut impl Emitter, sampler: &mut impl Sampler, buf: &mut [u16]) {
+    let mut samples = pin!(sampler.capture(buf));
+    let mut started = false;
+    poll_fn(|cx| {
+        let poll = samples.as_mut().poll(cx);
+        if !started {
+            emitter.start();
+            started = true;
+        }
+        poll
+    })
+    .await;
+    emitter.stop().await;
+}
+
+/// Phase of the pickup signal, from correlating it with a table of (sine, cosine) of the emitter frequency at each sample.
+pub fn phase(samples: &[u16], sine_cosine_table: &[(f32, f32)]) -> f32 {
+    let mut sum_sine: f32 = 0.0;
+    let mut sum_cosine: f32 = 0.0;
+    for (&sample, &(sine, cosine)) in samples.iter().zip(sine_cosine_table) {
+        sum_sine += sample as f32 * sine;
+        sum_cosine += sample as f32 * cosine;
+    }
+    sum_sine.atan2(sum_cosine)
+}
+
+/// Amplitude of the pickup signal at the emitter frequency, in ADC counts, from the same correlation as `phase`.
+/// The table has to span whole cycles, so the ADC's DC offset cancels out.
+pub fn amplitude(samples: &[u16], sine_cosine_table: &[(f32, f32)]) -> f32 {
+    let mut sum_sine: f32 = 0.0;
+    let mut sum_cosine: f32 = 0.0;
+    for (&sample, &(sine, cosine)) in samples.iter().zip(sine_cosine_table) {
+        sum_sine += sample as f32 * sine;
+        sum_cosine += sample as f32 * cosine;
+    }
+    let n = samples.len().min(sine_cosine_table.len()) as f32;
+    2.0 * (sum_sine * sum_sine + sum_cosine * sum_cosine).sqrt() / n
+}
@@ -0,0 +1,165 @@
+//! When to measure, and when to sleep, for battery powered builds.
+//!
+//! Captures run at `active_interval_ms` while the reading changes, drop to `stable_interval_ms` once it has been still for `stable_after_ms`,
+//! and after `stop_after_ms` the chip goes into Stop mode, waking every `probe_interval_ms` for one capture to check for motion.
+
+// `abs` is the only float method used here, and it's inherent on f32 even without std, so there's no `num_traits::Float` to import.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerConfig {
+    pub active_interval_ms: u32,
+    pub stable_interval_ms: u32,
+    /// Movement smaller than this, from where the reading settled, counts as still.
+    pub stable_threshold_mm: f32,
+    pub stable_after_ms: u32,
+    pub stop_after_ms: u32,
+    /// Also bounds how fast the slider can move while stopped: more than half the scale pitch between probes and the phase wraps unseen.
+    pub probe_interval_ms: u32,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        PowerConfig {
+            active_interval_ms: 10,
+            stable_interval_ms: 200,
+            stable_threshold_mm: 0.02,
+            stable_after_ms: 2_000,
+            stop_after_ms: 60_000,
+            probe_interval_ms: 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerMode {
+    Active,
+    Stable,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerAction {
+    /// Wait (in Sleep mode, with everything clocked) before the next capture.
+    Measure { after_ms: u32 },
+    /// Enter Stop mode until the button or the motion probe.
+    Stop { probe_after_ms: u32 },
+}
+
+pub struct PowerPolicy {
+    config: PowerConfig,
+    mode: PowerMode,
+    /// Where the reading settled, and when.
+    still_at_mm: f32,
+    still_since_ms: u64,
+}
+
+impl PowerPolicy {
+    pub fn new(config: PowerConfig, now_ms: u64, position_mm: f32) -> Self {
+        PowerPolicy {
+            config,
+            mode: PowerMode::Active,
+            still_at_mm: position_mm,
+            still_since_ms: now_ms,
+        }
+    }
+
+    pub fn mode(&self) -> PowerMode {
+        self.mode
+    }
+
+    /// Take the position from a capture (or a motion probe) and decide what to do next.
+    /// `now_ms` doesn't need to include time spent in Stop mode.
+    pub fn update(&mut self, now_ms: u64, position_mm: f32) -> PowerAction {
+        if (position_mm - self.still_at_mm).abs() > self.config.stable_threshold_mm {
+            self.still_at_mm = position_mm;
+            self.still_since_ms = now_ms;
+            self.mode = PowerMode::Active;
+        } else if self.mode != PowerMode::Stopped {
+            let still_for_ms = now_ms.saturating_sub(self.still_since_ms);
+            self.mode = if still_for_ms >= self.config.stop_after_ms as u64 {
+                PowerMode::Stopped
+            } else if still_for_ms >= self.config.stable_after_ms as u64 {
+                PowerMode::Stable
+            } else {
+                PowerMode::Active
+            };
+        }
+        self.action()
+    }
+
+    /// Someone pressed the button: back to full rate, as if the reading had just changed.
+    pub fn wake(&mut self, now_ms: u64) {
+        self.still_since_ms = now_ms;
+        self.mode = PowerMode::Active;
+    }
+
+    pub fn action(&self) -> PowerAction {
+        match self.mode {
+            PowerMode::Active => PowerAction::Measure {
+                after_ms: self.config.active_interval_ms,
+            },
+            PowerMode::Stable => PowerAction::Measure {
+                after_ms: self.config.stable_interval_ms,
+            },
+            PowerMode::Stopped => PowerAction::Stop {
+                probe_after_ms: self.config.probe_interval_ms,
+            },
+        }
+    }
+}
+
+////////////////////////
+// Current budget
+
+/// Supply current in each state, in mA.
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CurrentModel {
+    /// CPU running at 72 MHz with the PDM timer, DMA and ADC going, plus the emitter drive.
+    pub capture_mA: f32,
+    /// Sleep mode (WFE) between captures, peripheral clocks still on.
+    pub idle_mA: f32,
+    /// Stop mode, with the IWDG and RTC running on the LSI.
+    pub stop_mA: f32,
+}
+
+/// STM32F103 datasheet typical values at 72 MHz, plus a rough 4 mA for the emitter pads (not measured).
+/// Doesn't include the regulator, LEDs or anything else on the board.
+pub const STM32F103_CURRENT: CurrentModel = CurrentModel {
+    capture_mA: 40.,
+    idle_mA: 14.4,
+    stop_mA: 0.024,
+};
+
+/// Time spent in each state, for an average current estimate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CurrentBudget {
+    pub capture_ms: u64,
+    pub idle_ms: u64,
+    pub stop_ms: u64,
+}
+
+impl CurrentBudget {
+    pub fn total_ms(&self) -> u64 {
+        self.capture_ms + self.idle_ms + self.stop_ms
+    }
+
+    /// Time weighted average current in mA, or 0 if no time has been recorded.
+    #[allow(non_snake_case)]
+    pub fn average_mA(&self, model: &CurrentModel) -> f32 {
+        let total = self.total_ms();
+        if total == 0 {
+            return 0.;
+        }
+        (self.capture_ms as f32 * model.capture_mA
+            + self.idle_ms as f32 * model.idle_mA
+            + self.stop_ms as f32 * model.stop_mA)
+            / total as f32
+    }
+
+    /// Hours a battery of `capacity_mAh` would last at the average current.
+    #[allow(non_snake_case)]
+    pub fn battery_life_hours(&self, model: &CurrentModel, capacity_mAh: f32) -> f32 {
+        capacity_mAh / self.average_mA(model)
+    }
+}
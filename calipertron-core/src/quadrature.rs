@@ -0,0 +1,76 @@
+//! Incremental quadrature (A/B, plus optional index) output, for CNC controllers and DROs that take an encoder.
+//!
+//! Each count is one edge on A or B (what the receiver sees with x4 decoding). A leads B when the position increases.
+
+#[cfg_attr(any(test, feature = "std"), allow(unused_imports))]
+use num_traits::Float;
+
+/// Fraction of a count the position has to move past the halfway point before the output follows,
+/// so noise around a count boundary doesn't make the output dither.
+pub const HYSTERESIS_COUNTS: f32 = 0.25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Outputs {
+    pub a: bool,
+    pub b: bool,
+    pub index: bool,
+}
+
+pub struct Quadrature {
+    mm_per_count: f32,
+    /// Counts between index pulses, 0 for none.
+    index_every: u32,
+    /// Last count put on the outputs.
+    count: i32,
+    target: i32,
+}
+
+impl Quadrature {
+    /// Starts at count 0 (A and B low), which is also where the index pulses.
+    pub fn new(mm_per_count: f32, index_every: u32) -> Self {
+        Quadrature {
+            mm_per_count,
+            index_every,
+            count: 0,
+            target: 0,
+        }
+    }
+
+    /// Move the target to `position_mm`; `step` then walks the outputs there.
+    pub fn set_position(&mut self, position_mm: f32) {
+        let counts = position_mm / self.mm_per_count;
+        if (counts - self.target as f32).abs() > 0.5 + HYSTERESIS_COUNTS {
+            // saturates for positions far outside the i32 range
+            self.target = counts.round() as i32;
+        }
+    }
+
+    pub fn count(&self) -> i32 {
+        self.count
+    }
+
+    pub fn is_settled(&self) -> bool {
+        self.count == self.target
+    }
+
+    pub fn outputs(&self) -> Outputs {
+        let (a, b) = match self.count.rem_euclid(4) {
+            0 => (false, false),
+            1 => (true, false),
+            2 => (true, true),
+            _ => (false, true),
+        };
+        let index = self.index_every != 0 && self.count.rem_euclid(self.index_every as i32) == 0;
+        Outputs { a, b, index }
+    }
+
+    /// Move one count towards the target, returning the new outputs, or `None` if already there.
+    /// Call at most once per edge period, which sets the fastest the outputs change.
+    pub fn step(&mut self) -> Option<Outputs> {
+        if self.is_settled() {
+            return None;
+        }
+        self.count += (self.target - self.count).signum();
+        Some(self.outputs())
+    }
+}
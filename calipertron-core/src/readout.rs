@@ -0,0 +1,90 @@
+//! Display modes found on commercial calipers, layered over the (zeroed) position:
+//! hold, min/max tracking and relative (ABS/INC) readings.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Live position from the saved zero (ABS).
+    Absolute,
+    /// Frozen at the position when hold started.
+    Hold,
+    /// Live position, plus the smallest and largest seen since the mode started.
+    MinMax,
+    /// Position from where the mode started (INC); the saved zero is left alone.
+    Relative,
+}
+
+impl Mode {
+    /// Next mode when stepping through them all with one button.
+    pub fn next(self) -> Mode {
+        match self {
+            Mode::Absolute => Mode::Hold,
+            Mode::Hold => Mode::MinMax,
+            Mode::MinMax => Mode::Relative,
+            Mode::Relative => Mode::Absolute,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+    pub mode: Mode,
+    /// What the display shows, in mm.
+    pub value_mm: f32,
+    /// Smallest and largest position in `MinMax` mode.
+    pub min_max_mm: Option<(f32, f32)>,
+}
+
+pub struct Readout {
+    mode: Mode,
+    position_mm: f32,
+    /// Held value in `Hold`, origin in `Relative`.
+    reference_mm: f32,
+    min_mm: f32,
+    max_mm: f32,
+}
+
+impl Readout {
+    pub fn new(position_mm: f32) -> Self {
+        Readout {
+            mode: Mode::Absolute,
+            position_mm,
+            reference_mm: position_mm,
+            min_mm: position_mm,
+            max_mm: position_mm,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Switch to `mode` at the latest position.
+    /// Starting a mode again restarts it: hold takes the position again, min/max forgets its extremes and relative re-zeroes.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.reference_mm = self.position_mm;
+        self.min_mm = self.position_mm;
+        self.max_mm = self.position_mm;
+    }
+
+    /// Take a new position, zero offset already applied.
+    pub fn update(&mut self, position_mm: f32) -> Reading {
+        self.position_mm = position_mm;
+        self.min_mm = self.min_mm.min(position_mm);
+        self.max_mm = self.max_mm.max(position_mm);
+        self.reading()
+    }
+
+    pub fn reading(&self) -> Reading {
+        let value_mm = match self.mode {
+            Mode::Absolute | Mode::MinMax => self.position_mm,
+            Mode::Hold => self.reference_mm,
+            Mode::Relative => self.position_mm - self.reference_mm,
+        };
+        Reading {
+            mode: self.mode,
+            value_mm,
+            min_max_mm: (self.mode == Mode::MinMax).then_some((self.min_mm, self.max_mm)),
+        }
+    }
+}
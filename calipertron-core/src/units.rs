@@ -0,0 +1,140 @@
+//! Positions as people read them: mm to 0.01, decimal inches to 0.0005 and fractional inches to the nearest 1/64.
+//!
+//! Values are rounded half away from zero, and anything that rounds to zero is printed without a sign.
+//! Formatting goes through `core::fmt`, so it works into any buffer without allocating.
+
+use core::fmt;
+
+#[cfg_attr(any(test, feature = "std"), allow(unused_imports))]
+use num_traits::Float;
+
+pub const MM_PER_INCH: f32 = 25.4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Millimeter,
+    /// Decimal inches.
+    Inch,
+    /// Whole inches and a reduced fraction, e.g. `1 3/16`.
+    FractionalInch,
+}
+
+/// Finest fraction `Unit::FractionalInch` shows.
+pub const FRACTION_DENOMINATOR: u32 = 64;
+
+impl Unit {
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Unit::Millimeter => "mm",
+            Unit::Inch | Unit::FractionalInch => "in",
+        }
+    }
+
+    /// Steps of the unit's resolution per mm.
+    fn steps_per_mm(self) -> f64 {
+        match self {
+            Unit::Millimeter => 100.,
+            Unit::Inch => 2000. / MM_PER_INCH as f64,
+            Unit::FractionalInch => FRACTION_DENOMINATOR as f64 / MM_PER_INCH as f64,
+        }
+    }
+
+    /// Size of one step of the resolution, in the unit itself.
+    pub fn resolution(self) -> f32 {
+        match self {
+            Unit::Millimeter => 0.01,
+            Unit::Inch => 0.0005,
+            Unit::FractionalInch => 1. / FRACTION_DENOMINATOR as f32,
+        }
+    }
+
+    /// The position in whole steps of the resolution (0.01 mm, 0.0005 inch or 1/64 inch), rounded half away from zero.
+    pub fn steps(self, position_mm: f32) -> i64 {
+        // in f64 so the multiplication doesn't move values off a rounding boundary
+        (position_mm as f64 * self.steps_per_mm()).round() as i64
+    }
+
+    /// The position in this unit, rounded to its resolution.
+    pub fn round(self, position_mm: f32) -> f32 {
+        self.steps(position_mm) as f32 * self.resolution()
+    }
+}
+
+/// A rounded position, ready to print with `{}`: a plain number, without the unit suffix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Formatted {
+    /// `value / 10^decimals`.
+    Decimal { value: i64, decimals: u8 },
+    /// `value / FRACTION_DENOMINATOR` inches.
+    Fraction { value: i64 },
+}
+
+/// The position in `unit` at its resolution: "12.35", "-0.4860" or "1 3/16".
+pub fn format(position_mm: f32, unit: Unit) -> Formatted {
+    let steps = unit.steps(position_mm);
+    match unit {
+        Unit::Millimeter => Formatted::Decimal {
+            value: steps,
+            decimals: 2,
+        },
+        // each 0.0005 step is 5 ten-thousandths
+        Unit::Inch => Formatted::Decimal {
+            value: steps * 5,
+            decimals: 4,
+        },
+        Unit::FractionalInch => Formatted::Fraction { value: steps },
+    }
+}
+
+/// `value` (already in the units to print) with a fixed number of decimals.
+pub fn format_decimals(value: f32, decimals: u8) -> Formatted {
+    let scale = 10f64.powi(decimals as i32);
+    Formatted::Decimal {
+        value: (value as f64 * scale).round() as i64,
+        decimals,
+    }
+}
+
+impl fmt::Display for Formatted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Formatted::Decimal { value, decimals } => {
+                if value < 0 {
+                    f.write_str("-")?;
+                }
+                let scale = 10u64.pow(decimals as u32);
+                let magnitude = value.unsigned_abs();
+                write!(f, "{}", magnitude / scale)?;
+                if decimals > 0 {
+                    write!(
+                        f,
+                        ".{:0width$}",
+                        magnitude % scale,
+                        width = decimals as usize
+                    )?;
+                }
+                Ok(())
+            }
+
+            Formatted::Fraction { value } => {
+                if value < 0 {
+                    f.write_str("-")?;
+                }
+                let magnitude = value.unsigned_abs();
+                let whole = magnitude / FRACTION_DENOMINATOR as u64;
+                let numerator = magnitude % FRACTION_DENOMINATOR as u64;
+                // the denominator is a power of two, so dividing out the numerator's factors of two reduces it
+                let shift = numerator
+                    .trailing_zeros()
+                    .min(FRACTION_DENOMINATOR.trailing_zeros());
+                let (numerator, denominator) =
+                    (numerator >> shift, FRACTION_DENOMINATOR as u64 >> shift);
+                match (whole, numerator) {
+                    (whole, 0) => write!(f, "{}", whole),
+                    (0, numerator) => write!(f, "{}/{}", numerator, denominator),
+                    (whole, numerator) => write!(f, "{} {}/{}", whole, numerator, denominator),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use calipertron_core::button::*;
+
+const POLL_MS: u64 = 5;
+
+/// A button polled every `POLL_MS`.
+#[derive(Default)]
+struct Button {
+    gestures: ButtonGestures,
+    now_ms: u64,
+}
+
+impl Button {
+    /// Hold each level for its duration and collect what gets reported.
+    fn run(&mut self, levels: &[(bool, u64)]) -> Vec<Gesture> {
+        let mut reported = vec![];
+        for &(pressed, duration_ms) in levels {
+            let end_ms = self.now_ms + duration_ms;
+            while self.now_ms < end_ms {
+                reported.extend(self.gestures.update(self.now_ms, pressed));
+                self.now_ms += POLL_MS;
+            }
+        }
+        reported
+    }
+}
+
+#[test]
+fn short_press() {
+    let mut button = Button::default();
+    let reported = button.run(&[(false, 50), (true, 100), (false, 500)]);
+    assert_eq!(reported, [Gesture::Short]);
+}
+
+#[test]
+fn short_press_waits_for_a_possible_second_press() {
+    let mut button = Button::default();
+    let reported = button.run(&[(true, 100), (false, DOUBLE_PRESS_MS - 2 * POLL_MS)]);
+    assert_eq!(reported, []);
+}
+
+#[test]
+fn long_press_is_reported_while_held() {
+    let mut button = Button::default();
+    let reported = button.run(&[(true, LONG_PRESS_MS + 100)]);
+    assert_eq!(reported, [Gesture::Long]);
+
+    // and releasing it doesn't add a short press
+    let reported = button.run(&[(false, 1000)]);
+    assert_eq!(reported, []);
+}
+
+#[test]
+fn double_press() {
+    let mut button = Button::default();
+    let reported = button.run(&[(true, 80), (false, 120), (true, 80), (false, 1000)]);
+    assert_eq!(reported, [Gesture::Double]);
+}
+
+#[test]
+fn slow_presses_are_two_short_presses() {
+    let mut button = Button::default();
+    let reported = button.run(&[(true, 80), (false, 500), (true, 80), (false, 500)]);
+    assert_eq!(reported, [Gesture::Short, Gesture::Short]);
+}
+
+#[test]
+fn bounces_are_ignored() {
+    let mut button = Button::default();
+    let reported = button.run(&[
+        // contact bounce on press and release
+        (true, 5),
+        (false, 5),
+        (true, 5),
+        (false, 5),
+        (true, 100),
+        (false, 5),
+        (true, 5),
+        (false, 500),
+    ]);
+    assert_eq!(reported, [Gesture::Short]);
+
+    // glitches shorter than the debounce time never make a press
+    let reported = button.run(&[(true, 10), (false, 500)]);
+    assert_eq!(reported, []);
+}
+
+#[test]
+fn ignored_press_reports_nothing() {
+    let mut button = Button::default();
+    button.gestures.ignore_press(0);
+    let reported = button.run(&[(true, 100), (false, 500)]);
+    assert_eq!(reported, []);
+
+    let reported = button.run(&[(true, 100), (false, 500)]);
+    assert_eq!(reported, [Gesture::Short]);
+}
@@ -0,0 +1,39 @@
+use calipertron_core::chinese_caliper::*;
+
+#[test]
+fn counts() {
+    assert_eq!(to_counts(0.), 0);
+    assert_eq!(to_counts(25.4), 20480);
+    assert_eq!(to_counts(-25.4), -20480);
+    assert_eq!(to_counts(0.01), 8);
+    assert_eq!(to_counts(1e9), (1 << 23) - 1);
+    assert_eq!(to_counts(-1e9), -(1 << 23));
+    assert_eq!(to_counts(f32::NAN), 0);
+}
+
+#[test]
+fn reference_frames() {
+    assert_eq!(frame(0., 0.), 0);
+    // 1 inch absolute, 0 relative: 20480 = 0x005000
+    assert_eq!(frame(25.4, 0.), 0x000000_005000);
+    // relative word is second
+    assert_eq!(frame(25.4, 25.4), 0x005000_005000);
+    // -1 count is all ones in its own word only
+    assert_eq!(frame(-25.4 / 20480., 0.), 0x000000_FFFFFF);
+    // 100 mm absolute, -12.7 mm relative
+    assert_eq!(frame(100., -12.7), 0xFFD800_013AF6);
+}
+
+#[test]
+fn bits_are_lsb_first() {
+    let bits: Vec<bool> = bits(frame(25.4, -25.4 / 20480.)).collect();
+    assert_eq!(bits.len(), 48);
+    let set: Vec<usize> = bits[..24]
+        .iter()
+        .enumerate()
+        .filter(|(_, &b)| b)
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(set, [12, 14]);
+    assert!(bits[24..].iter().all(|&b| b));
+}
@@ -0,0 +1,57 @@
+use calipertron_core::digimatic::*;
+
+#[test]
+fn reference_frames() {
+    // 123.45 mm
+    assert_eq!(
+        frame(123.45, 2, Unit::Millimeter),
+        Some([0xF, 0xF, 0xF, 0xF, 0, 0, 1, 2, 3, 4, 5, 2, 0])
+    );
+    // -1.2345 in
+    assert_eq!(
+        frame(-1.2345, 4, Unit::Inch),
+        Some([0xF, 0xF, 0xF, 0xF, 8, 0, 1, 2, 3, 4, 5, 4, 1])
+    );
+    // 0.00 mm
+    assert_eq!(
+        frame(0., 2, Unit::Millimeter),
+        Some([0xF, 0xF, 0xF, 0xF, 0, 0, 0, 0, 0, 0, 0, 2, 0])
+    );
+    // 150 mm with no decimal point
+    assert_eq!(
+        frame(150., 0, Unit::Millimeter),
+        Some([0xF, 0xF, 0xF, 0xF, 0, 0, 0, 0, 1, 5, 0, 0, 0])
+    );
+}
+
+#[test]
+fn rounding() {
+    assert_eq!(
+        frame(0.005, 2, Unit::Millimeter),
+        frame(0.01, 2, Unit::Millimeter)
+    );
+    // too small to show, so not negative either
+    assert_eq!(
+        frame(-0.001, 2, Unit::Millimeter),
+        frame(0., 2, Unit::Millimeter)
+    );
+}
+
+#[test]
+fn out_of_range() {
+    assert_eq!(frame(9999.99, 2, Unit::Millimeter).map(|f| f[5]), Some(9));
+    assert_eq!(frame(10000., 2, Unit::Millimeter), None);
+    assert_eq!(frame(1., 6, Unit::Millimeter), None);
+    assert_eq!(frame(f32::NAN, 2, Unit::Millimeter), None);
+}
+
+#[test]
+fn bits_are_lsb_first() {
+    let frame = [0xF, 0xF, 0xF, 0xF, 8, 0, 0, 0, 0, 0, 1, 2, 0];
+    let bits: Vec<bool> = bits(&frame).collect();
+    assert_eq!(bits.len(), 52);
+    assert!(bits[..16].iter().all(|&b| b));
+    assert_eq!(bits[16..20], [false, false, false, true]);
+    assert_eq!(bits[40..44], [true, false, false, false]);
+    assert_eq!(bits[44..48], [false, true, false, false]);
+}
@@ -0,0 +1,61 @@
+use calipertron_core::gain::*;
+
+/// The MCP6S21's gain ladder.
+const FACTORS: [u8; 8] = [1, 2, 4, 5, 8, 10, 16, 32];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Step(usize);
+
+impl GainStep for Step {
+    fn factor(&self) -> u8 {
+        FACTORS[self.0]
+    }
+
+    fn higher(&self) -> Option<Step> {
+        (self.0 + 1 < FACTORS.len()).then(|| Step(self.0 + 1))
+    }
+
+    fn lower(&self) -> Option<Step> {
+        self.0.checked_sub(1).map(Step)
+    }
+}
+
+#[test]
+fn auto_gain_steps_up_on_small_signals() {
+    let mut agc = AutoGain::new(Step(0));
+    // ~10% of full scale
+    let samples = [1800, 2200, 1800, 2200];
+    assert_eq!(agc.update(&samples), Some(Step(1)));
+    assert_eq!(agc.update(&samples), Some(Step(2)));
+}
+
+#[test]
+fn auto_gain_holds_good_signals() {
+    let mut agc = AutoGain::new(Step(4));
+    // ~70% of full scale: too big to double, small enough to keep
+    assert_eq!(agc.update(&[600, 3500]), None);
+    assert_eq!(agc.gain, Step(4));
+}
+
+#[test]
+fn auto_gain_backs_off_when_clipping() {
+    let mut agc = AutoGain::new(Step(6));
+    // small swing, but pinned against the top rail by the amplified DC offset
+    assert_eq!(agc.update(&[3900, 4095]), Some(Step(5)));
+
+    let mut agc = AutoGain::new(Step(0));
+    assert_eq!(agc.update(&[0, 4095]), None);
+}
+
+#[test]
+fn auto_gain_ignores_empty_captures() {
+    let mut agc = AutoGain::new(Step(3));
+    assert_eq!(agc.update(&[]), None);
+}
+
+#[test]
+fn clipping_near_either_rail() {
+    assert!(!clipped(&[2048, 100, 3900]));
+    assert!(clipped(&[2048, 10]));
+    assert!(clipped(&[4090, 2048]));
+}
@@ -0,0 +1,68 @@
+use calipertron_core::hopping::*;
+use core::f32::consts::PI;
+
+fn wrap(angle: f32) -> f32 {
+    (angle + PI).rem_euclid(2. * PI) - PI
+}
+
+/// Slider moving steadily, so the true phase keeps wrapping round.
+fn true_phase(step: usize) -> f32 {
+    wrap(step as f32 * 0.05)
+}
+
+#[test]
+fn single_frequency_passes_through() {
+    let mut combiner = PhaseCombiner::<4>::new();
+    for step in 0..100 {
+        let phase = true_phase(step);
+        assert!((combiner.update(&[phase]) - phase).abs() < 1e-5);
+    }
+    assert_eq!(combiner.share(0), 1.);
+}
+
+#[test]
+fn fixed_phase_lags_are_learned() {
+    let lags = [0.1, 0.3, -0.2];
+    let mut combiner = PhaseCombiner::<4>::new();
+    let mut error = 0.;
+    for step in 0..1000 {
+        let phases = lags.map(|lag| wrap(true_phase(step) + lag));
+        error = wrap(combiner.update(&phases) - true_phase(step));
+    }
+    // what's left is a common offset, which zeroing takes out
+    let mean_lag = lags.iter().sum::<f32>() / lags.len() as f32;
+    assert!((error - mean_lag).abs() < 0.01, "{error}");
+    assert!((combiner.offset(1) - combiner.offset(0) - 0.2).abs() < 0.01);
+}
+
+#[test]
+fn interference_on_one_frequency_is_rejected() {
+    let mut combiner = PhaseCombiner::<4>::new();
+    let mut worst = 0f32;
+    for step in 0..500 {
+        let truth = true_phase(step);
+        // an interferer beating against the second drive frequency
+        let interference = 1.2 * (step as f32 * 2.3).sin();
+        let phases = [truth, wrap(truth + interference), truth];
+        let error = wrap(combiner.update(&phases) - truth).abs();
+        if step > 50 {
+            worst = worst.max(error);
+        }
+    }
+    // an equal-weight mean would be off by up to 0.4 rad
+    assert!(worst < 0.05, "{worst}");
+    assert!(combiner.share(1) < 0.02);
+    assert!((combiner.share(0) - combiner.share(2)).abs() < 0.01);
+}
+
+#[test]
+fn reset_forgets_what_was_learned() {
+    let mut combiner = PhaseCombiner::<2>::new();
+    for _ in 0..100 {
+        combiner.update(&[0., 1.]);
+    }
+    assert!(combiner.offset(1) != 0.);
+    combiner.reset();
+    assert_eq!(combiner.offset(1), 0.);
+    assert_eq!(combiner.share(0), 0.);
+}
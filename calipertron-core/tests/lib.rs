@@ -0,0 +1,111 @@
+use calipertron_core::*;
+use std::f32::consts::PI;
+
+#[test]
+fn empty_calibration_leaves_phase_alone() {
+    for phase in [-PI, -1., 0., 2.5] {
+        assert_eq!(correct_phase(phase, &[]), phase);
+    }
+}
+
+#[test]
+fn calibration_hits_table_points() {
+    let table = [0.1, -0.2, 0.3, 0.];
+    for (i, offset) in table.iter().enumerate() {
+        let phase = -PI + 2. * PI * i as f32 / table.len() as f32;
+        assert!((correct_phase(phase, &table) - (phase + offset)).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn calibration_interpolates_and_wraps_round() {
+    let table = [0.1, -0.2, 0.3, 0.5];
+    // halfway between the first two points
+    let phase = -PI + PI / 4.;
+    assert!((correct_phase(phase, &table) - (phase - 0.05)).abs() < 1e-5);
+    // between the last point and the first, a cycle on
+    let phase = -PI + 2. * PI * 3.5 / 4.;
+    assert!((correct_phase(phase, &table) - (phase + 0.3)).abs() < 1e-5);
+    // +PI is the same place as -PI
+    assert!((correct_phase(PI, &table) - (PI + 0.1)).abs() < 1e-5);
+}
+
+#[test]
+fn within_pitch_drops_whole_pitches() {
+    let pitch = 9.4;
+    for (position, expected) in [
+        (0., 0.),
+        (1., 1.),
+        (-1., -1.),
+        (3. * pitch + 1., 1.),
+        (-2. * pitch - 1., -1.),
+        (pitch - 1., -1.),
+    ] {
+        let wrapped = within_pitch(position, pitch);
+        assert!((wrapped - expected).abs() < 1e-4, "{position}: {wrapped}");
+    }
+}
+
+/// Position the firmware computes after the slider has gone from 0 to `path` (mm) since power-up.
+fn raw_position(path: &[f32], pitch: f32) -> f32 {
+    let phase_of = |x: f32| within_pitch(x, pitch) / pitch * 2. * PI;
+    let mut accumulator = PhaseAccumulator::new(0., 0.1);
+    for &x in path {
+        accumulator.update(phase_of(x));
+    }
+    accumulator.unwrapped_phase * pitch / (2. * PI)
+}
+
+#[test]
+fn zero_survives_a_reset() {
+    let pitch = 9.4;
+    // zeroed a few pitches along
+    let path: Vec<f32> = (0..=300).map(|i| i as f32 * 0.1).collect();
+    let zeroed_at = raw_position(&path, pitch);
+    assert!((zeroed_at - 30.).abs() < 1e-3);
+    let offset = within_pitch(zeroed_at, pitch);
+
+    // after a reset in the same spot the count starts within the pitch, and reads zero again
+    let after_reset = raw_position(&[30.], pitch);
+    assert!((after_reset - offset).abs() < 1e-3);
+    // moving on from there reads the distance moved
+    let moved = raw_position(&[30., 30.5, 31., 31.5, 32.], pitch);
+    assert!((moved - offset - 2.).abs() < 1e-3);
+}
+
+#[test]
+fn zero_reads_zero_right_away() {
+    let pitch = 9.4;
+    // zeroed several pitches out from power-up
+    let path: Vec<f32> = (0..=500).map(|i| i as f32 * 0.1).collect();
+    let zeroed_at = raw_position(&path, pitch);
+    assert!(zeroed_at > 5. * pitch);
+
+    // the offset is kept in full while running
+    let offset = zeroed_at;
+    assert!((raw_position(&path, pitch) - offset).abs() < 1e-3);
+    let moved: Vec<f32> = path.iter().copied().chain([50.5, 51.]).collect();
+    assert!((raw_position(&moved, pitch) - offset - 1.).abs() < 1e-3);
+
+    // whereas the part within a pitch would leave whole pitches in the reading
+    let reading = raw_position(&path, pitch) - within_pitch(zeroed_at, pitch);
+    assert!((reading - 5. * pitch).abs() < 1e-3);
+}
+
+#[test]
+fn dual_adc_words_unpack_high_half_first() {
+    // ADC2 converts first and sits in the high half of ADC1_DR
+    let words = [0x0002_0001, 0x0004_0003, 0x0FFF_0000];
+    let samples: Vec<u16> = unpack_dual_adc(&words).collect();
+    assert_eq!(samples, [2, 1, 4, 3, 0x0FFF, 0]);
+}
+
+#[test]
+fn dual_adc_unpacks_ramp_in_time_order() {
+    let ramp: Vec<u16> = (0..64).collect();
+    let words: Vec<u32> = ramp
+        .chunks(2)
+        .map(|pair| (pair[0] as u32) << 16 | pair[1] as u32)
+        .collect();
+    assert_eq!(unpack_dual_adc(&words).collect::<Vec<_>>(), ramp);
+}
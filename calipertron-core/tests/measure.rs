@@ -0,0 +1,188 @@
+use calipertron_core::measure::*;
+use calipertron_core::PhaseAccumulator;
+use std::cell::Cell;
+use std::f32::consts::PI;
+use std::future::Future;
+use std::pin::pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+const N: usize = 128;
+// same shape as the build.rs table: two cycles per capture
+const CYCLES: f32 = 2.;
+
+fn table() -> Vec<(f32, f32)> {
+    (0..N)
+        .map(|i| {
+            let angle = 2. * PI * CYCLES * i as f32 / N as f32;
+            (angle.sin(), angle.cos())
+        })
+        .collect()
+}
+
+fn signal(amplitude: f32, phase: f32) -> Vec<u16> {
+    (0..N)
+        .map(|i| {
+            let angle = 2. * PI * CYCLES * i as f32 / N as f32 + phase;
+            (2048. + amplitude * angle.sin()).round() as u16
+        })
+        .collect()
+}
+
+#[test]
+fn amplitude_ignores_offset_and_phase() {
+    for phase in [0., 1., -2.5] {
+        let measured = amplitude(&signal(300., phase), &table());
+        assert!((measured - 300.).abs() < 1., "{measured} at phase {phase}");
+    }
+    assert!(amplitude(&[2048; N], &table()) < 0.1);
+}
+
+#[test]
+fn phase_follows_the_signal() {
+    let table = table();
+    let a = phase(&signal(300., 0.), &table);
+    let b = phase(&signal(300., 1.), &table);
+    // correlating sine against sine comes out as pi/2 - signal phase
+    assert!((a - b - 1.).abs() < 0.01);
+}
+
+////////////////////////
+// Simulated hardware, as in frontend/src/bin/simulate.rs
+
+const PITCH_MM: f32 = 9.4;
+
+/// Run a future that only ever waits on itself, like the simulated hardware below.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+/// Returns `Pending` once, like a DMA transfer that hasn't finished yet.
+async fn yield_now() {
+    let mut yielded = false;
+    std::future::poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+struct SimulatedEmitter {
+    running: Rc<Cell<bool>>,
+    starts: usize,
+}
+
+impl Emitter for SimulatedEmitter {
+    fn start(&mut self) {
+        self.running.set(true);
+        self.starts += 1;
+    }
+
+    async fn stop(&mut self) {
+        yield_now().await;
+        self.running.set(false);
+    }
+}
+
+/// Pickup over a scale at `position_mm`, seeing the emitter waveform shifted by the position within the pitch.
+struct SimulatedPickup {
+    running: Rc<Cell<bool>>,
+    position_mm: Rc<Cell<f32>>,
+    /// Whether the emitter was already running when the capture was armed.
+    armed_late: bool,
+    rng: u32,
+}
+
+impl SimulatedPickup {
+    /// Uniform noise in [-1, 1) from a xorshift generator, so runs are repeatable.
+    fn next_noise(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as f32 / u32::MAX as f32 * 2. - 1.
+    }
+}
+
+impl Sampler for SimulatedPickup {
+    async fn capture(&mut self, buf: &mut [u16]) {
+        self.armed_late |= self.running.get();
+        // armed now, but nothing to see until the emitter starts
+        yield_now().await;
+        assert!(
+            self.running.get(),
+            "emitter should be running during a capture"
+        );
+
+        let offset = 2. * PI * self.position_mm.get() / PITCH_MM;
+        for (i, sample) in buf.iter_mut().enumerate() {
+            let angle = 2. * PI * CYCLES * i as f32 / N as f32;
+            let value = 2048. + 500. * (angle - offset).cos() + 50. * self.next_noise();
+            *sample = value.clamp(0., 4095.) as u16;
+        }
+    }
+}
+
+fn simulated_hardware() -> (SimulatedEmitter, SimulatedPickup, Rc<Cell<f32>>) {
+    let running = Rc::new(Cell::new(false));
+    let position_mm = Rc::new(Cell::new(0.));
+    let emitter = SimulatedEmitter {
+        running: running.clone(),
+        starts: 0,
+    };
+    let pickup = SimulatedPickup {
+        running,
+        position_mm: position_mm.clone(),
+        armed_late: false,
+        rng: 0x1234_5678,
+    };
+    (emitter, pickup, position_mm)
+}
+
+#[test]
+fn capture_arms_the_sampler_before_starting_the_emitter() {
+    let (mut emitter, mut pickup, _) = simulated_hardware();
+    let mut buf = [0u16; N];
+    for _ in 0..3 {
+        block_on(capture(&mut emitter, &mut pickup, &mut buf));
+        assert!(
+            !emitter.running.get(),
+            "emitter should be stopped after a capture"
+        );
+    }
+    assert!(!pickup.armed_late);
+    assert_eq!(emitter.starts, 3);
+}
+
+#[test]
+fn captured_phase_follows_a_moving_scale() {
+    let (mut emitter, mut pickup, position_mm) = simulated_hardware();
+    let table = table();
+    let mut buf = [0u16; N];
+    let mut accumulator = PhaseAccumulator::new(0., 0.1);
+
+    // a few pitches, forwards then back
+    let path = (0..400).chain((0..400).rev()).map(|n| n as f32 * 0.05);
+    for true_mm in path {
+        position_mm.set(true_mm);
+        block_on(capture(&mut emitter, &mut pickup, &mut buf));
+        accumulator.update(phase(&buf, &table));
+
+        let measured_mm = accumulator.unwrapped_phase * PITCH_MM / (2. * PI);
+        // the accumulator's 0.1 rad hysteresis is 0.15mm
+        assert!(
+            (measured_mm - true_mm).abs() < 0.2,
+            "measured {measured_mm}mm at {true_mm}mm"
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use calipertron_core::power::*;
+
+fn config() -> PowerConfig {
+    PowerConfig {
+        active_interval_ms: 10,
+        stable_interval_ms: 200,
+        stable_threshold_mm: 0.02,
+        stable_after_ms: 1_000,
+        stop_after_ms: 10_000,
+        probe_interval_ms: 500,
+    }
+}
+
+#[test]
+fn slows_down_then_stops_when_still() {
+    let mut policy = PowerPolicy::new(config(), 0, 5.0);
+    assert_eq!(
+        policy.update(500, 5.01),
+        PowerAction::Measure { after_ms: 10 }
+    );
+    assert_eq!(
+        policy.update(1_000, 4.99),
+        PowerAction::Measure { after_ms: 200 }
+    );
+    assert_eq!(policy.mode(), PowerMode::Stable);
+    assert_eq!(
+        policy.update(10_000, 5.0),
+        PowerAction::Stop {
+            probe_after_ms: 500
+        }
+    );
+
+    // probes that find nothing keep it stopped, even though the clock didn't move
+    assert_eq!(
+        policy.update(10_000, 5.01),
+        PowerAction::Stop {
+            probe_after_ms: 500
+        }
+    );
+}
+
+#[test]
+fn motion_goes_back_to_full_rate() {
+    let mut policy = PowerPolicy::new(config(), 0, 0.0);
+    policy.update(20_000, 0.0);
+    assert_eq!(policy.mode(), PowerMode::Stopped);
+
+    assert_eq!(
+        policy.update(20_000, 0.5),
+        PowerAction::Measure { after_ms: 10 }
+    );
+    // settles at the new position, timed from when it got there
+    assert_eq!(
+        policy.update(20_999, 0.5),
+        PowerAction::Measure { after_ms: 10 }
+    );
+    assert_eq!(
+        policy.update(21_000, 0.5),
+        PowerAction::Measure { after_ms: 200 }
+    );
+}
+
+#[test]
+fn slow_drift_is_noticed() {
+    let mut policy = PowerPolicy::new(config(), 0, 0.0);
+    // each step is under the threshold, but they add up
+    for (i, t) in (0..20_000).step_by(100).enumerate() {
+        policy.update(t, i as f32 * 0.001);
+    }
+    assert_ne!(policy.mode(), PowerMode::Stopped);
+}
+
+#[test]
+fn button_wakes() {
+    let mut policy = PowerPolicy::new(config(), 0, 0.0);
+    policy.update(20_000, 0.0);
+    policy.wake(20_000);
+    assert_eq!(policy.action(), PowerAction::Measure { after_ms: 10 });
+    assert_eq!(
+        policy.update(20_010, 0.0),
+        PowerAction::Measure { after_ms: 10 }
+    );
+}
+
+#[test]
+fn current_budget() {
+    let model = CurrentModel {
+        capture_mA: 40.,
+        idle_mA: 10.,
+        stop_mA: 0.,
+    };
+    let budget = CurrentBudget {
+        capture_ms: 1,
+        idle_ms: 3,
+        stop_ms: 6,
+    };
+    assert_eq!(budget.total_ms(), 10);
+    assert!((budget.average_mA(&model) - 7.).abs() < 1e-6);
+    assert!((budget.battery_life_hours(&model, 700.) - 100.).abs() < 1e-3);
+
+    assert_eq!(CurrentBudget::default().average_mA(&model), 0.);
+}
@@ -0,0 +1,88 @@
+use calipertron_core::quadrature::*;
+
+fn ab(outputs: Outputs) -> (bool, bool) {
+    (outputs.a, outputs.b)
+}
+
+fn run(q: &mut Quadrature) -> Vec<Outputs> {
+    std::iter::from_fn(|| q.step()).collect()
+}
+
+#[test]
+fn forward_and_back() {
+    let mut q = Quadrature::new(0.01, 0);
+    assert_eq!(ab(q.outputs()), (false, false));
+
+    q.set_position(0.04);
+    let forward: Vec<_> = run(&mut q).into_iter().map(ab).collect();
+    assert_eq!(
+        forward,
+        [(true, false), (true, true), (false, true), (false, false)]
+    );
+    assert_eq!(q.count(), 4);
+    assert!(q.is_settled());
+
+    q.set_position(0.02);
+    let back: Vec<_> = run(&mut q).into_iter().map(ab).collect();
+    assert_eq!(back, [(false, true), (true, true)]);
+    assert_eq!(q.count(), 2);
+}
+
+#[test]
+fn negative_positions() {
+    let mut q = Quadrature::new(0.01, 0);
+    q.set_position(-0.01);
+    assert_eq!(
+        run(&mut q).into_iter().map(ab).collect::<Vec<_>>(),
+        [(false, true)]
+    );
+    assert_eq!(q.count(), -1);
+}
+
+#[test]
+fn one_edge_per_step() {
+    let mut q = Quadrature::new(0.005, 0);
+    q.set_position(1.0);
+    let steps = run(&mut q);
+    assert_eq!(steps.len(), 200);
+
+    let mut previous = Outputs {
+        a: false,
+        b: false,
+        index: false,
+    };
+    for outputs in steps {
+        assert!((outputs.a != previous.a) ^ (outputs.b != previous.b));
+        previous = outputs;
+    }
+}
+
+#[test]
+fn hysteresis() {
+    let mut q = Quadrature::new(0.01, 0);
+    q.set_position(0.0074);
+    assert!(q.is_settled());
+    q.set_position(0.0076);
+    assert!(!q.is_settled());
+    run(&mut q);
+
+    // hovering around the boundary back to 0 doesn't move it
+    q.set_position(0.0026);
+    assert!(q.is_settled());
+    q.set_position(0.0024);
+    assert_eq!(run(&mut q).len(), 1);
+}
+
+#[test]
+fn index_pulses() {
+    let mut q = Quadrature::new(0.01, 8);
+    assert!(q.outputs().index);
+    q.set_position(0.17);
+    let indexed: Vec<_> = run(&mut q)
+        .iter()
+        .enumerate()
+        .filter(|(_, outputs)| outputs.index)
+        .map(|(i, outputs)| (i + 1, ab(*outputs)))
+        .collect();
+    assert_eq!(indexed, [(8, (false, false)), (16, (false, false))]);
+}
@@ -0,0 +1,68 @@
+use calipertron_core::readout::*;
+
+#[test]
+fn absolute_follows_the_position() {
+    let mut readout = Readout::new(1.);
+    let reading = readout.update(2.5);
+    assert_eq!(reading.mode, Mode::Absolute);
+    assert_eq!(reading.value_mm, 2.5);
+    assert_eq!(reading.min_max_mm, None);
+}
+
+#[test]
+fn hold_freezes_until_restarted() {
+    let mut readout = Readout::new(0.);
+    readout.update(3.);
+    readout.set_mode(Mode::Hold);
+    assert_eq!(readout.update(7.).value_mm, 3.);
+    assert_eq!(readout.update(-1.).value_mm, 3.);
+
+    readout.set_mode(Mode::Hold);
+    assert_eq!(readout.update(4.).value_mm, -1.);
+
+    readout.set_mode(Mode::Absolute);
+    assert_eq!(readout.update(4.).value_mm, 4.);
+}
+
+#[test]
+fn min_max_tracks_extremes_since_start() {
+    let mut readout = Readout::new(0.);
+    readout.update(-5.);
+    readout.update(2.);
+    readout.set_mode(Mode::MinMax);
+    readout.update(3.);
+    readout.update(1.5);
+    let reading = readout.update(2.5);
+    assert_eq!(reading.value_mm, 2.5);
+    assert_eq!(reading.min_max_mm, Some((1.5, 3.)));
+
+    // restarting forgets the old extremes
+    readout.set_mode(Mode::MinMax);
+    assert_eq!(readout.update(2.5).min_max_mm, Some((2.5, 2.5)));
+}
+
+#[test]
+fn relative_measures_from_where_it_started() {
+    let mut readout = Readout::new(0.);
+    readout.update(10.);
+    readout.set_mode(Mode::Relative);
+    assert_eq!(readout.update(12.5).value_mm, 2.5);
+    assert_eq!(readout.update(9.).value_mm, -1.);
+
+    readout.set_mode(Mode::Absolute);
+    assert_eq!(readout.update(9.).value_mm, 9.);
+}
+
+#[test]
+fn modes_cycle() {
+    let mut mode = Mode::Absolute;
+    let mut seen = vec![];
+    for _ in 0..4 {
+        mode = mode.next();
+        seen.push(mode);
+    }
+    assert_eq!(
+        seen,
+        [Mode::Hold, Mode::MinMax, Mode::Relative, Mode::Absolute]
+    );
+}
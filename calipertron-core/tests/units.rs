@@ -0,0 +1,63 @@
+use calipertron_core::units::*;
+
+fn text(position_mm: f32, unit: Unit) -> String {
+    format(position_mm, unit).to_string()
+}
+
+#[test]
+fn millimeters() {
+    assert_eq!(text(12.345, Unit::Millimeter), "12.35");
+    assert_eq!(text(12.344, Unit::Millimeter), "12.34");
+    assert_eq!(text(-3.1, Unit::Millimeter), "-3.10");
+    assert_eq!(text(-0.05, Unit::Millimeter), "-0.05");
+    assert_eq!(text(150., Unit::Millimeter), "150.00");
+}
+
+#[test]
+fn decimal_inches_round_to_half_thousandths() {
+    assert_eq!(text(25.4, Unit::Inch), "1.0000");
+    // 0.48622 in
+    assert_eq!(text(12.35, Unit::Inch), "0.4860");
+    // 0.48779 in
+    assert_eq!(text(12.39, Unit::Inch), "0.4880");
+    // 0.00025 in rounds away from zero
+    assert_eq!(text(0.00635, Unit::Inch), "0.0005");
+    assert_eq!(text(-0.00635, Unit::Inch), "-0.0005");
+}
+
+#[test]
+fn fractional_inches() {
+    assert_eq!(text(0., Unit::FractionalInch), "0");
+    assert_eq!(text(25.4, Unit::FractionalInch), "1");
+    assert_eq!(text(25.4 * 1.1875, Unit::FractionalInch), "1 3/16");
+    assert_eq!(text(25.4 * 0.5, Unit::FractionalInch), "1/2");
+    assert_eq!(text(-25.4 * 5. / 64., Unit::FractionalInch), "-5/64");
+    assert_eq!(text(-25.4 * 2.25, Unit::FractionalInch), "-2 1/4");
+    // 0.99 in is nearer 63/64 than 1
+    assert_eq!(text(25.4 * 0.99, Unit::FractionalInch), "63/64");
+    // 0.995 in is nearer 1
+    assert_eq!(text(25.4 * 0.995, Unit::FractionalInch), "1");
+}
+
+#[test]
+fn no_negative_zero() {
+    assert_eq!(text(-0.004, Unit::Millimeter), "0.00");
+    assert_eq!(text(-0.006, Unit::Inch), "0.0000");
+    assert_eq!(text(-0.1, Unit::FractionalInch), "0");
+    assert_eq!(format_decimals(-0.0004, 3).to_string(), "0.000");
+}
+
+#[test]
+fn fixed_decimals() {
+    assert_eq!(format_decimals(7.6, 0).to_string(), "8");
+    assert_eq!(format_decimals(-7.5, 0).to_string(), "-8");
+    assert_eq!(format_decimals(1.23456, 5).to_string(), "1.23456");
+    assert_eq!(format_decimals(0.1, 3).to_string(), "0.100");
+}
+
+#[test]
+fn rounded_values() {
+    assert_eq!(Unit::Millimeter.steps(-1.006), -101);
+    assert_eq!(Unit::FractionalInch.steps(25.4), 64);
+    assert!((Unit::Inch.round(12.35) - 0.486).abs() < 1e-6);
+}
@@ -2,20 +2,16 @@ use std::f64::consts::PI;
 use std::fs::File;
 use std::io::Write;
 
-fn generate_pdm_bsrr(n_samples: usize) -> String {
+// BSRR-style words with wave `w` on bit `w` (set) / `w + 16` (reset);
+// `calipertron::board::PDM_SIGNAL` moves them onto whichever pins drive each wave on the selected board.
+fn generate_pdm_waves(n_samples: usize) -> String {
     let mut output = String::new();
-    output.push_str("pub const PDM_SIGNAL: [u32; ");
+    output.push_str("pub const PDM_WAVES: [u32; ");
     output.push_str(&n_samples.to_string());
     output.push_str("] = [\n");
 
     let n_waves = 8;
 
-    // in PCB schematic v1.1 the pins PA0--PA7 are wired up for signal idx 0,4, 1,5, 2,6, 3,7
-    let mut wave_idx_to_pin = [0; 8];
-    for (pin_idx, wave_idx) in [0, 4, 1, 5, 2, 6, 3, 7].into_iter().enumerate() {
-        wave_idx_to_pin[wave_idx] = pin_idx;
-    }
-
     let mut errors = vec![0.0; n_waves];
     for sample in 0..n_samples {
         let mut bsrr = 0u32;
@@ -30,10 +26,10 @@ fn generate_pdm_bsrr(n_samples: usize) -> String {
             let normalized_signal = (1.0 - scale) / 2.0 + scale * normalized_signal;
 
             if normalized_signal > errors[wave] {
-                bsrr |= 1 << wave_idx_to_pin[wave]; // set bit
+                bsrr |= 1 << wave; // set bit
                 errors[wave] += 1.0 - normalized_signal;
             } else {
-                bsrr |= 1 << (wave_idx_to_pin[wave] + 16); // reset bit
+                bsrr |= 1 << (wave + 16); // reset bit
                 errors[wave] -= normalized_signal;
             }
         }
@@ -82,19 +78,19 @@ fn main() {
     let num_samples = 128;
 
     let signal_frequency = pdm_frequency as f64 / pdm_length as f64;
-    let adc_frequency = 12_000_000.;
-    // let adc_sample_cycles = 239.5;
-    // let adc_sample_cycles = 71.5;
-    let adc_sample_cycles = 41.5;
-    let adc_sample_overhead_cycles = 12.5; // see reference manual section 11.6
-    let sampling_frequency = adc_frequency / (adc_sample_cycles + adc_sample_overhead_cycles);
+
+    // ADC is triggered once every `adc_decimation` PDM steps (`schema::AdcTrigger::MEASURE`), so the sampling frequency is an exact
+    // fraction of the PDM frequency and the table below lines up with the drive signal on every capture.
+    // Conversions at 41.5 cycles (~4.5us) fit comfortably in the 9us between triggers.
+    let adc_decimation = schema::MEASURE_ADC_DECIMATION;
+    let sampling_frequency = pdm_frequency as f64 / adc_decimation as f64;
 
     f.write_all(
         generate_sine_cosine_table(signal_frequency, sampling_frequency, num_samples).as_bytes(),
     )
     .unwrap();
 
-    f.write_all(generate_pdm_bsrr(pdm_length).as_bytes())
+    f.write_all(generate_pdm_waves(pdm_length).as_bytes())
         .unwrap();
 
     // Tell Cargo to rerun this script if the source file changes
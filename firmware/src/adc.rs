@@ -0,0 +1,168 @@
+use embassy_stm32::adc::SampleTime;
+use embassy_stm32::pac::adc::vals::Dualmod;
+use embassy_stm32::pac::timer::vals::{Mms, Sms, Ts};
+use embassy_stm32::peripherals::{TIM2, TIM3};
+use embassy_stm32::timer::low_level::Timer;
+use schema::{AdcSamplingPeriod, AdcTrigger};
+
+pub fn sample_time(period: &AdcSamplingPeriod) -> SampleTime {
+    match period {
+        AdcSamplingPeriod::CYCLES1_5 => SampleTime::CYCLES1_5,
+        AdcSamplingPeriod::CYCLES7_5 => SampleTime::CYCLES7_5,
+        AdcSamplingPeriod::CYCLES13_5 => SampleTime::CYCLES13_5,
+        AdcSamplingPeriod::CYCLES28_5 => SampleTime::CYCLES28_5,
+        AdcSamplingPeriod::CYCLES41_5 => SampleTime::CYCLES41_5,
+        AdcSamplingPeriod::CYCLES55_5 => SampleTime::CYCLES55_5,
+        AdcSamplingPeriod::CYCLES71_5 => SampleTime::CYCLES71_5,
+        AdcSamplingPeriod::CYCLES239_5 => SampleTime::CYCLES239_5,
+        AdcSamplingPeriod::CYCLES1_5_DUAL_INTERLEAVED => SampleTime::CYCLES1_5,
+    }
+}
+
+/// Switch between ADC1 alone and ADC1 + ADC2 in fast interleaved mode, both converting `channel`.
+///
+/// In interleaved mode ADC2 starts each conversion as soon as it's triggered and ADC1 7 ADC clocks later,
+/// and the ADC1 data register holds both results (ADC2's in the high half), so DMA must read it as 32 bits (see `calipertron_core::unpack_dual_adc`).
+/// ADC2 must already be powered up (e.g. with `embassy_stm32::adc::Adc::new`).
+pub fn set_dual_interleaved(interleaved: bool, channel: u8) {
+    let (adc1, adc2) = (embassy_stm32::pac::ADC1, embassy_stm32::pac::ADC2);
+
+    if interleaved {
+        adc2.sqr1().modify(|w| w.set_l(0)); // one conversion.
+        adc2.sqr3().modify(|w| w.set_sq(0, channel));
+        adc2.smpr2()
+            .modify(|w| w.set_smp(channel as usize, SampleTime::CYCLES1_5));
+        adc2.cr2().modify(|w| {
+            w.set_cont(true);
+            // slave conversions are started by the master; software trigger keeps anything else from starting them
+            w.set_exttrig(true);
+            w.set_extsel(EXTSEL_SWSTART);
+        });
+    }
+
+    adc1.cr1().modify(|w| {
+        w.set_dualmod(if interleaved {
+            Dualmod::FASTINTERLEAVED
+        } else {
+            Dualmod::INDEPENDENT
+        })
+    });
+}
+
+const VREFINT_CHANNEL: u8 = 17;
+// injected group JEXTSEL value for a software start (JSWSTART)
+const JEXTSEL_JSWSTART: u8 = 0b111;
+
+/// Setup shared by every capture path, to run after `Adc::new` and before the regular sequence is started.
+///
+/// Reruns the ADC self-calibration and sets up VREFINT as the (only) injected channel,
+/// so `read_vrefint` can measure it without touching the regular sequence used for samples.
+pub async fn setup() {
+    let adc = embassy_stm32::pac::ADC1;
+
+    // calibration needs the ADC powered (ADON) but idle
+    adc.cr2().modify(|w| w.set_rstcal(true));
+    while adc.cr2().read().rstcal() {}
+    adc.cr2().modify(|w| w.set_cal(true));
+    while adc.cr2().read().cal() {}
+
+    adc.cr2().modify(|w| {
+        w.set_tsvrefe(true);
+        w.set_jexttrig(true);
+        w.set_jextsel(JEXTSEL_JSWSTART);
+    });
+    // with a single injected conversion, only JSQ4 is used
+    adc.jsqr().write(|w| {
+        w.set_jl(0);
+        w.set_jsq(3, VREFINT_CHANNEL);
+    });
+    // datasheet asks for at least 17.1us sampling time on VREFINT
+    adc.smpr1()
+        .modify(|w| w.set_smp(VREFINT_CHANNEL as usize - 10, SampleTime::CYCLES239_5));
+
+    // give vref some time to warm up
+    embassy_time::Timer::after_millis(100).await;
+}
+
+/// Convert VREFINT once, returning the raw reading (see `schema::to_millivolts`).
+///
+/// An injected conversion pre-empts any regular conversion in progress, so calling this mid-capture pauses sampling for ~20us.
+pub fn read_vrefint() -> u16 {
+    let adc = embassy_stm32::pac::ADC1;
+
+    adc.sr().modify(|w| w.set_jeoc(false));
+    adc.cr2().modify(|w| w.set_jswstart(true));
+    while !adc.sr().read().jeoc() {}
+
+    adc.jdr(0).read().jdata()
+}
+
+// ADC1 regular group EXTSEL values, see reference manual section 11.12.3.
+// TIM2 TRGO can't start ADC1 regular conversions directly, so it's routed through TIM3.
+const EXTSEL_TIM3_TRGO: u8 = 0b100;
+const EXTSEL_SWSTART: u8 = 0b111;
+
+/// Configure what starts ADC1 conversions.
+///
+/// For `AdcTrigger::PdmTimer`, TIM3 is clocked by TIM2 (the PDM timer) update events and its own update starts a conversion,
+/// so sample `n` is always taken on the same step of the PDM table. Call `restart_trigger` whenever the PDM timer is reset.
+pub fn set_trigger(
+    pdm_timer: &Timer<'_, TIM2>,
+    trigger_timer: &Timer<'_, TIM3>,
+    trigger: &AdcTrigger,
+) {
+    let adc = embassy_stm32::pac::ADC1;
+
+    match trigger {
+        AdcTrigger::FreeRunning => {
+            trigger_timer.stop();
+            adc.cr2().modify(|w| {
+                w.set_cont(true);
+                w.set_exttrig(false);
+                w.set_extsel(EXTSEL_SWSTART);
+            });
+        }
+
+        AdcTrigger::PdmTimer { decimation } => {
+            pdm_timer
+                .regs_gp16()
+                .cr2()
+                .modify(|w| w.set_mms(Mms::UPDATE));
+
+            let regs = trigger_timer.regs_gp16();
+            // TIM2 is internal trigger 1 for TIM3
+            regs.smcr().modify(|w| {
+                w.set_ts(Ts::ITR1);
+                w.set_sms(Sms::EXT_CLOCK_MODE);
+            });
+            regs.psc().write_value(0);
+            // at least `MIN_DECIMATION`; a reload value of 0 would stop TIM3 updating
+            regs.arr().write(|w| w.set_arr(decimation - 1));
+            regs.cr2().modify(|w| w.set_mms(Mms::UPDATE));
+            restart_trigger(trigger_timer);
+            trigger_timer.start();
+
+            adc.cr2().modify(|w| {
+                w.set_cont(false);
+                w.set_exttrig(true);
+                w.set_extsel(EXTSEL_TIM3_TRGO);
+            });
+        }
+    }
+}
+
+/// Line the trigger timer back up with the start of the PDM table.
+pub fn restart_trigger(trigger_timer: &Timer<'_, TIM3>) {
+    trigger_timer.reset();
+
+    // throw away any conversion left over from the last capture so it isn't DMA'd in as the first sample
+    let _ = embassy_stm32::pac::ADC1.dr().read();
+}
+
+/// Start ADC1 converting into a DMA transfer that has just been set up.
+pub fn start_conversions(trigger: &AdcTrigger) {
+    // Writing ADON while the ADC is already on starts a conversion straight away, which would put an unsynchronised sample at the front of a triggered capture.
+    if *trigger == AdcTrigger::FreeRunning {
+        embassy_stm32::pac::ADC1.cr2().modify(|w| w.set_adon(true));
+    }
+}
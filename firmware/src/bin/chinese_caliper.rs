@@ -0,0 +1,179 @@
+#![no_std]
+#![no_main]
+
+// Measures like `local`, and sends the position in the clock/data format of cheap digital calipers (see `calipertron_core::chinese_caliper`),
+// so it can stand in for a caliper's electronics in hobby DROs.
+//
+// Wiring (free on every board): PB6 clock, PB7 data, plus ground. Outputs are 3.3V push-pull, not inverted;
+// readers made for a caliper's 1.5V signals usually level shift with a transistor, which inverts them.
+
+use calipertron::board;
+use calipertron::button::Button;
+use calipertron::config_store::ConfigStore;
+use calipertron::measure::{AdcSampler, PdmEmitter};
+use calipertron::pga::Pga;
+use calipertron::watchdog::{self, Watchdog};
+use calipertron_core::button::Gesture;
+use calipertron_core::gain::AutoGain;
+use calipertron_core::*;
+use schema::{AdcSamplingPeriod, ButtonAction};
+
+use core::cell::Cell;
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_stm32::flash::Flash;
+use embassy_stm32::gpio::{Level, Output, Speed};
+use embassy_stm32::time::Hertz;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::signal::Signal;
+
+use embassy_time::{Duration, Timer};
+
+// panics and hard faults are handled by `calipertron::crash`
+use defmt_rtt as _;
+
+include!(concat!(env!("OUT_DIR"), "/constants.rs"));
+const NUM_SAMPLES: usize = SINE_COSINE_TABLE.len();
+
+// Half of each clock cycle, in CPU cycles: 6us at 72 MHz, close to a real caliper's ~80 kHz clock.
+// Too short for the time driver, so it's a busy wait; a whole frame takes ~0.6ms.
+const HALF_BIT_CYCLES: u32 = 72 * 6;
+
+// Calipers send a frame every few hundred ms, or every ~20ms in "fast mode"; readers sync on the gap between frames.
+const FRAME_INTERVAL: Duration = Duration::from_millis(20);
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_stm32::init(board::config());
+    let pins = calipertron::board_pins!(p);
+
+    info!("Hello World!");
+    info!("Reset cause: {:?}", watchdog::take_reset_cause());
+    // logged if there was one; there is no host to send it to
+    let _ = calipertron::crash::take_crash_report();
+
+    ////////////////////////
+    // Signal emission and ADC setup
+
+    let _drive = board::drive_outputs(pins.drive);
+
+    // TIM3 is clocked by TIM2 to trigger ADC conversions in step with the PDM table (`AdcTrigger::MEASURE`).
+    let hardware = calipertron::measure::setup(
+        p.TIM2,
+        p.TIM3,
+        p.ADC1,
+        pins.pickup,
+        Hertz(PDM_FREQUENCY),
+        &AdcSamplingPeriod::CYCLES41_5,
+    )
+    .await;
+
+    let mut emitter = PdmEmitter::new(
+        &hardware.pdm_timer,
+        &hardware.trigger_timer,
+        p.DMA1_CH2,
+        &board::PDM_SIGNAL,
+    );
+    let mut sampler = AdcSampler::new(p.DMA1_CH1);
+
+    let mut button = pins.button.map(Button::new);
+
+    let mut caliper_clock = Output::new(p.PB6, Level::High, Speed::Low);
+    let mut caliper_data = Output::new(p.PB7, Level::Low, Speed::Low);
+
+    let mut phase_accumulator = PhaseAccumulator::new(0.0, 0.1);
+
+    // Drive frequency and ADC sample time are baked into the build.rs tables, so only the scale settings are used here.
+    let mut config_store = ConfigStore::new(Flash::new_blocking(p.FLASH));
+    let mut config = config_store.load().unwrap_or_default();
+    info!("Config: {:?}", config);
+
+    let distance_per_phase_cycle = config.scale_pitch_mm;
+
+    // Boards without the front-end amplifier just skip gain control.
+    let mut auto_gain = AutoGain::new(config.gain);
+    let mut pga = Pga::setup(pins.pga, auto_gain.gain);
+
+    // A capture takes a few ms and frames go out every 20ms, so this only trips if the DMA stops.
+    const TASK_MAIN: usize = 0;
+    const TASK_OUTPUT: usize = 1;
+    let watchdog = Watchdog::<2>::new(Duration::from_secs(2));
+
+    // Absolute and relative (zero offset applied) positions in mm.
+    let position_mm = Cell::new((0f32, 0f32));
+
+    // Polled on its own so presses are debounced and timed whatever the measuring loop is doing.
+    let gesture: Signal<NoopRawMutex, Gesture> = Signal::new();
+    let fut_button = async {
+        let Some(button) = &mut button else {
+            return core::future::pending().await;
+        };
+        loop {
+            gesture.signal(button.gesture().await);
+        }
+    };
+
+    let fut_main = async {
+        let mut adc_buf = [0u16; NUM_SAMPLES];
+        loop {
+            watchdog.feed(TASK_MAIN);
+            measure::capture(&mut emitter, &mut sampler, &mut adc_buf).await;
+            let phase = correct_phase(
+                measure::phase(&adc_buf, &SINE_COSINE_TABLE),
+                &config.calibration,
+            );
+
+            // phase doesn't depend on amplitude, so the gain can change between captures
+            if let Some(pga) = &mut pga {
+                if let Some(gain) = auto_gain.update(&adc_buf) {
+                    info!("Gain: {}", gain);
+                    if let Err(e) = pga.set_gain(gain) {
+                        error!("Failed to set gain: {:?}", e);
+                    }
+                }
+            }
+
+            phase_accumulator.update(phase);
+            let raw_position = phase_accumulator.unwrapped_phase
+                * (distance_per_phase_cycle / (2.0 * core::f32::consts::PI));
+            position_mm.set((raw_position, raw_position - config.zero_offset_mm));
+
+            ///////////////////////
+            // handle button press
+
+            if let Some(g) = gesture.try_take() {
+                let action = config.buttons.action(g);
+                info!("Button: {:?} press, {:?}", Debug2Format(&g), action);
+                // the reader does its own holding, display modes and units, so zeroing is all there is to do here
+                if action == ButtonAction::Zero {
+                    config.zero_offset_mm = raw_position;
+                    if let Err(e) = config_store.save(&config) {
+                        error!("Failed to save zero offset: {:?}", e);
+                    }
+                }
+            }
+        }
+    };
+
+    let fut_output = async {
+        loop {
+            watchdog.feed(TASK_OUTPUT);
+            let (absolute_mm, relative_mm) = position_mm.get();
+            let frame = chinese_caliper::frame(absolute_mm, relative_mm);
+
+            // data changes while the clock is high and is read on the falling edge
+            for bit in chinese_caliper::bits(frame) {
+                caliper_data.set_level(bit.into());
+                cortex_m::asm::delay(HALF_BIT_CYCLES);
+                caliper_clock.set_low();
+                cortex_m::asm::delay(HALF_BIT_CYCLES);
+                caliper_clock.set_high();
+            }
+            caliper_data.set_low();
+
+            Timer::after(FRAME_INTERVAL).await;
+        }
+    };
+
+    embassy_futures::join::join4(fut_main, fut_button, fut_output, watchdog.run(p.IWDG)).await;
+}
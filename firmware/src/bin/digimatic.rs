@@ -0,0 +1,202 @@
+#![no_std]
+#![no_main]
+
+// Measures like `local`, and sends the position in Digimatic format whenever a data collector asks for it.
+//
+// Wiring (free on every board, and 5V tolerant): PB6 CK, PB7 DATA, PB8 REQ, plus ground.
+// CK and DATA are open drain, pulled up by the collector like an instrument's outputs.
+
+use calipertron::board;
+use calipertron::button::Button;
+use calipertron::config_store::ConfigStore;
+use calipertron::measure::{AdcSampler, PdmEmitter};
+use calipertron::pga::Pga;
+use calipertron::watchdog::{self, Watchdog};
+use calipertron_core::button::Gesture;
+use calipertron_core::gain::AutoGain;
+use calipertron_core::*;
+use schema::{AdcSamplingPeriod, ButtonAction, Units};
+
+use core::cell::Cell;
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_stm32::flash::Flash;
+use embassy_stm32::gpio::{Input, Level, OutputOpenDrain, Pull, Speed};
+use embassy_stm32::time::Hertz;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::signal::Signal;
+
+use embassy_time::{Duration, Timer};
+
+// panics and hard faults are handled by `calipertron::crash`
+use defmt_rtt as _;
+
+include!(concat!(env!("OUT_DIR"), "/constants.rs"));
+const NUM_SAMPLES: usize = SINE_COSINE_TABLE.len();
+
+// Half of each CK cycle. Instruments clock at a few kHz and collectors follow whatever they're given.
+const DIGIMATIC_HALF_BIT: Duration = Duration::from_micros(100);
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_stm32::init(board::config());
+    let pins = calipertron::board_pins!(p);
+
+    info!("Hello World!");
+    info!("Reset cause: {:?}", watchdog::take_reset_cause());
+    // logged if there was one; there is no host to send it to
+    let _ = calipertron::crash::take_crash_report();
+
+    ////////////////////////
+    // Signal emission and ADC setup
+
+    let _drive = board::drive_outputs(pins.drive);
+
+    // TIM3 is clocked by TIM2 to trigger ADC conversions in step with the PDM table (`AdcTrigger::MEASURE`).
+    let hardware = calipertron::measure::setup(
+        p.TIM2,
+        p.TIM3,
+        p.ADC1,
+        pins.pickup,
+        Hertz(PDM_FREQUENCY),
+        &AdcSamplingPeriod::CYCLES41_5,
+    )
+    .await;
+
+    let mut emitter = PdmEmitter::new(
+        &hardware.pdm_timer,
+        &hardware.trigger_timer,
+        p.DMA1_CH2,
+        &board::PDM_SIGNAL,
+    );
+    let mut sampler = AdcSampler::new(p.DMA1_CH1);
+
+    let mut button = pins.button.map(Button::new);
+
+    let digimatic_req = Input::new(p.PB8, Pull::Up);
+    let mut digimatic_clock = OutputOpenDrain::new(p.PB6, Level::High, Speed::Low);
+    let mut digimatic_data = OutputOpenDrain::new(p.PB7, Level::High, Speed::Low);
+
+    let mut phase_accumulator = PhaseAccumulator::new(0.0, 0.1);
+
+    // Drive frequency and ADC sample time are baked into the build.rs tables, so only the scale settings are used here.
+    let mut config_store = ConfigStore::new(Flash::new_blocking(p.FLASH));
+    let mut config = config_store.load().unwrap_or_default();
+    info!("Config: {:?}", config);
+
+    let distance_per_phase_cycle = config.scale_pitch_mm;
+
+    // Boards without the front-end amplifier just skip gain control.
+    let mut auto_gain = AutoGain::new(config.gain);
+    let mut pga = Pga::setup(pins.pga, auto_gain.gain);
+
+    // A capture takes a few ms and a frame ~10ms, so this only trips if the DMA stops.
+    const TASK_MAIN: usize = 0;
+    const TASK_DIGIMATIC: usize = 1;
+    let watchdog = Watchdog::<2>::new(Duration::from_secs(2));
+
+    // Zero offset applied, in mm.
+    let position_mm = Cell::new(0f32);
+    let units = config.units;
+
+    // Polled on its own so presses are debounced and timed whatever the measuring loop is doing.
+    let gesture: Signal<NoopRawMutex, Gesture> = Signal::new();
+    let fut_button = async {
+        let Some(button) = &mut button else {
+            return core::future::pending().await;
+        };
+        loop {
+            gesture.signal(button.gesture().await);
+        }
+    };
+
+    let fut_main = async {
+        let mut adc_buf = [0u16; NUM_SAMPLES];
+        loop {
+            watchdog.feed(TASK_MAIN);
+            measure::capture(&mut emitter, &mut sampler, &mut adc_buf).await;
+            let phase = correct_phase(
+                measure::phase(&adc_buf, &SINE_COSINE_TABLE),
+                &config.calibration,
+            );
+
+            // phase doesn't depend on amplitude, so the gain can change between captures
+            if let Some(pga) = &mut pga {
+                if let Some(gain) = auto_gain.update(&adc_buf) {
+                    info!("Gain: {}", gain);
+                    if let Err(e) = pga.set_gain(gain) {
+                        error!("Failed to set gain: {:?}", e);
+                    }
+                }
+            }
+
+            phase_accumulator.update(phase);
+            let raw_position = phase_accumulator.unwrapped_phase
+                * (distance_per_phase_cycle / (2.0 * core::f32::consts::PI));
+            position_mm.set(raw_position - config.zero_offset_mm);
+
+            ///////////////////////
+            // handle button press
+
+            if let Some(g) = gesture.try_take() {
+                let action = config.buttons.action(g);
+                info!("Button: {:?} press, {:?}", Debug2Format(&g), action);
+                // the reader does its own holding, display modes and units, so zeroing is all there is to do here
+                if action == ButtonAction::Zero {
+                    config.zero_offset_mm = raw_position;
+                    if let Err(e) = config_store.save(&config) {
+                        error!("Failed to save zero offset: {:?}", e);
+                    }
+                }
+            }
+        }
+    };
+
+    let fut_digimatic = async {
+        loop {
+            watchdog.idle(TASK_DIGIMATIC);
+            while digimatic_req.is_high() {
+                Timer::after_millis(1).await;
+            }
+            watchdog.feed(TASK_DIGIMATIC);
+
+            // rounded like every other output; Digimatic has no fractions, so those go out as decimal inches
+            let (value, decimals, unit) = match units {
+                Units::Millimeter => (
+                    units::Unit::Millimeter.round(position_mm.get()),
+                    2,
+                    digimatic::Unit::Millimeter,
+                ),
+                Units::Inch | Units::FractionalInch => (
+                    units::Unit::Inch.round(position_mm.get()),
+                    4,
+                    digimatic::Unit::Inch,
+                ),
+            };
+            let Some(frame) = digimatic::frame(value, decimals, unit) else {
+                warn!(
+                    "Position {}mm doesn't fit in a Digimatic frame",
+                    position_mm.get()
+                );
+                // wait for the collector to give up rather than answering the same request again
+                while digimatic_req.is_low() {
+                    Timer::after_millis(1).await;
+                }
+                continue;
+            };
+
+            // DATA is set up before CK falls and held until after it rises, so it's valid on either edge
+            for bit in digimatic::bits(&frame) {
+                digimatic_data.set_level(if bit { Level::High } else { Level::Low });
+                digimatic_clock.set_low();
+                Timer::after(DIGIMATIC_HALF_BIT).await;
+                digimatic_clock.set_high();
+                Timer::after(DIGIMATIC_HALF_BIT).await;
+            }
+            digimatic_data.set_high();
+            // a collector still holding REQ gets another frame, which is how DRO boxes read continuously
+        }
+    };
+
+    embassy_futures::join::join4(fut_main, fut_button, fut_digimatic, watchdog.run(p.IWDG)).await;
+}
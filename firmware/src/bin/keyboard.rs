@@ -0,0 +1,451 @@
+#![no_std]
+#![no_main]
+
+// Measures like `local`, and types the position into the host as a USB keyboard on the button gesture bound to `ButtonAction::Send`
+// (a double press by default).
+// The custom class interface stays available, so units and keystroke settings can be changed with `Command::SetUnits` / `Command::SetKeyboard`.
+
+use calipertron::board;
+use calipertron::button::Button;
+use calipertron::config_store::ConfigStore;
+use calipertron::dispatch::Dispatcher;
+use calipertron::measure::{noise_scan, AdcSampler, Hopper, PdmEmitter};
+use calipertron::pga::Pga;
+use calipertron::usb_state::UsbState;
+use calipertron::watchdog::{self, Watchdog};
+use calipertron_core::gain::AutoGain;
+use calipertron_core::readout::{Mode, Readout};
+use calipertron_core::*;
+use schema::*;
+
+use core::cell::{Cell, RefCell};
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_stm32::flash::Flash;
+use embassy_stm32::time::Hertz;
+use embassy_stm32::{bind_interrupts, peripherals, usb};
+use embassy_time::Duration;
+use embassy_usb::class::hid::{self, HidWriter};
+use embassy_usb::Builder;
+
+// panics and hard faults are handled by `calipertron::crash`
+use defmt_rtt as _;
+
+include!(concat!(env!("OUT_DIR"), "/constants.rs"));
+const NUM_SAMPLES: usize = SINE_COSINE_TABLE.len();
+
+bind_interrupts!(struct Irqs {
+    USB_LP_CAN1_RX0 => usb::InterruptHandler<peripherals::USB>;
+});
+
+const MAX_PACKET_SIZE: u8 = 64;
+
+pub const USB_CLASS_CUSTOM: u8 = 0xFF;
+const USB_SUBCLASS_CUSTOM: u8 = 0x00;
+const USB_PROTOCOL_CUSTOM: u8 = 0x00;
+
+/// Boot keyboard report descriptor (HID 1.11 appendix E.6), matching `schema::KeyboardReport`.
+#[rustfmt::skip]
+const KEYBOARD_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x06, // Usage (Keyboard)
+    0xA1, 0x01, // Collection (Application)
+    0x05, 0x07, //   Usage Page (Keyboard/Keypad)
+    0x19, 0xE0, //   Usage Minimum (Left Control)
+    0x29, 0xE7, //   Usage Maximum (Right GUI)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x08, //   Report Count (8)
+    0x81, 0x02, //   Input (Data, Variable, Absolute): modifiers
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x08, //   Report Size (8)
+    0x81, 0x01, //   Input (Constant): reserved
+    0x95, 0x05, //   Report Count (5)
+    0x75, 0x01, //   Report Size (1)
+    0x05, 0x08, //   Usage Page (LEDs)
+    0x19, 0x01, //   Usage Minimum (Num Lock)
+    0x29, 0x05, //   Usage Maximum (Kana)
+    0x91, 0x02, //   Output (Data, Variable, Absolute): LEDs
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x03, //   Report Size (3)
+    0x91, 0x01, //   Output (Constant): LED padding
+    0x95, 0x06, //   Report Count (6)
+    0x75, 0x08, //   Report Size (8)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x65, //   Logical Maximum (101)
+    0x05, 0x07, //   Usage Page (Keyboard/Keypad)
+    0x19, 0x00, //   Usage Minimum (0)
+    0x29, 0x65, //   Usage Maximum (101)
+    0x81, 0x00, //   Input (Data, Array): keys
+    0xC0,       // End Collection
+];
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let mut p = embassy_stm32::init(board::config());
+
+    info!("Hello World!");
+
+    let reset_cause = watchdog::take_reset_cause();
+    info!("Reset cause: {:?}", reset_cause);
+    let crash_report = calipertron::crash::take_crash_report();
+
+    board::reset_usb(&mut p.PA12).await;
+    let pins = calipertron::board_pins!(p);
+
+    ////////////////////////
+    // Signal emission and ADC setup
+
+    let _drive = board::drive_outputs(pins.drive);
+
+    // TIM3 is clocked by TIM2 to trigger ADC conversions in step with the PDM table (`AdcTrigger::MEASURE`).
+    let hardware = calipertron::measure::setup(
+        p.TIM2,
+        p.TIM3,
+        p.ADC1,
+        pins.pickup,
+        Hertz(PDM_FREQUENCY),
+        &AdcSamplingPeriod::CYCLES41_5,
+    )
+    .await;
+
+    let mut emitter = PdmEmitter::new(
+        &hardware.pdm_timer,
+        &hardware.trigger_timer,
+        p.DMA1_CH2,
+        &board::PDM_SIGNAL,
+    );
+    let mut sampler = AdcSampler::new(p.DMA1_CH1);
+
+    let mut button = pins.button.map(Button::new);
+    if button.is_none() {
+        warn!("No button on this board, measurements will never be typed");
+    }
+
+    ////////////////////////
+    // Persisted settings
+
+    // ADC sample time and the base drive frequency are baked into the build.rs tables, so only the scale, hopping and keyboard settings are used here.
+    let mut config_store = ConfigStore::new(Flash::new_blocking(p.FLASH));
+    let config = RefCell::new(config_store.load().unwrap_or_default());
+    info!("Config: {:?}", *config.borrow());
+
+    // Boards without the front-end amplifier just skip gain control.
+    let mut auto_gain = AutoGain::new(config.borrow().gain);
+    let mut pga = Pga::setup(pins.pga, auto_gain.gain);
+
+    ////////////////////////
+    // USB Setup
+
+    let driver = embassy_stm32::usb::Driver::new(p.USB, Irqs, p.PA12, p.PA11);
+    let (vid, pid) = (0xc0de, 0xcafe);
+    let mut usb_config = embassy_usb::Config::new(vid, pid);
+    usb_config.max_packet_size_0 = MAX_PACKET_SIZE;
+    usb_config.product = Some("Calipertron");
+
+    let mut config_descriptor = [0; 256];
+    let mut bos_descriptor = [0; 256];
+    let mut control_buf = [0; 64];
+
+    let mut builder = Builder::new(
+        driver,
+        usb_config,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut [], // no msos descriptors
+        &mut control_buf,
+    );
+
+    // Lets the tasks below notice the host going away, see `UsbState`.
+    let usb_state = UsbState::new();
+    let mut usb_handler = usb_state.handler();
+    builder.handler(&mut usb_handler);
+
+    // Same endpoints as `recorder`, so the host tools find the command interface where they expect it.
+    let mut func = builder.function(USB_CLASS_CUSTOM, USB_SUBCLASS_CUSTOM, USB_PROTOCOL_CUSTOM);
+    let mut iface = func.interface();
+    let mut iface_alt = iface.alt_setting(
+        USB_CLASS_CUSTOM,
+        USB_SUBCLASS_CUSTOM,
+        USB_PROTOCOL_CUSTOM,
+        None,
+    );
+    let read_ep = iface_alt.endpoint_bulk_out(MAX_PACKET_SIZE as u16);
+    // no sample data in this firmware, but it keeps the response endpoint at 0x82
+    let _data_ep = iface_alt.endpoint_bulk_in(MAX_PACKET_SIZE as u16);
+    let response_ep = iface_alt.endpoint_bulk_in(MAX_PACKET_SIZE as u16);
+    drop(func);
+
+    let mut hid_state = hid::State::new();
+    let hid_config = hid::Config {
+        report_descriptor: KEYBOARD_REPORT_DESCRIPTOR,
+        request_handler: None,
+        poll_ms: 10,
+        max_packet_size: KEYBOARD_REPORT_SIZE as u16,
+    };
+    let mut keyboard =
+        HidWriter::<_, KEYBOARD_REPORT_SIZE>::new(&mut builder, &mut hid_state, hid_config);
+
+    let mut usb = builder.build();
+
+    ////////////////////////
+    // Watchdog
+
+    const TASK_MEASURE: usize = 0;
+    const TASK_COMMANDS: usize = 1;
+    const TASK_BUTTON: usize = 2;
+    // A capture takes a few ms and the host polls the keyboard every 10ms, so anything taking this long means USB or DMA is wedged.
+    let watchdog = Watchdog::<3>::new(Duration::from_secs(5));
+
+    ////////////////////////
+    // Measurement
+
+    // Latest position in mm, without the zero offset.
+    let raw_position = Cell::new(0f32);
+    // Display mode over the zeroed position, for what gets typed.
+    let readout = RefCell::new(Readout::new(0.));
+
+    let fut_measure = async {
+        let mut phase_accumulator = PhaseAccumulator::new(0.0, 0.1);
+        let mut adc_buf = [0u16; NUM_SAMPLES];
+
+        // hopping already copes with interference, so only look for a quiet frequency without it
+        let mut base_frequency = Hertz(PDM_FREQUENCY);
+        let pick_quiet_frequency = {
+            let config = config.borrow();
+            config.pick_quiet_frequency && config.hop_frequencies_kHz.is_empty()
+        };
+        if pick_quiet_frequency {
+            let report = noise_scan(
+                &mut emitter,
+                &mut sampler,
+                &mut adc_buf,
+                &SINE_COSINE_TABLE,
+                auto_gain.gain,
+                base_frequency,
+            )
+            .await;
+            info!("Measuring at {}kHz", report.quietest_kHz());
+            base_frequency = Hertz((report.quietest_kHz() * 1000.) as u32);
+        }
+        let mut hopper = Hopper::new(base_frequency);
+
+        loop {
+            watchdog.feed(TASK_MEASURE);
+            // picks up `Command::SetHopping` between measurements
+            hopper.set_frequencies(
+                &config.borrow().hop_frequencies_kHz,
+                &AdcSamplingPeriod::CYCLES41_5,
+            );
+            let phase = hopper
+                .measure(
+                    &mut emitter,
+                    &mut sampler,
+                    &mut adc_buf,
+                    &SINE_COSINE_TABLE,
+                    |samples| {
+                        // phase doesn't depend on amplitude, so the gain can change between captures
+                        if let Some(pga) = &mut pga {
+                            if let Some(gain) = auto_gain.update(samples) {
+                                info!("Gain: {}", gain);
+                                if let Err(e) = pga.set_gain(gain) {
+                                    error!("Failed to set gain: {:?}", e);
+                                }
+                            }
+                        }
+                    },
+                )
+                .await;
+
+            let (calibration, scale_pitch_mm, zero_offset_mm) = {
+                let config = config.borrow();
+                (
+                    config.calibration,
+                    config.scale_pitch_mm,
+                    config.zero_offset_mm,
+                )
+            };
+            let phase = correct_phase(phase, &calibration);
+
+            phase_accumulator.update(phase);
+            raw_position.set(
+                phase_accumulator.unwrapped_phase
+                    * (scale_pitch_mm / (2.0 * core::f32::consts::PI)),
+            );
+            readout
+                .borrow_mut()
+                .update(raw_position.get() - zero_offset_mm);
+        }
+    };
+
+    ////////////////////////
+    // Button and typing
+
+    let fut_button = async {
+        loop {
+            watchdog.idle(TASK_BUTTON);
+            let gesture = match &mut button {
+                Some(button) => button.gesture().await,
+                None => core::future::pending().await,
+            };
+            watchdog.feed(TASK_BUTTON);
+
+            let mut config = config.borrow_mut();
+            let mut readout = readout.borrow_mut();
+            let action = config.buttons.action(gesture);
+            info!("Button: {:?} press, {:?}", Debug2Format(&gesture), action);
+            match action {
+                ButtonAction::None => continue,
+                // saved with the rest of the config by `Command::SaveConfig`
+                ButtonAction::Zero => {
+                    config.zero_offset_mm = raw_position.get();
+                    continue;
+                }
+                ButtonAction::Hold => {
+                    let mode = match readout.mode() {
+                        Mode::Hold => Mode::Absolute,
+                        _ => Mode::Hold,
+                    };
+                    readout.set_mode(mode);
+                    continue;
+                }
+                ButtonAction::NextMode => {
+                    readout.set_mode(readout.mode().next());
+                    continue;
+                }
+                ButtonAction::ToggleUnits => {
+                    config.units = config.units.next();
+                    continue;
+                }
+                ButtonAction::Send => {}
+            }
+
+            if !usb_state.is_connected() {
+                warn!("Button pressed with no host to type into");
+                continue;
+            }
+
+            let (units, keyboard_config) = (config.units, config.keyboard);
+            let value_mm = readout.reading().value_mm;
+            // don't hold the borrows across the USB writes
+            drop(config);
+            drop(readout);
+            let text = format_measurement(value_mm, units, &keyboard_config);
+            info!("Typing {}", text.as_str());
+
+            for report in keystrokes(&text, keyboard_config.terminator) {
+                let written = usb_state.or_disconnect(keyboard.write(&report)).await;
+                if let Some(Err(e)) = written {
+                    error!("Failed to send keystroke: {:?}", e);
+                }
+                if !matches!(written, Some(Ok(()))) {
+                    // the rest of the number on its own would be wrong
+                    break;
+                }
+            }
+        }
+    };
+
+    //////////////////////////
+    // handle commands from host
+
+    let mut dispatcher = Dispatcher::new(read_ep, response_ep);
+
+    let fut_commands = async {
+        loop {
+            watchdog.idle(TASK_COMMANDS);
+            usb_state.wait_connected().await;
+            let Some(command) = usb_state.or_disconnect(dispatcher.next_command()).await else {
+                continue;
+            };
+            watchdog.feed(TASK_COMMANDS);
+
+            let result = {
+                use Command::*;
+                match command {
+                    SetUnits(units) => {
+                        config.borrow_mut().units = units;
+                        Ok(())
+                    }
+
+                    SetKeyboard(keyboard) => {
+                        config.borrow_mut().keyboard = keyboard;
+                        Ok(())
+                    }
+
+                    SetButtons(buttons) => {
+                        config.borrow_mut().buttons = buttons;
+                        Ok(())
+                    }
+
+                    SetHopping(frequencies) => {
+                        config.borrow_mut().hop_frequencies_kHz = frequencies;
+                        Ok(())
+                    }
+
+                    // takes effect from the next startup
+                    SetPickQuietFrequency(pick) => {
+                        config.borrow_mut().pick_quiet_frequency = pick;
+                        Ok(())
+                    }
+
+                    SetMode(mode) => {
+                        readout.borrow_mut().set_mode(mode.into());
+                        Ok(())
+                    }
+
+                    SaveConfig => config_store.save(&config.borrow()).map_err(|e| {
+                        error!("Failed to save config: {:?}", e);
+                        CommandError::Storage
+                    }),
+
+                    LoadConfig => {
+                        *config.borrow_mut() = config_store.load().unwrap_or_default();
+                        Ok(())
+                    }
+
+                    FactoryReset => {
+                        *config.borrow_mut() = DeviceConfig::default();
+                        config_store.erase().map_err(|e| {
+                            error!("Failed to erase config: {:?}", e);
+                            CommandError::Storage
+                        })
+                    }
+
+                    GetDeviceInfo => {
+                        let info = DeviceInfo { reset_cause };
+                        let response = Response::DeviceInfo(info);
+                        watchdog.idle(TASK_COMMANDS);
+                        usb_state.or_disconnect(dispatcher.respond(response)).await;
+                        continue;
+                    }
+
+                    GetCrashReport => {
+                        let report = crash_report.clone();
+                        let response = Response::CrashReport(report);
+                        watchdog.idle(TASK_COMMANDS);
+                        usb_state.or_disconnect(dispatcher.respond(response)).await;
+                        continue;
+                    }
+
+                    // drive frequency, ADC and gain are fixed by the measurement loop, and there's no recording
+                    _ => Err(CommandError::Unsupported),
+                }
+            };
+
+            // the host reads the response whenever it gets to it, just like it sends commands
+            watchdog.idle(TASK_COMMANDS);
+            usb_state.or_disconnect(dispatcher.respond(result)).await;
+        }
+    };
+
+    embassy_futures::join::join5(
+        usb.run(),
+        fut_measure,
+        fut_button,
+        fut_commands,
+        watchdog.run(p.IWDG),
+    )
+    .await;
+}
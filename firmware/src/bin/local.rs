@@ -1,196 +1,258 @@
 #![no_std]
 #![no_main]
 
+use calipertron::board;
+use calipertron::button::Button;
+use calipertron::config_store::ConfigStore;
+use calipertron::measure::{noise_scan, AdcSampler, Hopper, PdmEmitter};
+use calipertron::pga::Pga;
+use calipertron::power::{self, WakeReason};
+use calipertron::watchdog::{self, Watchdog};
+use calipertron_core::button::Gesture;
+use calipertron_core::gain::AutoGain;
+use calipertron_core::power::*;
+use calipertron_core::readout::{Mode, Readout};
 use calipertron_core::*;
+use schema::{format_measurement, format_position, to_millivolts, AdcSamplingPeriod, ButtonAction};
 
 use defmt::*;
 use embassy_executor::Spawner;
-use embassy_stm32::dma::*;
-use embassy_stm32::gpio::{Flex, Input, Level, Output, Speed};
+use embassy_futures::select::{select, Either};
+use embassy_stm32::flash::Flash;
 use embassy_stm32::time::Hertz;
-use embassy_stm32::{adc, Config};
 
-//use embassy_time::Duration;
-use num_traits::Float;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
 
-use {defmt_rtt as _, panic_probe as _};
+// panics and hard faults are handled by `calipertron::crash`
+use defmt_rtt as _;
 
 include!(concat!(env!("OUT_DIR"), "/constants.rs"));
 const NUM_SAMPLES: usize = SINE_COSINE_TABLE.len();
 
+// How often to log the power estimate, counting time in Stop mode. It's only on RTT, for bench measurements with a probe attached.
+const POWER_REPORT_INTERVAL_MS: u64 = 10_000;
+#[allow(non_upper_case_globals)]
+const REPORT_BATTERY_mAh: f32 = 1000.;
+
 #[embassy_executor::main]
 async fn main(_spawner: Spawner) {
-    let mut config = Config::default();
-    {
-        use embassy_stm32::rcc::*;
-        config.rcc.hse = Some(Hse {
-            freq: Hertz(8_000_000),
-            mode: HseMode::Oscillator,
-        });
-        config.rcc.pll = Some(Pll {
-            src: PllSource::HSE,
-            prediv: PllPreDiv::DIV1,
-            mul: PllMul::MUL9,
-        });
-        config.rcc.sys = Sysclk::PLL1_P;
-        config.rcc.ahb_pre = AHBPrescaler::DIV1;
-        config.rcc.apb1_pre = APBPrescaler::DIV2;
-        config.rcc.apb2_pre = APBPrescaler::DIV1;
-    }
-    let p = embassy_stm32::init(config);
+    let p = embassy_stm32::init(board::config());
+    let pins = calipertron::board_pins!(p);
 
     info!("Hello World!");
+    info!("Reset cause: {:?}", watchdog::take_reset_cause());
+    // logged if there was one; there is no host to send it to
+    let _ = calipertron::crash::take_crash_report();
 
     ////////////////////////
-    // Signal emission setup
-
-    let _pins = [
-        Output::new(p.PA0, Level::Low, Speed::Low),
-        Output::new(p.PA1, Level::Low, Speed::Low),
-        Output::new(p.PA2, Level::Low, Speed::Low),
-        Output::new(p.PA3, Level::Low, Speed::Low),
-        Output::new(p.PA4, Level::Low, Speed::Low),
-        Output::new(p.PA5, Level::Low, Speed::Low),
-        Output::new(p.PA6, Level::Low, Speed::Low),
-        Output::new(p.PA7, Level::Low, Speed::Low),
-    ];
-
-    let tim = embassy_stm32::timer::low_level::Timer::new(p.TIM2);
-    let timer_registers = tim.regs_gp16();
-    timer_registers
-        .cr2()
-        .modify(|w| w.set_ccds(embassy_stm32::pac::timer::vals::Ccds::ONUPDATE));
-    timer_registers.dier().modify(|w| {
-        // Enable update DMA request
-        w.set_ude(true);
-        // Enable update interrupt request
-        w.set_uie(true);
-    });
-
-    tim.set_frequency(Hertz(PDM_FREQUENCY));
-
-    let start_pdm = || unsafe {
-        let mut opts = TransferOptions::default();
-        opts.circular = true;
-
-        let dma_ch = embassy_stm32::Peripheral::clone_unchecked(&p.DMA1_CH2);
-        let request = embassy_stm32::timer::UpDma::request(&dma_ch);
-
-        tim.reset();
-
-        let t = Transfer::new_write(
-            dma_ch,
-            request,
-            &PDM_SIGNAL,
-            embassy_stm32::pac::GPIOA.bsrr().as_ptr() as *mut u32,
-            opts,
-        );
-
-        tim.start();
-        t
-    };
-
-    ////////////////////////
-    // ADC + DMA setup
-
-    let start_adc = |sample_buf| unsafe {
-        let dma_ch = embassy_stm32::Peripheral::clone_unchecked(&p.DMA1_CH1);
-        let request = embassy_stm32::adc::RxDma::request(&dma_ch);
-        let opts = TransferOptions::default();
-
-        let t = Transfer::new_read(
-            dma_ch,
-            request,
-            embassy_stm32::pac::ADC1.dr().as_ptr() as *mut u16,
-            sample_buf,
-            opts,
-        );
-
-        // Start ADC conversions
-        embassy_stm32::pac::ADC1.cr2().modify(|w| w.set_adon(true));
-        t
-    };
-
-    // just need this to power on ADC
-    let _adc = adc::Adc::new(p.ADC1);
-
-    // Configure ADC for continuous conversion with DMA
-    let adc = embassy_stm32::pac::ADC1;
+    // Signal emission and ADC setup
+
+    let _drive = board::drive_outputs(pins.drive);
+
+    // TIM3 is clocked by TIM2 to trigger ADC conversions in step with the PDM table (`AdcTrigger::MEASURE`).
+    let hardware = calipertron::measure::setup(
+        p.TIM2,
+        p.TIM3,
+        p.ADC1,
+        pins.pickup,
+        Hertz(PDM_FREQUENCY),
+        &AdcSamplingPeriod::CYCLES41_5,
+    )
+    .await;
+
+    let mut emitter = PdmEmitter::new(
+        &hardware.pdm_timer,
+        &hardware.trigger_timer,
+        p.DMA1_CH2,
+        &board::PDM_SIGNAL,
+    );
+    let mut sampler = AdcSampler::new(p.DMA1_CH1);
+
+    let mut button = pins.button.map(Button::new);
 
-    adc.cr1().modify(|w| {
-        w.set_scan(true);
-        w.set_eocie(true);
-    });
-
-    adc.cr2().modify(|w| {
-        w.set_dma(true);
-        w.set_cont(true);
-    });
+    let mut phase_accumulator = PhaseAccumulator::new(0.0, 0.1);
 
-    // Configure channel and sampling time
-    adc.sqr1().modify(|w| w.set_l(0)); // one conversion.
+    // ADC sample time is baked into the build.rs tables, so only the scale, frequency choice and hopping settings are used here.
+    let mut config_store = ConfigStore::new(Flash::new_blocking(p.FLASH));
+    let mut config = config_store.load().unwrap_or_default();
+    info!("Config: {:?}", config);
+
+    let distance_per_phase_cycle = config.scale_pitch_mm;
+
+    // Boards without the front-end amplifier just skip gain control.
+    let mut auto_gain = AutoGain::new(config.gain);
+    let mut pga = Pga::setup(pins.pga, auto_gain.gain);
+
+    // hopping already copes with interference, so only look for a quiet frequency without it
+    let mut base_frequency = Hertz(PDM_FREQUENCY);
+    if config.pick_quiet_frequency && config.hop_frequencies_kHz.is_empty() {
+        let mut adc_buf = [0u16; NUM_SAMPLES];
+        let report = noise_scan(
+            &mut emitter,
+            &mut sampler,
+            &mut adc_buf,
+            &SINE_COSINE_TABLE,
+            auto_gain.gain,
+            base_frequency,
+        )
+        .await;
+        info!("Measuring at {}kHz", report.quietest_kHz());
+        base_frequency = Hertz((report.quietest_kHz() * 1000.) as u32);
+    }
 
-    // TODO: this may not be necessary
-    let mut pb1 = Flex::new(p.PB1);
-    pb1.set_as_analog();
+    let mut hopper = Hopper::new(base_frequency);
+    hopper.set_frequencies(&config.hop_frequencies_kHz, &AdcSamplingPeriod::CYCLES41_5);
 
-    const PIN_CHANNEL: u8 = 9; // PB1 is on channel 9 for STM32F103
-    adc.sqr3().modify(|w| w.set_sq(0, PIN_CHANNEL));
-    adc.smpr2()
-        .modify(|w| w.set_smp(PIN_CHANNEL as usize, adc::SampleTime::CYCLES41_5));
+    // Measure at full rate while the slider moves, slower when it's still, and stop the chip when it's left alone.
+    let mut power_policy = PowerPolicy::new(PowerConfig::default(), Instant::now().as_millis(), 0.);
+    let mut current_budget = CurrentBudget::default();
 
-    let user_button = Input::new(p.PB14, embassy_stm32::gpio::Pull::None);
+    // A capture takes a few ms, so this only trips if the DMA stops.
+    const TASK_MAIN: usize = 0;
+    let watchdog = Watchdog::<1>::new(Duration::from_secs(2));
 
-    let mut phase_accumulator = PhaseAccumulator::new(0.0, 0.1);
-
-    // 9.4mm spacing across all 8 emission pads on the v1.1 PCB Mitko sent me.
-    let distance_per_phase_cycle = 9.4;
+    // Polled on its own so gestures are timed properly even while measurements are slowed down.
+    let gesture: Signal<NoopRawMutex, Gesture> = Signal::new();
+    let woke_by_button: Signal<NoopRawMutex, ()> = Signal::new();
+    let fut_button = async {
+        let Some(button) = &mut button else {
+            return core::future::pending().await;
+        };
+        loop {
+            match select(button.gesture(), woke_by_button.wait()).await {
+                Either::First(g) => gesture.signal(g),
+                Either::Second(()) => button.ignore_press(),
+            }
+        }
+    };
 
     let fut_main = async {
+        let mut readout = Readout::new(0.);
+        let mut last_vrefint = Instant::now();
+        let mut adc_buf = [0u16; NUM_SAMPLES];
         loop {
-            // TODO: I'd rather this be local, but Transfer requires the buffer have the same lifetime as the DMA channel for some reason.
-            static mut ADC_BUF: [u16; NUM_SAMPLES] = [0u16; NUM_SAMPLES];
-
-            let adc_buf = unsafe { &mut ADC_BUF[..] };
-            let adc_transfer = start_adc(adc_buf);
-            let mut pdm_transfer = start_pdm();
-            // wait for all of the samples to be taken
-            adc_transfer.await;
-            pdm_transfer.request_stop();
-
-            let mut sum_sine: f32 = 0.0;
-            let mut sum_cosine: f32 = 0.0;
-
-            let adc_buf = unsafe { &ADC_BUF[..] };
-
-            for i in 0..NUM_SAMPLES {
-                let (sine, cosine) = SINE_COSINE_TABLE[i];
-                sum_sine += adc_buf[i] as f32 * sine;
-                sum_cosine += adc_buf[i] as f32 * cosine;
-            }
-            let phase = sum_sine.atan2(sum_cosine);
+            watchdog.feed(TASK_MAIN);
+            let capture_start = Instant::now();
+            let phase = hopper
+                .measure(
+                    &mut emitter,
+                    &mut sampler,
+                    &mut adc_buf,
+                    &SINE_COSINE_TABLE,
+                    |samples| {
+                        // phase doesn't depend on amplitude, so the gain can change between captures
+                        if let Some(pga) = &mut pga {
+                            if let Some(gain) = auto_gain.update(samples) {
+                                info!("Gain: {}", gain);
+                                if let Err(e) = pga.set_gain(gain) {
+                                    error!("Failed to set gain: {:?}", e);
+                                }
+                            }
+                        }
+                    },
+                )
+                .await;
+            let phase = correct_phase(phase, &config.calibration);
 
             phase_accumulator.update(phase);
-            info!(
-                //"Phase: {:06.2} Position: {:06.2}",
-                "Position: {}mm, Phase: {} ",
-                phase_accumulator.unwrapped_phase
-                    * (distance_per_phase_cycle / (2.0 * core::f32::consts::PI)),
-                phase,
+            let raw_position = phase_accumulator.unwrapped_phase
+                * (distance_per_phase_cycle / (2.0 * core::f32::consts::PI));
+            let reading = readout.update(raw_position - config.zero_offset_mm);
+            let line = format_position(
+                reading.value_mm,
+                config.units,
+                reading.mode.into(),
+                reading.min_max_mm,
             );
-
-            // make sure everything is reset before we continue
-            pdm_transfer.await;
+            info!("Position: {}, Phase: {}", line.trim_end(), phase);
+            current_budget.capture_ms += capture_start.elapsed().as_millis();
+
+            // Phase doesn't depend on the ADC scale, but keep an eye on the supply.
+            // Triggered conversions are idle between captures, so this doesn't disturb anything.
+            if last_vrefint.elapsed() >= Duration::from_secs(10) {
+                let vrefint = calipertron::adc::read_vrefint();
+                info!("VDDA: {}mV", to_millivolts(4095, vrefint));
+                hopper.log_shares();
+                last_vrefint = Instant::now();
+            }
 
             ///////////////////////
             // handle button press
 
-            if user_button.is_low() {
-                info!("Button pressed, zeroing");
-                phase_accumulator.unwrapped_phase = 0.;
+            if let Some(g) = gesture.try_take() {
+                // using the button counts as activity
+                power_policy.wake(Instant::now().as_millis());
+                let action = config.buttons.action(g);
+                info!("Button: {:?} press, {:?}", Debug2Format(&g), action);
+                match action {
+                    ButtonAction::None => {}
+                    ButtonAction::Zero => {
+                        config.zero_offset_mm = raw_position;
+                        if let Err(e) = config_store.save(&config) {
+                            error!("Failed to save zero offset: {:?}", e);
+                        }
+                    }
+                    ButtonAction::Hold if readout.mode() == Mode::Hold => {
+                        readout.set_mode(Mode::Absolute)
+                    }
+                    ButtonAction::Hold => readout.set_mode(Mode::Hold),
+                    ButtonAction::NextMode => readout.set_mode(readout.mode().next()),
+                    // not saved; the next zero saves it along with the offset
+                    ButtonAction::ToggleUnits => {
+                        config.units = config.units.next();
+                    }
+                    ButtonAction::Send => {
+                        let text =
+                            format_measurement(reading.value_mm, config.units, &config.keyboard);
+                        info!("Measurement: {} {:?}", text.as_str(), config.units);
+                    }
+                }
+            }
+
+            ///////////////////////
+            // power
+
+            if current_budget.total_ms() >= POWER_REPORT_INTERVAL_MS {
+                let average_mA = current_budget.average_mA(&STM32F103_CURRENT);
+                info!(
+                    "Power: {:?}, ~{}mA average over the last {}s ({}ms stopped), ~{}h on a {}mAh battery",
+                    Debug2Format(&power_policy.mode()),
+                    average_mA,
+                    current_budget.total_ms() / 1000,
+                    current_budget.stop_ms,
+                    current_budget.battery_life_hours(&STM32F103_CURRENT, REPORT_BATTERY_mAh),
+                    REPORT_BATTERY_mAh,
+                );
+                current_budget = CurrentBudget::default();
+            }
+
+            let was_stopped = power_policy.mode() == PowerMode::Stopped;
+            match power_policy.update(Instant::now().as_millis(), raw_position) {
+                PowerAction::Measure { after_ms } => {
+                    if was_stopped {
+                        info!("Motion, waking up");
+                    }
+                    Timer::after_millis(after_ms as u64).await;
+                    current_budget.idle_ms += after_ms as u64;
+                }
+                PowerAction::Stop { probe_after_ms } => {
+                    if !was_stopped {
+                        info!("Idle, entering Stop mode");
+                    }
+                    let (reason, stopped_ms) = power::stop(probe_after_ms, board::WAKE_BUTTON);
+                    current_budget.stop_ms += stopped_ms as u64;
+                    if reason == WakeReason::Button {
+                        info!("Button pressed, waking up");
+                        power_policy.wake(Instant::now().as_millis());
+                        // the press that woke it isn't a gesture
+                        woke_by_button.signal(());
+                    }
+                }
             }
         }
     };
 
-    fut_main.await
+    embassy_futures::join::join3(fut_main, fut_button, watchdog.run(p.IWDG)).await;
 }
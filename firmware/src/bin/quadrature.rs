@@ -0,0 +1,152 @@
+#![no_std]
+#![no_main]
+
+// Measures like `local`, and outputs the position as quadrature A/B and index pulses, like a linear encoder.
+//
+// Wiring (free on every board): PB6 A, PB7 B, PB8 index, plus ground. Outputs are 3.3V push-pull.
+// Resolution, maximum edge rate and index spacing come from the saved `QuadratureConfig`.
+
+use calipertron::board;
+use calipertron::config_store::ConfigStore;
+use calipertron::measure::{AdcSampler, PdmEmitter};
+use calipertron::pga::Pga;
+use calipertron::watchdog::{self, Watchdog};
+use calipertron_core::gain::AutoGain;
+use calipertron_core::quadrature::Quadrature;
+use calipertron_core::*;
+use schema::AdcSamplingPeriod;
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_stm32::flash::Flash;
+use embassy_stm32::gpio::{Level, Output, Speed};
+use embassy_stm32::time::Hertz;
+
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+
+// panics and hard faults are handled by `calipertron::crash`
+use defmt_rtt as _;
+
+include!(concat!(env!("OUT_DIR"), "/constants.rs"));
+const NUM_SAMPLES: usize = SINE_COSINE_TABLE.len();
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_stm32::init(board::config());
+    let pins = calipertron::board_pins!(p);
+
+    info!("Hello World!");
+    info!("Reset cause: {:?}", watchdog::take_reset_cause());
+    // logged if there was one; there is no host to send it to
+    let _ = calipertron::crash::take_crash_report();
+
+    ////////////////////////
+    // Signal emission and ADC setup
+
+    let _drive = board::drive_outputs(pins.drive);
+
+    // TIM3 is clocked by TIM2 to trigger ADC conversions in step with the PDM table (`AdcTrigger::MEASURE`).
+    let hardware = calipertron::measure::setup(
+        p.TIM2,
+        p.TIM3,
+        p.ADC1,
+        pins.pickup,
+        Hertz(PDM_FREQUENCY),
+        &AdcSamplingPeriod::CYCLES41_5,
+    )
+    .await;
+
+    let mut emitter = PdmEmitter::new(
+        &hardware.pdm_timer,
+        &hardware.trigger_timer,
+        p.DMA1_CH2,
+        &board::PDM_SIGNAL,
+    );
+    let mut sampler = AdcSampler::new(p.DMA1_CH1);
+
+    let mut quadrature_a = Output::new(p.PB6, Level::Low, Speed::Low);
+    let mut quadrature_b = Output::new(p.PB7, Level::Low, Speed::Low);
+    let mut quadrature_index = Output::new(p.PB8, Level::Low, Speed::Low);
+
+    let mut phase_accumulator = PhaseAccumulator::new(0.0, 0.1);
+
+    // Drive frequency and ADC sample time are baked into the build.rs tables, so only the scale and quadrature settings are used here.
+    // Nothing is saved from here, so the store isn't kept around.
+    let config = ConfigStore::new(Flash::new_blocking(p.FLASH))
+        .load()
+        .unwrap_or_default();
+    info!("Config: {:?}", config);
+
+    let distance_per_phase_cycle = config.scale_pitch_mm;
+
+    // Boards without the front-end amplifier just skip gain control.
+    let mut auto_gain = AutoGain::new(config.gain);
+    let mut pga = Pga::setup(pins.pga, auto_gain.gain);
+
+    // Captures take a few ms and every capture sends a position, so this only trips if the DMA stops.
+    const TASK_MAIN: usize = 0;
+    const TASK_QUADRATURE: usize = 1;
+    let watchdog = Watchdog::<2>::new(Duration::from_secs(2));
+
+    // Raw position in mm, without the zero offset: the receiver keeps its own zero.
+    let new_position: Signal<NoopRawMutex, f32> = Signal::new();
+    let quadrature_config = config.quadrature;
+    let edge_period = Duration::from_ticks(
+        embassy_time::TICK_HZ.div_ceil(quadrature_config.max_edge_rate_Hz.max(1) as u64),
+    );
+
+    let fut_main = async {
+        let mut adc_buf = [0u16; NUM_SAMPLES];
+        loop {
+            watchdog.feed(TASK_MAIN);
+            measure::capture(&mut emitter, &mut sampler, &mut adc_buf).await;
+            let phase = correct_phase(
+                measure::phase(&adc_buf, &SINE_COSINE_TABLE),
+                &config.calibration,
+            );
+
+            // phase doesn't depend on amplitude, so the gain can change between captures
+            if let Some(pga) = &mut pga {
+                if let Some(gain) = auto_gain.update(&adc_buf) {
+                    info!("Gain: {}", gain);
+                    if let Err(e) = pga.set_gain(gain) {
+                        error!("Failed to set gain: {:?}", e);
+                    }
+                }
+            }
+
+            phase_accumulator.update(phase);
+            let raw_position = phase_accumulator.unwrapped_phase
+                * (distance_per_phase_cycle / (2.0 * core::f32::consts::PI));
+            new_position.signal(raw_position);
+        }
+    };
+
+    let fut_quadrature = async {
+        let mut quadrature = Quadrature::new(
+            quadrature_config.resolution_mm,
+            quadrature_config.index_every as u32,
+        );
+        loop {
+            watchdog.feed(TASK_QUADRATURE);
+            quadrature.set_position(new_position.wait().await);
+
+            // big moves are played out at the maximum edge rate, following the position as it keeps moving
+            while let Some(outputs) = quadrature.step() {
+                quadrature_a.set_level(outputs.a.into());
+                quadrature_b.set_level(outputs.b.into());
+                quadrature_index.set_level(outputs.index.into());
+                Timer::after(edge_period).await;
+                watchdog.feed(TASK_QUADRATURE);
+
+                if let Some(position) = new_position.try_take() {
+                    quadrature.set_position(position);
+                }
+            }
+        }
+    };
+
+    embassy_futures::join::join3(fut_main, fut_quadrature, watchdog.run(p.IWDG)).await;
+}
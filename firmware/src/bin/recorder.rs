@@ -1,18 +1,30 @@
 #![no_std]
 #![no_main]
+use calipertron::board;
+use calipertron::config_store::ConfigStore;
+use calipertron::dispatch::Dispatcher;
+use calipertron::pga::Pga;
+use calipertron::usb_state::UsbState;
+use calipertron::watchdog::{self, Watchdog};
+use calipertron_core::unpack_dual_adc;
 use schema::*;
 
+use core::cell::{Cell, RefCell};
 use defmt::*;
 use embassy_executor::Spawner;
 use embassy_stm32::adc::Adc;
 use embassy_stm32::dma::*;
-use embassy_stm32::gpio::{Flex, Level, Output, Speed};
+use embassy_stm32::flash::Flash;
+use embassy_stm32::gpio::Flex;
 use embassy_stm32::time::Hertz;
-use embassy_stm32::{adc, bind_interrupts, peripherals, usb, Config};
-use embassy_time::Timer;
-use embassy_usb::driver::{Endpoint, EndpointIn, EndpointOut};
+use embassy_stm32::{bind_interrupts, peripherals, usb};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Duration;
+use embassy_usb::driver::EndpointIn;
 use embassy_usb::Builder;
-use {defmt_rtt as _, panic_probe as _};
+// panics and hard faults are handled by `calipertron::crash`
+use defmt_rtt as _;
 
 include!(concat!(env!("OUT_DIR"), "/constants.rs"));
 
@@ -20,9 +32,8 @@ bind_interrupts!(struct Irqs {
     USB_LP_CAN1_RX0 => usb::InterruptHandler<peripherals::USB>;
 });
 
-const MAX_PACKET_SIZE: u8 = 64;
-const SAMPLES_PER_PACKET: usize = (MAX_PACKET_SIZE as usize) / 2; // 2 bytes per sample
-const NUM_SAMPLES: usize = SAMPLES_PER_PACKET * 128;
+const MAX_PACKET_SIZE: u8 = DATA_PACKET_SIZE as u8;
+const NUM_SAMPLES: usize = RECORD_NUM_SAMPLES;
 
 pub const USB_CLASS_CUSTOM: u8 = 0xFF;
 const USB_SUBCLASS_CUSTOM: u8 = 0x00;
@@ -30,47 +41,21 @@ const USB_PROTOCOL_CUSTOM: u8 = 0x00;
 
 #[embassy_executor::main]
 async fn main(_spawner: Spawner) {
-    let mut config = Config::default();
-    {
-        use embassy_stm32::rcc::*;
-        config.rcc.hse = Some(Hse {
-            freq: Hertz(8_000_000),
-            mode: HseMode::Oscillator,
-        });
-        config.rcc.pll = Some(Pll {
-            src: PllSource::HSE,
-            prediv: PllPreDiv::DIV1,
-            mul: PllMul::MUL9,
-        });
-        config.rcc.sys = Sysclk::PLL1_P;
-        config.rcc.ahb_pre = AHBPrescaler::DIV1;
-        config.rcc.apb1_pre = APBPrescaler::DIV2;
-        config.rcc.apb2_pre = APBPrescaler::DIV1;
-    }
-    let mut p = embassy_stm32::init(config);
+    let mut p = embassy_stm32::init(board::config());
 
     info!("Hello World!");
 
-    {
-        // Board has a pull-up resistor on the D+ line; pull it down to send a RESET condition to the USB bus.
-        // This forced reset is needed only for development, without it host will not reset your device when you upload new firmware.
-        let _dp = Output::new(&mut p.PA12, Level::Low, Speed::Low);
-        Timer::after_millis(10).await;
-    }
+    let reset_cause = watchdog::take_reset_cause();
+    info!("Reset cause: {:?}", reset_cause);
+    let crash_report = calipertron::crash::take_crash_report();
+
+    board::reset_usb(&mut p.PA12).await;
+    let pins = calipertron::board_pins!(p);
 
     ////////////////////////
     // Signal emission setup
 
-    let _pins = [
-        Output::new(p.PA0, Level::Low, Speed::Low),
-        Output::new(p.PA1, Level::Low, Speed::Low),
-        Output::new(p.PA2, Level::Low, Speed::Low),
-        Output::new(p.PA3, Level::Low, Speed::Low),
-        Output::new(p.PA4, Level::Low, Speed::Low),
-        Output::new(p.PA5, Level::Low, Speed::Low),
-        Output::new(p.PA6, Level::Low, Speed::Low),
-        Output::new(p.PA7, Level::Low, Speed::Low),
-    ];
+    let _drive = board::drive_outputs(pins.drive);
 
     let tim = embassy_stm32::timer::low_level::Timer::new(p.TIM2);
     let timer_registers = tim.regs_gp16();
@@ -84,7 +69,8 @@ async fn main(_spawner: Spawner) {
         w.set_uie(true);
     });
 
-    tim.set_frequency(Hertz(100_000));
+    // Clocked by TIM2 to trigger ADC conversions in step with the PDM table, see `AdcTrigger::PdmTimer`.
+    let trigger_tim = embassy_stm32::timer::low_level::Timer::new(p.TIM3);
 
     let start_pdm = || unsafe {
         let mut opts = TransferOptions::default();
@@ -94,11 +80,12 @@ async fn main(_spawner: Spawner) {
         let request = embassy_stm32::timer::UpDma::request(&dma_ch);
 
         tim.reset();
+        calipertron::adc::restart_trigger(&trigger_tim);
 
         let t = Transfer::new_write(
             dma_ch,
             request,
-            &PDM_SIGNAL,
+            &board::PDM_SIGNAL,
             embassy_stm32::pac::GPIOA.bsrr().as_ptr() as *mut u32,
             opts,
         );
@@ -129,6 +116,11 @@ async fn main(_spawner: Spawner) {
         &mut control_buf,
     );
 
+    // Lets the tasks below notice the host going away, see `UsbState`.
+    let usb_state = UsbState::new();
+    let mut usb_handler = usb_state.handler();
+    builder.handler(&mut usb_handler);
+
     let mut func = builder.function(USB_CLASS_CUSTOM, USB_SUBCLASS_CUSTOM, USB_PROTOCOL_CUSTOM);
     let mut iface = func.interface();
 
@@ -138,8 +130,9 @@ async fn main(_spawner: Spawner) {
         USB_PROTOCOL_CUSTOM,
         None,
     );
-    let mut read_ep = iface_alt.endpoint_bulk_out(MAX_PACKET_SIZE as u16);
+    let read_ep = iface_alt.endpoint_bulk_out(MAX_PACKET_SIZE as u16);
     let mut write_ep = iface_alt.endpoint_bulk_in(MAX_PACKET_SIZE as u16);
+    let response_ep = iface_alt.endpoint_bulk_in(MAX_PACKET_SIZE as u16);
     drop(func);
 
     let mut usb = builder.build();
@@ -149,39 +142,42 @@ async fn main(_spawner: Spawner) {
     ////////////////////////
     // ADC + DMA setup
 
-    let start_adc = |sample_buf| unsafe {
+    // In dual ADC mode each 32-bit word of the buffer holds one sample from each ADC, otherwise it holds two consecutive ADC1 samples.
+    let start_adc = |sample_buf: &'static mut [u32], trigger: &AdcTrigger, dual: bool| unsafe {
         let dma_ch = embassy_stm32::Peripheral::clone_unchecked(&p.DMA1_CH1);
         let request = embassy_stm32::adc::RxDma::request(&dma_ch);
         let opts = TransferOptions::default();
 
-        let t = Transfer::new_read(
-            dma_ch,
-            request,
-            embassy_stm32::pac::ADC1.dr().as_ptr() as *mut u16,
-            sample_buf,
-            opts,
-        );
+        let t = if dual {
+            Transfer::new_read(
+                dma_ch,
+                request,
+                embassy_stm32::pac::ADC1.dr().as_ptr() as *mut u32,
+                sample_buf,
+                opts,
+            )
+        } else {
+            Transfer::new_read(
+                dma_ch,
+                request,
+                embassy_stm32::pac::ADC1.dr().as_ptr() as *mut u16,
+                as_samples(sample_buf),
+                opts,
+            )
+        };
 
         // Start ADC conversions
-        embassy_stm32::pac::ADC1.cr2().modify(|w| w.set_adon(true));
+        calipertron::adc::start_conversions(trigger);
         t
     };
 
-    let mut adc = Adc::new(p.ADC1);
-
-    let vrefint_sample = {
-        let mut vrefint = adc.enable_vref();
-
-        // give vref some time to warm up
-        Timer::after_millis(100).await;
+    let _adc = Adc::new(p.ADC1);
+    // only converts when interleaved with ADC1, see `AdcSamplingPeriod::CYCLES1_5_DUAL_INTERLEAVED`
+    let _adc2 = Adc::new(p.ADC2);
 
-        adc.read(&mut vrefint).await as u32
-    };
-    info!("VREFINT: {}", vrefint_sample);
-
-    //let convert_to_millivolts = |sample| (sample as u32 * adc::VREF_INT / vrefint_sample) as u16;
+    calipertron::adc::setup().await;
 
-    // Configure ADC for continuous conversion with DMA
+    // Configure ADC for DMA; continuous or timer-triggered conversion is set by `apply_config`
     let adc = embassy_stm32::pac::ADC1;
 
     adc.cr1().modify(|w| {
@@ -189,125 +185,308 @@ async fn main(_spawner: Spawner) {
         w.set_eocie(true);
     });
 
-    adc.cr2().modify(|w| {
-        w.set_dma(true);
-        w.set_cont(true);
-    });
+    adc.cr2().modify(|w| w.set_dma(true));
 
     // Configure channel and sampling time
     adc.sqr1().modify(|w| w.set_l(0)); // one conversion.
 
     // TODO: this may not be necessary
-    let mut pb1 = Flex::new(p.PB1);
-    pb1.set_as_analog();
+    let mut pickup = Flex::new(pins.pickup);
+    pickup.set_as_analog();
+
+    adc.sqr3()
+        .modify(|w| w.set_sq(0, board::PICKUP_ADC_CHANNEL));
+
+    // Not every board has the front-end amplifier.
+    let pga = RefCell::new(pins.pga.map(Pga::new));
+
+    // Read by the recording future, which can't borrow `device_config` while commands are being handled.
+    let adc_trigger = Cell::new(AdcTrigger::FreeRunning);
+    let dual_adc = Cell::new(false);
+
+    let apply_config = |device_config: &DeviceConfig| {
+        tim.set_frequency(Hertz((device_config.frequency_kHz * 1000.) as u32));
+        adc.smpr2().modify(|w| {
+            w.set_smp(
+                board::PICKUP_ADC_CHANNEL as usize,
+                calipertron::adc::sample_time(&device_config.adc_sampling_period),
+            )
+        });
+        calipertron::adc::set_trigger(&tim, &trigger_tim, &device_config.adc_trigger);
+        adc_trigger.set(device_config.adc_trigger);
+
+        let dual = device_config.adc_sampling_period.is_dual_interleaved();
+        calipertron::adc::set_dual_interleaved(dual, board::PICKUP_ADC_CHANNEL);
+        dual_adc.set(dual);
 
-    const PIN_CHANNEL: u8 = 9; // PB1 is on channel 9 for STM32F103
-    adc.sqr3().modify(|w| w.set_sq(0, PIN_CHANNEL));
-    adc.smpr2()
-        .modify(|w| w.set_smp(PIN_CHANNEL as usize, adc::SampleTime::CYCLES239_5));
+        if let Some(pga) = pga.borrow_mut().as_mut() {
+            if let Err(e) = pga.set_gain(device_config.gain) {
+                error!("Failed to set gain: {:?}", e);
+            }
+        }
+    };
+
+    // Only take on settings the ADC can keep up with.
+    let update_config = |new_config: DeviceConfig, device_config: &mut DeviceConfig| {
+        if new_config.adc_sample_rate_Hz().is_none() {
+            return Err(CommandError::TriggerTooFast);
+        }
+        *device_config = new_config;
+        apply_config(device_config);
+        Ok(())
+    };
+
+    ////////////////////////
+    // Persisted settings
+
+    let mut config_store = ConfigStore::new(Flash::new_blocking(p.FLASH));
+    let mut device_config = config_store.load().unwrap_or_else(|| {
+        info!("No stored config, using defaults");
+        DeviceConfig::default()
+    });
+    info!("Config: {:?}", device_config);
+    apply_config(&device_config);
+
+    ////////////////////////
+    // Watchdog
+
+    const TASK_COMMANDS: usize = 0;
+    const TASK_RECORD: usize = 1;
+    // Host tools read each response as soon as it's sent, so a command taking this long means USB or the flash is wedged.
+    // Recordings are only watched while the ADC DMA runs: sending them waits on the host, which may stop reading at any point.
+    let watchdog = Watchdog::<2>::new(Duration::from_secs(5));
+    let fut_watchdog = watchdog.run(p.IWDG);
 
     //////////////////////////
     // handle commands from host
+
+    let mut dispatcher = Dispatcher::new(read_ep, response_ep);
+
+    // Commands keep being answered while a recording is sent, so the host gets a Busy error rather than a stalled endpoint.
+    let recording = Cell::new(false);
+    let start_recording: Signal<NoopRawMutex, ()> = Signal::new();
+
     let fut_commands = async {
-        // Wait for USB to connect
-        read_ep.wait_enabled().await;
+        loop {
+            watchdog.idle(TASK_COMMANDS);
+            usb_state.wait_connected().await;
+            let Some(command) = usb_state.or_disconnect(dispatcher.next_command()).await else {
+                continue;
+            };
+            watchdog.feed(TASK_COMMANDS);
+
+            let result = if recording.get() {
+                Err(CommandError::Busy)
+            } else {
+                use Command::*;
+                match command {
+                    SetFrequency {
+                        frequency_kHz,
+                        adc_sampling_period,
+                    } => update_config(
+                        DeviceConfig {
+                            frequency_kHz,
+                            adc_sampling_period,
+                            ..device_config.clone()
+                        },
+                        &mut device_config,
+                    ),
+
+                    SetAdcTrigger(trigger) => update_config(
+                        DeviceConfig {
+                            adc_trigger: trigger,
+                            ..device_config.clone()
+                        },
+                        &mut device_config,
+                    ),
+
+                    SetGain(_) if pga.borrow().is_none() => Err(CommandError::Unsupported),
+                    SetGain(gain) => update_config(
+                        DeviceConfig {
+                            gain,
+                            ..device_config.clone()
+                        },
+                        &mut device_config,
+                    ),
+
+                    // only used by the `local`, `keyboard` and `quadrature` firmware, but saved from here too
+                    SetUnits(units) => {
+                        device_config.units = units;
+                        Ok(())
+                    }
 
-        // Wait for USB to connect
-        write_ep.wait_enabled().await;
+                    SetKeyboard(keyboard) => {
+                        device_config.keyboard = keyboard;
+                        Ok(())
+                    }
 
-        info!("Ready");
+                    SetQuadrature(quadrature) => {
+                        device_config.quadrature = quadrature;
+                        Ok(())
+                    }
+
+                    SetButtons(buttons) => {
+                        device_config.buttons = buttons;
+                        Ok(())
+                    }
+
+                    // frequencies the ADC can't keep up with are skipped by the measuring firmware
+                    SetHopping(frequencies) => {
+                        device_config.hop_frequencies_kHz = frequencies;
+                        Ok(())
+                    }
+
+                    SetPickQuietFrequency(pick) => {
+                        device_config.pick_quiet_frequency = pick;
+                        Ok(())
+                    }
+
+                    // no measurements to show
+                    SetMode(_) => Err(CommandError::Unsupported),
+
+                    // need the fixed build.rs correlation table, see the `self_test` firmware
+                    SelfTest | NoiseScan => Err(CommandError::Unsupported),
+
+                    SaveConfig => config_store.save(&device_config).map_err(|e| {
+                        error!("Failed to save config: {:?}", e);
+                        CommandError::Storage
+                    }),
+
+                    LoadConfig => {
+                        device_config = config_store.load().unwrap_or_default();
+                        apply_config(&device_config);
+                        Ok(())
+                    }
+
+                    FactoryReset => {
+                        device_config = DeviceConfig::default();
+                        apply_config(&device_config);
+                        config_store.erase().map_err(|e| {
+                            error!("Failed to erase config: {:?}", e);
+                            CommandError::Storage
+                        })
+                    }
+
+                    GetDeviceInfo => {
+                        let info = DeviceInfo { reset_cause };
+                        let response = Response::DeviceInfo(info);
+                        watchdog.idle(TASK_COMMANDS);
+                        usb_state.or_disconnect(dispatcher.respond(response)).await;
+                        continue;
+                    }
+
+                    GetCrashReport => {
+                        let report = crash_report.clone();
+                        let response = Response::CrashReport(report);
+                        watchdog.idle(TASK_COMMANDS);
+                        usb_state.or_disconnect(dispatcher.respond(response)).await;
+                        continue;
+                    }
+
+                    Record => {
+                        recording.set(true);
+                        start_recording.signal(());
+                        Ok(())
+                    }
+                }
+            };
+
+            // the host reads the response whenever it gets to it, just like it sends commands
+            watchdog.idle(TASK_COMMANDS);
+            usb_state.or_disconnect(dispatcher.respond(result)).await;
+        }
+    };
+
+    // would be nice to extract this, but async closures aren't stable yet and no way in hell I'm going to write out the types.
+    let fut_record = async {
+        let mut capture_id: u16 = 0;
 
         loop {
-            let mut command_buf = [0u8; MAX_PACKET_SIZE as usize];
-
-            match read_ep.read(&mut command_buf).await {
-                Ok(size) => {
-                    if let Some(command) = Command::deserialize(&command_buf[..size]) {
-                        info!("Received command: {:?}", command);
-                        use Command::*;
-                        match command {
-                            SetFrequency {
-                                frequency_kHz,
-                                adc_sampling_period,
-                            } => {
-                                tim.set_frequency(Hertz((frequency_kHz * 1000.) as u32));
-
-                                adc.smpr2().modify(|w| {
-                                    w.set_smp(
-                                        PIN_CHANNEL as usize,
-                                        match adc_sampling_period {
-                                            AdcSamplingPeriod::CYCLES1_5 => {
-                                                adc::SampleTime::CYCLES1_5
-                                            }
-                                            AdcSamplingPeriod::CYCLES7_5 => {
-                                                adc::SampleTime::CYCLES7_5
-                                            }
-                                            AdcSamplingPeriod::CYCLES13_5 => {
-                                                adc::SampleTime::CYCLES13_5
-                                            }
-                                            AdcSamplingPeriod::CYCLES28_5 => {
-                                                adc::SampleTime::CYCLES28_5
-                                            }
-                                            AdcSamplingPeriod::CYCLES41_5 => {
-                                                adc::SampleTime::CYCLES41_5
-                                            }
-                                            AdcSamplingPeriod::CYCLES55_5 => {
-                                                adc::SampleTime::CYCLES55_5
-                                            }
-                                            AdcSamplingPeriod::CYCLES71_5 => {
-                                                adc::SampleTime::CYCLES71_5
-                                            }
-                                            AdcSamplingPeriod::CYCLES239_5 => {
-                                                adc::SampleTime::CYCLES239_5
-                                            }
-                                        },
-                                    )
-                                })
-                            }
-
-                            // would be nice to extract this, but async closures aren't stable yet and no way in hell I'm going to write out the types.
-                            Record => {
-                                // TODO: I'd rather this be local, but Transfer requires the buffer have the same lifetime as the DMA channel for some reason.
-                                static mut ADC_BUF: [u16; NUM_SAMPLES] = [0u16; NUM_SAMPLES];
-
-                                let buf = unsafe { &mut ADC_BUF[..] };
-
-                                // start ADC
-                                let adc_transfer = start_adc(buf);
-
-                                // start PDM
-                                let mut pdm_transfer = start_pdm();
-
-                                // wait for all of the samples to be taken
-                                adc_transfer.await;
-                                // TODO: why am I getting errors about multiple mutable borrows --- shouldn't awaiting the adc_transfer above end the borrow?
-                                let buf = unsafe { &mut ADC_BUF[..] };
-
-                                pdm_transfer.request_stop();
-
-                                // now we can send the collected results back to the host
-
-                                // for x in buf.iter_mut() {
-                                //     *x = convert_to_millivolts(*x);
-                                // }
-                                for c in buf.chunks(SAMPLES_PER_PACKET) {
-                                    let r = write_ep.write(bytemuck::cast_slice(c)).await;
-                                    if r.is_err() {
-                                        error!("USB Error: {:?}", r);
-                                        break;
-                                    }
-                                }
-
-                                // make sure everything is reset before we continue
-                                pdm_transfer.await;
-                            }
-                        }
-                    } else {
-                        error!("Failed to deserialize command");
+            watchdog.idle(TASK_RECORD);
+            if usb_state
+                .or_disconnect(start_recording.wait())
+                .await
+                .is_none()
+            {
+                // a recording asked for before the host went away isn't wanted any more
+                start_recording.reset();
+                recording.set(false);
+                usb_state.wait_connected().await;
+                continue;
+            }
+            watchdog.feed(TASK_RECORD);
+            capture_id = capture_id.wrapping_add(1);
+
+            // TODO: I'd rather this be local, but Transfer requires the buffer have the same lifetime as the DMA channel for some reason.
+            static mut ADC_BUF: [u32; NUM_SAMPLES / 2] = [0u32; NUM_SAMPLES / 2];
+
+            let buf = unsafe { &mut ADC_BUF[..] };
+            let dual = dual_adc.get();
+
+            // re-read between captures to follow supply drift; the host gets it in the first packet
+            let vrefint = calipertron::adc::read_vrefint();
+
+            // start ADC
+            let adc_transfer = start_adc(buf, &adc_trigger.get(), dual);
+
+            // start PDM
+            let mut pdm_transfer = start_pdm();
+
+            // wait for all of the samples to be taken; if the host goes away first, dropping the transfer stops the ADC DMA
+            let captured = usb_state.or_disconnect(adc_transfer).await.is_some();
+            // the send below goes at whatever pace the host reads it
+            watchdog.idle(TASK_RECORD);
+            // TODO: why am I getting errors about multiple mutable borrows --- shouldn't awaiting the adc_transfer above end the borrow?
+            let buf = unsafe { &mut ADC_BUF[..] };
+
+            pdm_transfer.request_stop();
+
+            // now we can send the collected results back to the host
+            let send = async {
+                let mut packet = [0u8; DATA_PACKET_SIZE];
+                let packet = DataPacket::encode_scale(capture_id, 0, vrefint, &mut packet);
+                if let Err(e) = write_ep.write(packet).await {
+                    error!("USB Error: {:?}", e);
+                }
+
+                let mut single_samples;
+                let mut dual_samples;
+                let samples: &mut dyn Iterator<Item = u16> = if dual {
+                    dual_samples = unpack_dual_adc(buf);
+                    &mut dual_samples
+                } else {
+                    single_samples = as_samples(buf).iter().copied();
+                    &mut single_samples
+                };
+
+                for sequence in 0..NUM_SAMPLES.div_ceil(SAMPLES_PER_DATA_PACKET) {
+                    let mut chunk = [0u16; SAMPLES_PER_DATA_PACKET];
+                    let mut n = 0;
+                    for (c, sample) in chunk.iter_mut().zip(samples.by_ref()) {
+                        *c = sample;
+                        n += 1;
+                    }
+
+                    let mut packet = [0u8; DATA_PACKET_SIZE];
+                    // sequence 0 is the scale marker
+                    let sequence = sequence as u16 + 1;
+                    let packet = DataPacket::encode(capture_id, sequence, &chunk[..n], &mut packet);
+                    let r = write_ep.write(packet).await;
+                    if r.is_err() {
+                        error!("USB Error: {:?}", r);
+                        break;
                     }
                 }
-                Err(e) => error!("Failed to read USB packet: {:?}", e),
+            };
+
+            if !captured || usb_state.or_disconnect(send).await.is_none() {
+                warn!("Host went away, capture {} abandoned", capture_id);
             }
+
+            // make sure everything is reset before we continue
+            pdm_transfer.await;
+
+            recording.set(false);
         }
     };
 
@@ -315,9 +494,16 @@ async fn main(_spawner: Spawner) {
     // embassy_futures::join::join3(fut_commands, fut_usb, fut_stream_adc).await;
 
     let fut_commands = core::pin::pin!(fut_commands);
+    let fut_record = core::pin::pin!(fut_record);
     let fut_usb = core::pin::pin!(fut_usb);
+    let fut_watchdog = core::pin::pin!(fut_watchdog);
 
-    let futures: [core::pin::Pin<&mut dyn core::future::Future<Output = _>>; 2] =
-        [fut_usb, fut_commands];
+    let futures: [core::pin::Pin<&mut dyn core::future::Future<Output = _>>; 4] =
+        [fut_usb, fut_commands, fut_record, fut_watchdog];
     embassy_futures::join::join_array(futures).await;
 }
+
+/// View a buffer of packed sample pairs as individual samples.
+fn as_samples(words: &mut [u32]) -> &mut [u16] {
+    unsafe { core::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u16, words.len() * 2) }
+}
@@ -0,0 +1,292 @@
+#![no_std]
+#![no_main]
+
+// Emitter self-test: drives each of the 8 pads on its own, then each adjacent pair, and reports the amplitude the pickup sees
+// for each as a `SelfTestReport`, graded against the rest (see `schema::self_test`).
+// Runs once at startup (logged over RTT) and again on every `Command::SelfTest`; `Command::SetGain` changes the amplifier gain
+// it runs at, e.g. when pads come back clipped.
+// `Command::NoiseScan` listens with every pad held low instead, for interference at each drive frequency (`schema::NoiseScanReport`).
+
+use calipertron::board;
+use calipertron::config_store::ConfigStore;
+use calipertron::dispatch::Dispatcher;
+use calipertron::measure::{noise_scan, AdcSampler, PdmEmitter};
+use calipertron::pga::Pga;
+use calipertron::usb_state::UsbState;
+use calipertron::watchdog::{self, Watchdog};
+use calipertron_core::gain::clipped;
+use calipertron_core::*;
+use schema::*;
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_stm32::flash::Flash;
+use embassy_stm32::time::Hertz;
+use embassy_stm32::{bind_interrupts, peripherals, usb};
+use embassy_time::Duration;
+use embassy_usb::Builder;
+
+// panics and hard faults are handled by `calipertron::crash`
+use defmt_rtt as _;
+
+include!(concat!(env!("OUT_DIR"), "/constants.rs"));
+const NUM_SAMPLES: usize = SINE_COSINE_TABLE.len();
+
+bind_interrupts!(struct Irqs {
+    USB_LP_CAN1_RX0 => usb::InterruptHandler<peripherals::USB>;
+});
+
+const MAX_PACKET_SIZE: u8 = 64;
+
+pub const USB_CLASS_CUSTOM: u8 = 0xFF;
+const USB_SUBCLASS_CUSTOM: u8 = 0x00;
+const USB_PROTOCOL_CUSTOM: u8 = 0x00;
+
+/// Pattern for the pads under test.
+static mut SIGNAL: [u32; PDM_WAVES.len()] = [0; PDM_WAVES.len()];
+
+/// Capture with only the waves in `mask` driven.
+async fn measure_pads(
+    emitter: &mut PdmEmitter<'_>,
+    sampler: &mut AdcSampler,
+    adc_buf: &mut [u16; NUM_SAMPLES],
+    mask: u8,
+) -> PadMeasurement {
+    // Only rewritten between captures, while the DMA isn't reading it, and the emitter's old reference is replaced straight away.
+    let signal = unsafe { &mut *core::ptr::addr_of_mut!(SIGNAL) };
+    board::masked_pdm_signal(mask, signal);
+    emitter.set_signal(signal);
+
+    measure::capture(emitter, sampler, adc_buf).await;
+    PadMeasurement {
+        amplitude: measure::amplitude(adc_buf, &SINE_COSINE_TABLE),
+        clipped: clipped(adc_buf),
+    }
+}
+
+async fn run_self_test(
+    emitter: &mut PdmEmitter<'_>,
+    sampler: &mut AdcSampler,
+    gain: Gain,
+) -> SelfTestReport {
+    let mut adc_buf = [0u16; NUM_SAMPLES];
+    let mut pads = [PadMeasurement {
+        amplitude: 0.,
+        clipped: false,
+    }; SELF_TEST_PADS];
+    let mut pairs = pads;
+    for i in 0..SELF_TEST_PADS {
+        let next = (i + 1) % SELF_TEST_PADS;
+        pads[i] = measure_pads(emitter, sampler, &mut adc_buf, 1 << i).await;
+        pairs[i] = measure_pads(emitter, sampler, &mut adc_buf, 1 << i | 1 << next).await;
+    }
+
+    emitter.set_signal(&board::PDM_SIGNAL);
+
+    let report = SelfTestReport::new(gain, &pads, &pairs);
+    for (i, (pad, pair)) in report.pads.iter().zip(&report.pairs).enumerate() {
+        info!(
+            "Pad {}: {} {:?}, with {}: {} {:?}",
+            i,
+            pad.amplitude,
+            pad.status,
+            (i + 1) % SELF_TEST_PADS,
+            pair.amplitude,
+            pair.status
+        );
+    }
+    if report.passed() {
+        info!("Self-test passed");
+    } else {
+        warn!("Self-test failed");
+    }
+    report
+}
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let mut p = embassy_stm32::init(board::config());
+
+    info!("Hello World!");
+
+    let reset_cause = watchdog::take_reset_cause();
+    info!("Reset cause: {:?}", reset_cause);
+    let crash_report = calipertron::crash::take_crash_report();
+
+    board::reset_usb(&mut p.PA12).await;
+    let pins = calipertron::board_pins!(p);
+
+    ////////////////////////
+    // Signal emission and ADC setup
+
+    let _drive = board::drive_outputs(pins.drive);
+
+    // TIM3 is clocked by TIM2 to trigger ADC conversions in step with the PDM table (`AdcTrigger::MEASURE`).
+    let hardware = calipertron::measure::setup(
+        p.TIM2,
+        p.TIM3,
+        p.ADC1,
+        pins.pickup,
+        Hertz(PDM_FREQUENCY),
+        &AdcSamplingPeriod::CYCLES41_5,
+    )
+    .await;
+
+    let mut emitter = PdmEmitter::new(
+        &hardware.pdm_timer,
+        &hardware.trigger_timer,
+        p.DMA1_CH2,
+        &board::PDM_SIGNAL,
+    );
+    let mut sampler = AdcSampler::new(p.DMA1_CH1);
+
+    ////////////////////////
+    // Amplifier
+
+    // Fixed gain rather than automatic, so every pad is measured the same way.
+    // Boards without the front-end amplifier always run (and report) X1.
+    let config = ConfigStore::new(Flash::new_blocking(p.FLASH))
+        .load()
+        .unwrap_or_default();
+    let mut pga = Pga::setup(pins.pga, config.gain);
+    let mut gain = match pga {
+        Some(_) => config.gain,
+        None => Gain::X1,
+    };
+
+    ////////////////////////
+    // USB Setup
+
+    let driver = embassy_stm32::usb::Driver::new(p.USB, Irqs, p.PA12, p.PA11);
+    let (vid, pid) = (0xc0de, 0xcafe);
+    let mut usb_config = embassy_usb::Config::new(vid, pid);
+    usb_config.max_packet_size_0 = MAX_PACKET_SIZE;
+    usb_config.product = Some("Calipertron");
+
+    let mut config_descriptor = [0; 256];
+    let mut bos_descriptor = [0; 256];
+    let mut control_buf = [0; 64];
+
+    let mut builder = Builder::new(
+        driver,
+        usb_config,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut [], // no msos descriptors
+        &mut control_buf,
+    );
+
+    // Lets the command loop notice the host going away, see `UsbState`.
+    let usb_state = UsbState::new();
+    let mut usb_handler = usb_state.handler();
+    builder.handler(&mut usb_handler);
+
+    // Same endpoints as `recorder`, so the host tools find the command interface where they expect it.
+    let mut func = builder.function(USB_CLASS_CUSTOM, USB_SUBCLASS_CUSTOM, USB_PROTOCOL_CUSTOM);
+    let mut iface = func.interface();
+    let mut iface_alt = iface.alt_setting(
+        USB_CLASS_CUSTOM,
+        USB_SUBCLASS_CUSTOM,
+        USB_PROTOCOL_CUSTOM,
+        None,
+    );
+    let read_ep = iface_alt.endpoint_bulk_out(MAX_PACKET_SIZE as u16);
+    // no sample data in this firmware, but it keeps the response endpoint at 0x82
+    let _data_ep = iface_alt.endpoint_bulk_in(MAX_PACKET_SIZE as u16);
+    let response_ep = iface_alt.endpoint_bulk_in(MAX_PACKET_SIZE as u16);
+    drop(func);
+
+    let mut usb = builder.build();
+
+    ////////////////////////
+    // Watchdog
+
+    const TASK_COMMANDS: usize = 0;
+    // The self-test is 16 captures of a few ms each, and the noise scan 128.
+    let watchdog = Watchdog::<1>::new(Duration::from_secs(5));
+
+    //////////////////////////
+    // handle commands from host
+
+    let mut dispatcher = Dispatcher::new(read_ep, response_ep);
+
+    let fut_commands = async {
+        watchdog.feed(TASK_COMMANDS);
+        run_self_test(&mut emitter, &mut sampler, gain).await;
+
+        loop {
+            watchdog.idle(TASK_COMMANDS);
+            usb_state.wait_connected().await;
+            let Some(command) = usb_state.or_disconnect(dispatcher.next_command()).await else {
+                continue;
+            };
+            watchdog.feed(TASK_COMMANDS);
+
+            let result = {
+                use Command::*;
+                match command {
+                    SelfTest => {
+                        let report = run_self_test(&mut emitter, &mut sampler, gain).await;
+                        let response = Response::SelfTest(report);
+                        watchdog.idle(TASK_COMMANDS);
+                        usb_state.or_disconnect(dispatcher.respond(response)).await;
+                        continue;
+                    }
+
+                    NoiseScan => {
+                        let mut adc_buf = [0u16; NUM_SAMPLES];
+                        let report = noise_scan(
+                            &mut emitter,
+                            &mut sampler,
+                            &mut adc_buf,
+                            &SINE_COSINE_TABLE,
+                            gain,
+                            Hertz(PDM_FREQUENCY),
+                        )
+                        .await;
+                        let response = Response::NoiseScan(report);
+                        watchdog.idle(TASK_COMMANDS);
+                        usb_state.or_disconnect(dispatcher.respond(response)).await;
+                        continue;
+                    }
+
+                    SetGain(_) if pga.is_none() => Err(CommandError::Unsupported),
+                    SetGain(new_gain) => {
+                        gain = new_gain;
+                        if let Some(pga) = &mut pga {
+                            if let Err(e) = pga.set_gain(gain) {
+                                error!("Failed to set gain: {:?}", e);
+                            }
+                        }
+                        Ok(())
+                    }
+
+                    GetDeviceInfo => {
+                        let info = DeviceInfo { reset_cause };
+                        let response = Response::DeviceInfo(info);
+                        watchdog.idle(TASK_COMMANDS);
+                        usb_state.or_disconnect(dispatcher.respond(response)).await;
+                        continue;
+                    }
+
+                    GetCrashReport => {
+                        let report = crash_report.clone();
+                        let response = Response::CrashReport(report);
+                        watchdog.idle(TASK_COMMANDS);
+                        usb_state.or_disconnect(dispatcher.respond(response)).await;
+                        continue;
+                    }
+
+                    // nothing to configure or save here; the drive frequency is fixed by build.rs to match the correlation table
+                    _ => Err(CommandError::Unsupported),
+                }
+            };
+
+            // the host reads the response whenever it gets to it, just like it sends commands
+            watchdog.idle(TASK_COMMANDS);
+            usb_state.or_disconnect(dispatcher.respond(result)).await;
+        }
+    };
+
+    embassy_futures::join::join3(usb.run(), fut_commands, watchdog.run(p.IWDG)).await;
+}
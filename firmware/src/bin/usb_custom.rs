@@ -1,72 +1,71 @@
 #![no_std]
 #![no_main]
+use calipertron::board;
+use calipertron::dispatch::Dispatcher;
+use calipertron::usb_state::UsbState;
+use calipertron::watchdog::{self, Watchdog};
 use schema::*;
 
+use core::cell::Cell;
 use defmt::*;
 use embassy_executor::Spawner;
 use embassy_stm32::adc::Adc;
 use embassy_stm32::gpio::{Flex, Level, Output, Speed};
 use embassy_stm32::time::Hertz;
-use embassy_stm32::{adc, bind_interrupts, interrupt, peripherals, usb, Config};
-use embassy_time::Timer;
-use embassy_usb::driver::{Endpoint, EndpointIn, EndpointOut};
+use embassy_stm32::{adc, bind_interrupts, interrupt, peripherals, usb};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant};
+use embassy_usb::driver::{EndpointError, EndpointIn};
 use embassy_usb::Builder;
 
-use {defmt_rtt as _, panic_probe as _};
+// panics and hard faults are handled by `calipertron::crash`
+use defmt_rtt as _;
 
 bind_interrupts!(struct Irqs {
     USB_LP_CAN1_RX0 => usb::InterruptHandler<peripherals::USB>;
 });
 
-const MAX_PACKET_SIZE: u8 = 64;
-const SAMPLES_PER_PACKET: usize = (MAX_PACKET_SIZE as usize) / 2; // 2 bytes per sample
+const MAX_PACKET_SIZE: u8 = DATA_PACKET_SIZE as u8;
+const SAMPLES_PER_PACKET: usize = SAMPLES_PER_DATA_PACKET;
+
+// Awaiting USB used to overrun the ADC DMA, so samples go through two buffers:
+// the DMA ring buffer soaks up latency in the sampling loop and the packet queue soaks up latency on the USB side.
+const RING_BUFFER_PACKETS: usize = 8;
+const PACKET_QUEUE_DEPTH: usize = 16;
+
+// Each re-read pauses sampling for one VREFINT conversion, which the host sees as a break in the stream.
+const VREFINT_INTERVAL: Duration = Duration::from_secs(10);
+
 pub const USB_CLASS_CUSTOM: u8 = 0xFF;
 const USB_SUBCLASS_CUSTOM: u8 = 0x00;
 const USB_PROTOCOL_CUSTOM: u8 = 0x00;
 
+enum StreamItem {
+    Samples([u16; SAMPLES_PER_PACKET]),
+    /// Samples were dropped just before this point.
+    Gap,
+    /// VREFINT reading for the samples after this point.
+    Scale(u16),
+}
+
 #[embassy_executor::main]
 async fn main(_spawner: Spawner) {
-    let mut config = Config::default();
-    {
-        use embassy_stm32::rcc::*;
-        config.rcc.hse = Some(Hse {
-            freq: Hertz(8_000_000),
-            mode: HseMode::Oscillator,
-        });
-        config.rcc.pll = Some(Pll {
-            src: PllSource::HSE,
-            prediv: PllPreDiv::DIV1,
-            mul: PllMul::MUL9,
-        });
-        config.rcc.sys = Sysclk::PLL1_P;
-        config.rcc.ahb_pre = AHBPrescaler::DIV1;
-        config.rcc.apb1_pre = APBPrescaler::DIV2;
-        config.rcc.apb2_pre = APBPrescaler::DIV1;
-    }
-    let mut p = embassy_stm32::init(config);
+    let mut p = embassy_stm32::init(board::config());
 
     info!("Hello World!");
 
-    {
-        // Board has a pull-up resistor on the D+ line; pull it down to send a RESET condition to the USB bus.
-        // This forced reset is needed only for development, without it host will not reset your device when you upload new firmware.
-        let _dp = Output::new(&mut p.PA12, Level::Low, Speed::Low);
-        Timer::after_millis(10).await;
-    }
+    let reset_cause = watchdog::take_reset_cause();
+    info!("Reset cause: {:?}", reset_cause);
+    let crash_report = calipertron::crash::take_crash_report();
+
+    board::reset_usb(&mut p.PA12).await;
+    let pins = calipertron::board_pins!(p);
 
     ////////////////////////
     // Signal emission setup
 
-    let _pins = [
-        Output::new(p.PA0, Level::Low, Speed::Low),
-        Output::new(p.PA1, Level::Low, Speed::Low),
-        Output::new(p.PA2, Level::Low, Speed::Low),
-        Output::new(p.PA3, Level::Low, Speed::Low),
-        Output::new(p.PA4, Level::Low, Speed::Low),
-        Output::new(p.PA5, Level::Low, Speed::Low),
-        Output::new(p.PA6, Level::Low, Speed::Low),
-        Output::new(p.PA7, Level::Low, Speed::Low),
-    ];
+    let _drive = board::drive_outputs(pins.drive);
 
     let tim = embassy_stm32::timer::low_level::Timer::new(p.TIM2);
     let timer_registers = tim.regs_gp16();
@@ -141,6 +140,11 @@ async fn main(_spawner: Spawner) {
         &mut control_buf,
     );
 
+    // Lets the tasks below notice the host going away, see `UsbState`.
+    let usb_state = UsbState::new();
+    let mut usb_handler = usb_state.handler();
+    builder.handler(&mut usb_handler);
+
     let mut func = builder.function(USB_CLASS_CUSTOM, USB_SUBCLASS_CUSTOM, USB_PROTOCOL_CUSTOM);
     let mut iface = func.interface();
 
@@ -150,8 +154,9 @@ async fn main(_spawner: Spawner) {
         USB_PROTOCOL_CUSTOM,
         None,
     );
-    let mut read_ep = iface_alt.endpoint_bulk_out(MAX_PACKET_SIZE as u16);
+    let read_ep = iface_alt.endpoint_bulk_out(MAX_PACKET_SIZE as u16);
     let mut write_ep = iface_alt.endpoint_bulk_in(MAX_PACKET_SIZE as u16);
+    let response_ep = iface_alt.endpoint_bulk_in(MAX_PACKET_SIZE as u16);
     drop(func);
 
     let mut usb = builder.build();
@@ -161,7 +166,7 @@ async fn main(_spawner: Spawner) {
     ////////////////////////
     // ADC + DMA setup
 
-    let mut adc_buffer = [0; 2 * SAMPLES_PER_PACKET];
+    let mut adc_buffer = [0; RING_BUFFER_PACKETS * SAMPLES_PER_PACKET];
     let request = embassy_stm32::adc::RxDma::request(&p.DMA1_CH1);
     let mut opts = TransferOptions::default();
     opts.half_transfer_ir = true;
@@ -175,19 +180,11 @@ async fn main(_spawner: Spawner) {
         )
     };
 
-    let mut adc = Adc::new(p.ADC1);
-
-    let vrefint_sample = {
-        let mut vrefint = adc.enable_vref();
-
-        // give vref some time to warm up
-        Timer::after_millis(100).await;
-
-        adc.read(&mut vrefint).await as u32
-    };
-    info!("VREFINT: {}", vrefint_sample);
-
-    let convert_to_millivolts = |sample| (sample as u32 * adc::VREF_INT / vrefint_sample) as u16;
+    let _adc = Adc::new(p.ADC1);
+    calipertron::adc::setup().await;
+    // read before conversions start, so it can't disturb the stream
+    let initial_vrefint = calipertron::adc::read_vrefint();
+    info!("VREFINT: {}", initial_vrefint);
 
     // Configure ADC for continuous conversion with DMA
     let adc = embassy_stm32::pac::ADC1;
@@ -206,14 +203,14 @@ async fn main(_spawner: Spawner) {
     adc.sqr1().modify(|w| w.set_l(0)); // one conversion.
 
     // TODO: this may not be necessary
-    let mut pb1 = Flex::new(p.PB1);
-    pb1.set_as_analog();
+    let mut pickup = Flex::new(pins.pickup);
+    pickup.set_as_analog();
 
-    const PIN_CHANNEL: u8 = 9; // PB1 is on channel 9 for STM32F103
-    adc.sqr3().modify(|w| w.set_sq(0, PIN_CHANNEL));
+    adc.sqr3()
+        .modify(|w| w.set_sq(0, board::PICKUP_ADC_CHANNEL));
     adc.smpr2().modify(|w| {
         w.set_smp(
-            PIN_CHANNEL as usize,
+            board::PICKUP_ADC_CHANNEL as usize,
             adc::SampleTime::CYCLES239_5,
             //adc::SampleTime::CYCLES71_5,
         )
@@ -222,74 +219,195 @@ async fn main(_spawner: Spawner) {
     // Start ADC conversions
     adc.cr2().modify(|w| w.set_adon(true));
 
+    ////////////////////////
+    // Watchdog
+
+    // The ADC ring buffer fills every few ms, and host tools read each response straight after sending a command.
+    // Waiting on the host to read the stream isn't watched, since nobody may be listening.
+    const TASK_SAMPLE_ADC: usize = 0;
+    const TASK_COMMANDS: usize = 1;
+    let watchdog = Watchdog::<2>::new(Duration::from_secs(5));
+    let fut_watchdog = watchdog.run(p.IWDG);
+
     ////////////////////////
     // Stream ADC data to host
 
-    let fut_stream_adc = async {
-        // Wait for USB to connect
-        write_ep.wait_enabled().await;
+    let packets: Channel<NoopRawMutex, StreamItem, PACKET_QUEUE_DEPTH> = Channel::new();
+
+    // Number of times samples have been dropped, reported to the host in every gap marker.
+    let overruns = Cell::new(0u32);
 
+    // Keep sampling whether or not the host is keeping up; anything that can't be queued is dropped and replaced by a gap marker.
+    let fut_sample_adc = async {
         // Start handling DMA requests from ADC
         adc_rb.start();
 
         let mut buf = [0; SAMPLES_PER_PACKET];
+        let mut gap_pending = false;
+        let mut scale_pending = Some(initial_vrefint);
+        let mut last_vrefint = Instant::now();
+
         loop {
-            loop {
-                let r = adc_rb.read_exact(&mut buf).await;
+            watchdog.feed(TASK_SAMPLE_ADC);
+            if let Err(e) = adc_rb.read_exact(&mut buf).await {
+                warn!("ADC overrun: {:?}", e);
+                overruns.set(overruns.get().wrapping_add(1));
+                gap_pending = true;
+                adc_rb.clear();
+                continue;
+            }
 
-                if r.is_err() {
-                    error!("ADC_RB error: {:?}", r);
-                    break;
+            if gap_pending {
+                if packets.try_send(StreamItem::Gap).is_err() {
+                    // still no room, so these samples are lost too
+                    continue;
                 }
+                gap_pending = false;
+            }
 
-                for x in buf.iter_mut() {
-                    *x = convert_to_millivolts(*x);
+            if let Some(vrefint) = scale_pending {
+                if packets.try_send(StreamItem::Scale(vrefint)).is_err() {
+                    overruns.set(overruns.get().wrapping_add(1));
+                    gap_pending = true;
+                    continue;
                 }
+                scale_pending = None;
+            }
 
-                let r = write_ep.write(bytemuck::cast_slice(&buf)).await;
-                if r.is_err() {
-                    error!("USB Error: {:?}", r);
-                    break;
+            if packets.try_send(StreamItem::Samples(buf)).is_err() {
+                overruns.set(overruns.get().wrapping_add(1));
+                gap_pending = true;
+            }
+
+            // follow supply drift; everything in `buf` was converted before this
+            if last_vrefint.elapsed() >= VREFINT_INTERVAL {
+                let vrefint = calipertron::adc::read_vrefint();
+                debug!("VREFINT: {}", vrefint);
+                scale_pending = Some(vrefint);
+                last_vrefint = Instant::now();
+            }
+        }
+    };
+
+    let fut_stream_adc = async {
+        usb_state.wait_connected().await;
+
+        // The stream only starts a new capture when the device restarts; gaps within it are marked explicitly.
+        let capture_id: u16 = 1;
+        let mut sequence: u16 = 0;
+        let mut gap_pending = false;
+
+        loop {
+            let item = packets.receive().await;
+
+            if gap_pending || matches!(item, StreamItem::Gap) {
+                let mut packet = [0u8; DATA_PACKET_SIZE];
+                let packet =
+                    DataPacket::encode_gap(capture_id, sequence, overruns.get(), &mut packet);
+                if let Err(e) = usb_state
+                    .or_disconnect(write_ep.write(packet))
+                    .await
+                    .unwrap_or(Err(EndpointError::Disabled))
+                {
+                    error!("USB Error: {:?}", e);
+                    gap_pending = true;
+                    usb_state.wait_connected().await;
+                    continue;
                 }
+                sequence = sequence.wrapping_add(1);
+                gap_pending = false;
             }
 
-            adc_rb.clear();
+            if let StreamItem::Scale(vrefint) = item {
+                let mut packet = [0u8; DATA_PACKET_SIZE];
+                let packet = DataPacket::encode_scale(capture_id, sequence, vrefint, &mut packet);
+                if let Err(e) = usb_state
+                    .or_disconnect(write_ep.write(packet))
+                    .await
+                    .unwrap_or(Err(EndpointError::Disabled))
+                {
+                    error!("USB Error: {:?}", e);
+                    overruns.set(overruns.get().wrapping_add(1));
+                    gap_pending = true;
+                    usb_state.wait_connected().await;
+                    continue;
+                }
+                sequence = sequence.wrapping_add(1);
+            }
+
+            if let StreamItem::Samples(samples) = item {
+                let mut packet = [0u8; DATA_PACKET_SIZE];
+                let packet = DataPacket::encode(capture_id, sequence, &samples, &mut packet);
+                if let Err(e) = usb_state
+                    .or_disconnect(write_ep.write(packet))
+                    .await
+                    .unwrap_or(Err(EndpointError::Disabled))
+                {
+                    error!("USB Error: {:?}", e);
+                    // these samples never made it, so whatever comes next doesn't follow on
+                    overruns.set(overruns.get().wrapping_add(1));
+                    gap_pending = true;
+                    usb_state.wait_connected().await;
+                    continue;
+                }
+                sequence = sequence.wrapping_add(1);
+            }
         }
     };
 
     //////////////////////////
     // handle commands from host
-    let fut_commands = async {
-        // Wait for USB to connect
-        read_ep.wait_enabled().await;
 
+    let mut dispatcher = Dispatcher::new(read_ep, response_ep);
+
+    let fut_commands = async {
         loop {
-            let mut command_buf = [0u8; MAX_PACKET_SIZE as usize];
-
-            match read_ep.read(&mut command_buf).await {
-                Ok(size) => {
-                    if let Some(command) = Command::deserialize(&command_buf[..size]) {
-                        info!("Received command: {:?}", command);
-                        match command {
-                            Command::SetFrequency { frequency_kHz } => {
-                                tim.stop();
-                                tim.reset();
-
-                                tim.set_frequency(Hertz((frequency_kHz * 1000.) as u32));
-                                tim.start();
-                            }
-                            x => {
-                                defmt::todo!("Can't handle: {}", x)
-                            }
-                        }
-                    } else {
-                        error!("Failed to deserialize command");
-                    }
+            watchdog.idle(TASK_COMMANDS);
+            usb_state.wait_connected().await;
+            let Some(command) = usb_state.or_disconnect(dispatcher.next_command()).await else {
+                continue;
+            };
+            watchdog.feed(TASK_COMMANDS);
+
+            let result = match command {
+                Command::SetFrequency {
+                    frequency_kHz,
+                    adc_sampling_period,
+                } => {
+                    tim.stop();
+                    tim.reset();
+
+                    tim.set_frequency(Hertz((frequency_kHz * 1000.) as u32));
+                    adc.smpr2().modify(|w| {
+                        w.set_smp(
+                            board::PICKUP_ADC_CHANNEL as usize,
+                            calipertron::adc::sample_time(&adc_sampling_period),
+                        )
+                    });
+                    tim.start();
+                    Ok(())
+                }
+                Command::GetDeviceInfo => {
+                    let info = DeviceInfo { reset_cause };
+                    let response = Response::DeviceInfo(info);
+                    watchdog.idle(TASK_COMMANDS);
+                    usb_state.or_disconnect(dispatcher.respond(response)).await;
+                    continue;
                 }
-                Err(e) => {
-                    error!("Failed to read USB packet: {:?}", e);
+                Command::GetCrashReport => {
+                    let report = crash_report.clone();
+                    let response = Response::CrashReport(report);
+                    watchdog.idle(TASK_COMMANDS);
+                    usb_state.or_disconnect(dispatcher.respond(response)).await;
+                    continue;
                 }
+                // This firmware streams continuously and has no flash settings.
+                _ => Err(CommandError::Unsupported),
             };
+
+            // the host reads the response whenever it gets to it, just like it sends commands
+            watchdog.idle(TASK_COMMANDS);
+            usb_state.or_disconnect(dispatcher.respond(result)).await;
         }
     };
 
@@ -298,10 +416,17 @@ async fn main(_spawner: Spawner) {
 
     let fut_commands = core::pin::pin!(fut_commands);
     let fut_usb = core::pin::pin!(fut_usb);
+    let fut_sample_adc = core::pin::pin!(fut_sample_adc);
     let fut_stream_adc = core::pin::pin!(fut_stream_adc);
-
-    let futures: [core::pin::Pin<&mut dyn core::future::Future<Output = _>>; 3] =
-        [fut_commands, fut_usb, fut_stream_adc];
+    let fut_watchdog = core::pin::pin!(fut_watchdog);
+
+    let futures: [core::pin::Pin<&mut dyn core::future::Future<Output = _>>; 5] = [
+        fut_commands,
+        fut_usb,
+        fut_sample_adc,
+        fut_stream_adc,
+        fut_watchdog,
+    ];
     embassy_futures::join::join_array(futures).await;
 }
 
@@ -1,63 +1,123 @@
 #![no_std]
 #![no_main]
 
+// Text console over USB CDC-ACM: measures like `local`, prints positions and takes commands typed in a serial terminal.
+// See `schema::CONSOLE_HELP` for the commands.
+
+use calipertron::board;
+use calipertron::config_store::ConfigStore;
+use calipertron::measure::{AdcSampler, PdmEmitter};
+use calipertron::pga::Pga;
+use calipertron::usb_state::UsbState;
+use calipertron::watchdog::{self, Watchdog};
+use calipertron_core::gain::AutoGain;
+use calipertron_core::readout::Readout;
+use calipertron_core::*;
+use schema::*;
+
+use core::cell::{Cell, RefCell};
+use core::fmt::Write;
 use defmt::{panic, *};
 use embassy_executor::Spawner;
-use embassy_futures::join::join;
-use embassy_stm32::gpio::{Level, Output, Speed};
+use embassy_futures::select::{select, Either};
+use embassy_stm32::flash::Flash;
 use embassy_stm32::time::Hertz;
 use embassy_stm32::usb::{Driver, Instance};
-use embassy_stm32::{bind_interrupts, peripherals, usb, Config};
-use embassy_time::Timer;
-use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
+use embassy_stm32::{bind_interrupts, peripherals, usb};
+use embassy_time::{Duration, Timer};
+use embassy_usb::class::cdc_acm::{CdcAcmClass, ControlChanged, Receiver, Sender, State};
 use embassy_usb::driver::EndpointError;
 use embassy_usb::Builder;
-use {defmt_rtt as _, panic_probe as _};
+// panics and hard faults are handled by `calipertron::crash`
+use defmt_rtt as _;
+
+include!(concat!(env!("OUT_DIR"), "/constants.rs"));
+const NUM_SAMPLES: usize = SINE_COSINE_TABLE.len();
 
 bind_interrupts!(struct Irqs {
     USB_LP_CAN1_RX0 => usb::InterruptHandler<peripherals::USB>;
 });
 
-bind_interrupts!(struct AdcIrqs {
-    ADC1_2 => adc::InterruptHandler<ADC1>;
-});
-
 const MAX_PACKET_SIZE: u8 = 64;
 
+// Fast enough to follow the slider by hand, slow enough to read.
+const STREAM_INTERVAL: Duration = Duration::from_millis(100);
+
+// Samples per line of `record` output.
+const RECORD_SAMPLES_PER_LINE: usize = 16;
+
 #[embassy_executor::main]
 async fn main(_spawner: Spawner) {
-    let mut config = Config::default();
-    {
-        use embassy_stm32::rcc::*;
-        config.rcc.hse = Some(Hse {
-            freq: Hertz(8_000_000),
-            mode: HseMode::Oscillator,
-        });
-        config.rcc.pll = Some(Pll {
-            src: PllSource::HSE,
-            prediv: PllPreDiv::DIV1,
-            mul: PllMul::MUL9,
-        });
-        config.rcc.sys = Sysclk::PLL1_P;
-        config.rcc.ahb_pre = AHBPrescaler::DIV1;
-        config.rcc.apb1_pre = APBPrescaler::DIV2;
-        config.rcc.apb2_pre = APBPrescaler::DIV1;
-    }
-    let mut p = embassy_stm32::init(config);
+    let mut p = embassy_stm32::init(board::config());
 
     info!("Hello World!");
+    info!("Reset cause: {:?}", watchdog::take_reset_cause());
+    // logged if there was one; the console doesn't report it
+    let _ = calipertron::crash::take_crash_report();
+
+    board::reset_usb(&mut p.PA12).await;
+    let pins = calipertron::board_pins!(p);
 
-    {
-        // Board has a pull-up resistor on the D+ line; pull it down to send a RESET condition to the USB bus.
-        // This forced reset is needed only for development, without it host will not reset your device when you upload new firmware.
-        let _dp = Output::new(&mut p.PA12, Level::Low, Speed::Low);
-        Timer::after_millis(10).await;
+    ////////////////////////
+    // Signal emission and ADC setup
+
+    let _drive = board::drive_outputs(pins.drive);
+
+    // TIM3 is clocked by TIM2 to trigger ADC conversions in step with the PDM table (`AdcTrigger::MEASURE`).
+    // Samples stay phase locked to the drive signal at any PDM frequency, so the build.rs table works for `freq` too.
+    let adc_sampling_period = AdcSamplingPeriod::CYCLES41_5;
+    let hardware = calipertron::measure::setup(
+        p.TIM2,
+        p.TIM3,
+        p.ADC1,
+        pins.pickup,
+        Hertz(PDM_FREQUENCY),
+        &adc_sampling_period,
+    )
+    .await;
+
+    let mut emitter = PdmEmitter::new(
+        &hardware.pdm_timer,
+        &hardware.trigger_timer,
+        p.DMA1_CH2,
+        &board::PDM_SIGNAL,
+    );
+    let mut sampler = AdcSampler::new(p.DMA1_CH1);
+
+    // Only PDM frequencies the ADC can keep up with at the fixed decimation.
+    #[allow(non_snake_case)]
+    let set_frequency = |frequency_kHz: f64| {
+        AdcTrigger::MEASURE.sample_rate_Hz(frequency_kHz, &adc_sampling_period)?;
+        hardware
+            .pdm_timer
+            .set_frequency(Hertz((frequency_kHz * 1000.) as u32));
+        Some(())
+    };
+
+    ////////////////////////
+    // Persisted settings
+
+    let mut config_store = ConfigStore::new(Flash::new_blocking(p.FLASH));
+    let config = RefCell::new(config_store.load().unwrap_or_default());
+    info!("Config: {:?}", *config.borrow());
+    if set_frequency(config.borrow().frequency_kHz).is_none() {
+        warn!("Saved frequency is too fast for the ADC, using the default");
+        hardware.pdm_timer.set_frequency(Hertz(PDM_FREQUENCY));
+        config.borrow_mut().frequency_kHz = PDM_FREQUENCY as f64 / 1000.;
     }
 
+    // Boards without the front-end amplifier just skip gain control.
+    let mut auto_gain = AutoGain::new(config.borrow().gain);
+    let mut pga = Pga::setup(pins.pga, auto_gain.gain);
+
+    ////////////////////////
+    // USB Setup
+
     let driver = Driver::new(p.USB, Irqs, p.PA12, p.PA11);
     let (vid, pid) = (0xc0de, 0xcafe);
-    let mut config = embassy_usb::Config::new(vid, pid);
-    config.max_packet_size_0 = MAX_PACKET_SIZE;
+    let mut usb_config = embassy_usb::Config::new(vid, pid);
+    usb_config.max_packet_size_0 = MAX_PACKET_SIZE;
+    usb_config.product = Some("Calipertron");
 
     // Create embassy-usb DeviceBuilder using the driver and config.
     // It needs some buffers for building the descriptors.
@@ -69,33 +129,287 @@ async fn main(_spawner: Spawner) {
 
     let mut builder = Builder::new(
         driver,
-        config,
+        usb_config,
         &mut config_descriptor,
         &mut bos_descriptor,
         &mut [], // no msos descriptors
         &mut control_buf,
     );
 
-    let mut class = CdcAcmClass::new(&mut builder, &mut state, MAX_PACKET_SIZE as u16);
+    // Lets the console notice the host going away, see `UsbState`.
+    let usb_state = UsbState::new();
+    let mut usb_handler = usb_state.handler();
+    builder.handler(&mut usb_handler);
+
+    let (sender, receiver, control) =
+        CdcAcmClass::new(&mut builder, &mut state, MAX_PACKET_SIZE as u16).split_with_control();
+    let mut console = Console {
+        sender,
+        receiver,
+        control,
+    };
     let mut usb = builder.build();
-    let usb_fut = usb.run();
 
-    let mut adc = Adc::new(p.ADC1);
-    let mut pin = p.PB1;
+    ////////////////////////
+    // Watchdog
+
+    const TASK_MEASURE: usize = 0;
+    const TASK_CONSOLE: usize = 1;
+    // A capture takes a few ms and a console command at most a flash write, so anything taking this long means DMA or flash is wedged.
+    // Talking to the host isn't watched: a terminal reads and types whenever it likes, and closing it ends the session.
+    let watchdog = Watchdog::<2>::new(Duration::from_secs(5));
+
+    ////////////////////////
+    // Measurement
+
+    // Position before the zero offset, so `zero` can take it as the new offset.
+    let raw_position_mm = Cell::new(0f32);
+    // Display mode over the zeroed position, for what gets printed.
+    let readout = RefCell::new(Readout::new(0.));
+    let last_capture = RefCell::new([0u16; NUM_SAMPLES]);
+
+    let fut_measure = async {
+        let mut phase_accumulator = PhaseAccumulator::new(0.0, 0.1);
+        let mut adc_buf = [0u16; NUM_SAMPLES];
+        loop {
+            watchdog.feed(TASK_MEASURE);
+            measure::capture(&mut emitter, &mut sampler, &mut adc_buf).await;
+
+            let (calibration, scale_pitch_mm, zero_offset_mm) = {
+                let config = config.borrow();
+                (
+                    config.calibration,
+                    config.scale_pitch_mm,
+                    config.zero_offset_mm,
+                )
+            };
+            let phase = correct_phase(measure::phase(&adc_buf, &SINE_COSINE_TABLE), &calibration);
+
+            // phase doesn't depend on amplitude, so the gain can change between captures
+            if let Some(pga) = &mut pga {
+                if let Some(gain) = auto_gain.update(&adc_buf) {
+                    info!("Gain: {}", gain);
+                    if let Err(e) = pga.set_gain(gain) {
+                        error!("Failed to set gain: {:?}", e);
+                    }
+                }
+            }
+
+            phase_accumulator.update(phase);
+            raw_position_mm.set(
+                phase_accumulator.unwrapped_phase
+                    * (scale_pitch_mm / (2.0 * core::f32::consts::PI)),
+            );
+            readout
+                .borrow_mut()
+                .update(raw_position_mm.get() - zero_offset_mm);
+            last_capture.borrow_mut().copy_from_slice(&adc_buf);
+        }
+    };
+
+    ////////////////////////
+    // Console
+
+    let position_line = || {
+        let reading = readout.borrow().reading();
+        format_position(
+            reading.value_mm,
+            config.borrow().units,
+            reading.mode.into(),
+            reading.min_max_mm,
+        )
+    };
+
+    // Settings commands; `help` and `record` print their output from the console loop instead.
+    let mut run_command = |command: ConsoleCommand, streaming: &mut bool| match command {
+        ConsoleCommand::Help | ConsoleCommand::Record => Ok(()),
 
-    let fut = async {
+        ConsoleCommand::Zero => {
+            let mut config = config.borrow_mut();
+            config.zero_offset_mm = raw_position_mm.get();
+            Ok(())
+        }
+
+        ConsoleCommand::Units(units) => {
+            config.borrow_mut().units = units;
+            Ok(())
+        }
+
+        ConsoleCommand::Mode(mode) => {
+            readout.borrow_mut().set_mode(mode.into());
+            Ok(())
+        }
+
+        ConsoleCommand::Frequency { frequency_kHz } => match set_frequency(frequency_kHz) {
+            Some(()) => {
+                config.borrow_mut().frequency_kHz = frequency_kHz;
+                Ok(())
+            }
+            None => Err("frequency too fast for the ADC"),
+        },
+
+        ConsoleCommand::Stream(on) => {
+            *streaming = on;
+            Ok(())
+        }
+
+        ConsoleCommand::Save => config_store.save(&config.borrow()).map_err(|e| {
+            error!("Failed to save config: {:?}", e);
+            "saving failed"
+        }),
+    };
+
+    let fut_console = async {
         loop {
-            class.wait_connection().await;
-            info!("Connected");
-            //let _ = echo(&mut class).await;
-            let _ = stream_adc(&mut class, &mut adc, &mut pin).await;
-            info!("Disconnected");
+            watchdog.idle(TASK_CONSOLE);
+            usb_state.wait_connected().await;
+            // the terminal opening the port sets DTR
+            let Some(()) = usb_state
+                .or_disconnect(console.receiver.wait_connection())
+                .await
+            else {
+                continue;
+            };
+            info!("Terminal connected");
+
+            let session = async {
+                let mut line = LineBuffer::<32>::new();
+                let mut streaming = true;
+                write_text(
+                    &mut console,
+                    b"Calipertron console, type `help` for commands\r\n",
+                )
+                .await?;
+
+                loop {
+                    let mut buf = [0u8; MAX_PACKET_SIZE as usize];
+                    let input = if streaming {
+                        match select(console.read_packet(&mut buf), Timer::after(STREAM_INTERVAL))
+                            .await
+                        {
+                            Either::First(n) => Some(n?),
+                            Either::Second(()) => None,
+                        }
+                    } else {
+                        Some(console.read_packet(&mut buf).await?)
+                    };
+
+                    let Some(n) = input else {
+                        // clear the line being typed, print the position, then put the line back
+                        let mut out = heapless::Vec::<u8, 96>::new();
+                        let _ = out.extend_from_slice(b"\r\x1b[K");
+                        let _ = out.extend_from_slice(position_line().as_bytes());
+                        let _ = out.extend_from_slice(line.line().as_bytes());
+                        write_text(&mut console, &out).await?;
+                        continue;
+                    };
+
+                    let mut echo = heapless::Vec::<u8, { 3 * MAX_PACKET_SIZE as usize }>::new();
+                    for &byte in &buf[..n] {
+                        match line.push(byte) {
+                            LineEvent::Echo(b) => {
+                                let _ = echo.push(b);
+                            }
+                            LineEvent::Erase => {
+                                let _ = echo.extend_from_slice(ERASE);
+                            }
+                            LineEvent::Ignored => {}
+                            LineEvent::Done => {
+                                let _ = echo.extend_from_slice(b"\r\n");
+                                write_text(&mut console, &echo).await?;
+                                echo.clear();
+
+                                let command = parse_console_line(line.line());
+                                line.clear();
+                                let result = match command {
+                                    Ok(None) => continue,
+                                    Ok(Some(ConsoleCommand::Help)) => {
+                                        write_text(&mut console, CONSOLE_HELP.as_bytes()).await?;
+                                        continue;
+                                    }
+                                    Ok(Some(ConsoleCommand::Record)) => {
+                                        let samples = *last_capture.borrow();
+                                        write_samples(&mut console, &samples).await?;
+                                        continue;
+                                    }
+                                    Ok(Some(command)) => {
+                                        info!("Console command: {:?}", command);
+                                        watchdog.feed(TASK_CONSOLE);
+                                        let result = run_command(command, &mut streaming);
+                                        watchdog.idle(TASK_CONSOLE);
+                                        result
+                                    }
+                                    Err(e) => Err(e.message()),
+                                };
+
+                                let mut reply = heapless::String::<64>::new();
+                                let _ = match result {
+                                    Ok(()) => reply.push_str("ok\r\n"),
+                                    Err(message) => write!(reply, "error: {}\r\n", message),
+                                };
+                                write_text(&mut console, reply.as_bytes()).await?;
+                            }
+                        }
+                    }
+                    write_text(&mut console, &echo).await?;
+                }
+            };
+
+            // a suspended bus blocks writes rather than failing them, see `UsbState`
+            let _: Option<Result<(), Disconnected>> = usb_state.or_disconnect(session).await;
+            info!("Terminal disconnected");
         }
     };
 
     // Run everything concurrently.
     // If we had made everything `'static` above instead, we could do this using separate tasks instead.
-    join(usb_fut, fut).await;
+    embassy_futures::join::join4(usb.run(), fut_measure, fut_console, watchdog.run(p.IWDG)).await;
+}
+
+/// The CDC-ACM class split up, so reads and writes can give up when the terminal closes the port.
+struct Console<'d, T: Instance + 'd> {
+    sender: Sender<'d, Driver<'d, T>>,
+    receiver: Receiver<'d, Driver<'d, T>>,
+    control: ControlChanged<'d>,
+}
+
+impl<'d, T: Instance + 'd> Console<'d, T> {
+    async fn read_packet(&mut self, buf: &mut [u8]) -> Result<usize, Disconnected> {
+        let Console {
+            sender,
+            receiver,
+            control,
+        } = self;
+        match select(receiver.read_packet(buf), hangup(control, || sender.dtr())).await {
+            Either::First(n) => Ok(n?),
+            Either::Second(()) => Err(Disconnected {}),
+        }
+    }
+
+    // A closed terminal stops reading but the host keeps the port open, so without this a write would wait forever.
+    async fn write_packet(&mut self, packet: &[u8]) -> Result<(), Disconnected> {
+        let Console {
+            sender,
+            receiver,
+            control,
+        } = self;
+        match select(
+            sender.write_packet(packet),
+            hangup(control, || receiver.dtr()),
+        )
+        .await
+        {
+            Either::First(result) => Ok(result?),
+            Either::Second(()) => Err(Disconnected {}),
+        }
+    }
+}
+
+/// Waits for the terminal to close the port, which drops DTR.
+async fn hangup(control: &ControlChanged<'_>, dtr: impl Fn() -> bool) {
+    while dtr() {
+        control.control_changed().await;
+    }
 }
 
 struct Disconnected {}
@@ -109,44 +423,35 @@ impl From<EndpointError> for Disconnected {
     }
 }
 
-async fn echo<'d, T: Instance + 'd>(
-    class: &mut CdcAcmClass<'d, Driver<'d, T>>,
+async fn write_text<'d, T: Instance + 'd>(
+    console: &mut Console<'d, T>,
+    text: &[u8],
 ) -> Result<(), Disconnected> {
-    let mut buf = [0; MAX_PACKET_SIZE as usize];
-    loop {
-        let n = class.read_packet(&mut buf).await?;
-        let data = &buf[..n];
-        info!("data: {:x}", data);
-        class.write_packet(data).await?;
+    for packet in text.chunks(MAX_PACKET_SIZE as usize) {
+        console.write_packet(packet).await?;
+    }
+    // Hosts hold on to a full packet until a short one ends the transfer (the macOS buffering this firmware used to suffer from),
+    // so follow one with an empty packet.
+    if !text.is_empty() && text.len() % MAX_PACKET_SIZE as usize == 0 {
+        console.write_packet(&[]).await?;
     }
+    Ok(())
 }
 
-use embassy_stm32::adc;
-use embassy_stm32::adc::Adc;
-use embassy_stm32::peripherals::ADC1;
-
-async fn stream_adc<'d, T: Instance + 'd>(
-    class: &mut CdcAcmClass<'d, Driver<'d, T>>,
-    adc: &mut Adc<'d, ADC1>,
-    pin: &mut impl embassy_stm32::adc::AdcChannel<ADC1>,
+/// Raw ADC samples as comma separated lines, for pasting into a spreadsheet.
+async fn write_samples<'d, T: Instance + 'd>(
+    console: &mut Console<'d, T>,
+    samples: &[u16],
 ) -> Result<(), Disconnected> {
-    let mut vrefint = adc.enable_vref();
-    let vrefint_sample = adc.read(&mut vrefint).await;
-    let convert_to_millivolts = |sample| {
-        const VREFINT_MV: u32 = 1200;
-        (u32::from(sample) * VREFINT_MV / u32::from(vrefint_sample)) as u16
-    };
-
-    let mut buf = [0u8; MAX_PACKET_SIZE as usize];
-    let samples_per_packet = (MAX_PACKET_SIZE as usize) / 2; // 2 bytes per sample
-
-    loop {
-        for i in 0..samples_per_packet {
-            let v = adc.read(pin).await;
-            let mv = convert_to_millivolts(v);
-            buf[i * 2] = (mv >> 8) as u8;
-            buf[i * 2 + 1] = mv as u8;
+    for chunk in samples.chunks(RECORD_SAMPLES_PER_LINE) {
+        // 4 digits and a comma per sample
+        let mut line = heapless::String::<{ 5 * RECORD_SAMPLES_PER_LINE + 2 }>::new();
+        for (i, sample) in chunk.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            let _ = write!(line, "{}{}", separator, sample);
         }
-        class.write_packet(&buf).await?;
+        let _ = line.push_str("\r\n");
+        write_text(console, line.as_bytes()).await?;
     }
+    Ok(())
 }
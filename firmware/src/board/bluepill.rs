@@ -0,0 +1,37 @@
+//! Plain bluepill dev board, with the pickup jumpered to PB1 and emitter wires on PA0--PA7 in order.
+//! There's no amplifier or user button.
+
+use embassy_stm32::peripherals;
+
+pub const USB_DP_PULLUP: bool = true;
+
+/// PB1 is on channel 9 for STM32F103
+pub const PICKUP_ADC_CHANNEL: u8 = 9;
+pub type PickupPin = peripherals::PB1;
+
+/// No button, so Stop mode only ends with a motion probe.
+pub const WAKE_BUTTON: Option<crate::power::WakePin> = None;
+
+pub const DRIVE_PINS: [u8; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+
+#[macro_export]
+macro_rules! board_pins {
+    ($p:ident) => {{
+        use embassy_stm32::gpio::Pin;
+        $crate::board::Pins {
+            drive: [
+                $p.PA0.degrade(),
+                $p.PA1.degrade(),
+                $p.PA2.degrade(),
+                $p.PA3.degrade(),
+                $p.PA4.degrade(),
+                $p.PA5.degrade(),
+                $p.PA6.degrade(),
+                $p.PA7.degrade(),
+            ],
+            pickup: $p.PB1,
+            button: None,
+            pga: None,
+        }
+    }};
+}
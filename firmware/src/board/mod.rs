@@ -0,0 +1,129 @@
+//! Clocks, pin maps and emitter wiring for each supported board.
+//!
+//! Select one with a cargo feature (`board-v1_1` is the default). A board module provides:
+//! - `USB_DP_PULLUP`, whether D+ has a fixed pull-up that needs `reset_usb`
+//! - `PICKUP_ADC_CHANNEL` and the `PickupPin` type
+//! - `DRIVE_PINS`, the GPIOA pin driving each of the 8 emitter waves
+//! - `WAKE_BUTTON`, the button's port and pin if it has one, for waking from Stop mode
+//! - a `board_pins!` macro that takes its pins out of `embassy_stm32::Peripherals` as a `Pins`
+
+use embassy_stm32::gpio::{AnyPin, Level, Output, Speed};
+use embassy_stm32::time::Hertz;
+use embassy_stm32::{peripherals, Config};
+
+#[cfg(feature = "board-v1_1")]
+mod v1_1;
+#[cfg(feature = "board-v1_1")]
+pub use v1_1::*;
+
+#[cfg(feature = "bluepill")]
+mod bluepill;
+#[cfg(feature = "bluepill")]
+pub use bluepill::*;
+
+#[cfg(not(any(feature = "board-v1_1", feature = "bluepill")))]
+compile_error!("Select a board with a cargo feature, e.g. `--features board-v1_1`");
+
+#[cfg(all(feature = "board-v1_1", feature = "bluepill"))]
+compile_error!("Only one board feature can be enabled; use `--no-default-features` to pick a board other than v1.1");
+
+#[allow(dead_code)]
+mod constants {
+    include!(concat!(env!("OUT_DIR"), "/constants.rs"));
+}
+
+pub struct Pins {
+    /// PA0--PA7 in order; the PDM DMA writes GPIOA's BSRR directly, so every board has to drive the emitter from these.
+    pub drive: [AnyPin; 8],
+    pub pickup: PickupPin,
+    pub button: Option<AnyPin>,
+    pub pga: Option<PgaPins>,
+}
+
+/// MCP6S21 amplifier wiring.
+pub struct PgaPins {
+    pub spi: peripherals::SPI2,
+    pub sck: peripherals::PB13,
+    pub si: peripherals::PB15,
+    pub cs: AnyPin,
+}
+
+/// 72 MHz system clock from an 8 MHz crystal, with the ADC on 12 MHz.
+pub fn config() -> Config {
+    let mut config = Config::default();
+    {
+        use embassy_stm32::rcc::*;
+        config.rcc.hse = Some(Hse {
+            freq: Hertz(8_000_000),
+            mode: HseMode::Oscillator,
+        });
+        config.rcc.pll = Some(Pll {
+            src: PllSource::HSE,
+            prediv: PllPreDiv::DIV1,
+            mul: PllMul::MUL9,
+        });
+        config.rcc.sys = Sysclk::PLL1_P;
+        config.rcc.ahb_pre = AHBPrescaler::DIV1;
+        config.rcc.apb1_pre = APBPrescaler::DIV2;
+        config.rcc.apb2_pre = APBPrescaler::DIV1;
+    }
+    config
+}
+
+/// Make the host re-enumerate the device, e.g. after flashing new firmware.
+pub async fn reset_usb(dp: &mut peripherals::PA12) {
+    if USB_DP_PULLUP {
+        // Board has a pull-up resistor on the D+ line; pull it down to send a RESET condition to the USB bus.
+        // This forced reset is needed only for development, without it host will not reset your device when you upload new firmware.
+        let _dp = Output::new(dp, Level::Low, Speed::Low);
+        embassy_time::Timer::after_millis(10).await;
+    }
+}
+
+/// Set up the emitter pins, all low.
+pub fn drive_outputs(pins: [AnyPin; 8]) -> [Output<'static>; 8] {
+    pins.map(|pin| Output::new(pin, Level::Low, Speed::Low))
+}
+
+/// PDM emitter pattern as GPIOA BSRR words, wired up for this board.
+pub static PDM_SIGNAL: [u32; constants::PDM_WAVES.len()] = pdm_bsrr(&constants::PDM_WAVES);
+
+/// Every pad held low, for listening to the background with the timing of a normal capture.
+pub static QUIET_PDM_SIGNAL: [u32; constants::PDM_WAVES.len()] =
+    pdm_bsrr(&[0xFF << 16; constants::PDM_WAVES.len()]);
+
+/// `PDM_SIGNAL` with only the waves in `mask` (bit `w` for wave `w`) driven, and the rest held low, e.g. for the self-test.
+pub fn masked_pdm_signal(mask: u8, signal: &mut [u32; constants::PDM_WAVES.len()]) {
+    let driven = mask as u32;
+    let held_low = (!mask) as u32;
+    for (bsrr, &waves) in signal.iter_mut().zip(&constants::PDM_WAVES) {
+        *bsrr = wave_bsrr((waves & (driven | driven << 16)) | held_low << 16);
+    }
+}
+
+/// Move each wave's set/reset bits (bit `w` / `w + 16` in `build.rs` output) onto the pin driving it.
+const fn pdm_bsrr<const N: usize>(waves: &[u32; N]) -> [u32; N] {
+    let mut bsrr = [0u32; N];
+    let mut i = 0;
+    while i < N {
+        bsrr[i] = wave_bsrr(waves[i]);
+        i += 1;
+    }
+    bsrr
+}
+
+const fn wave_bsrr(waves: u32) -> u32 {
+    let mut bsrr = 0;
+    let mut wave = 0;
+    while wave < 8 {
+        let pin = DRIVE_PINS[wave];
+        if waves & (1 << wave) != 0 {
+            bsrr |= 1 << pin;
+        }
+        if waves & (1 << (wave + 16)) != 0 {
+            bsrr |= 1 << (pin + 16);
+        }
+        wave += 1;
+    }
+    bsrr
+}
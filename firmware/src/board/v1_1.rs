@@ -0,0 +1,42 @@
+//! v1.1 PCB: https://github.com/MitkoDyakov/Calipatron/tree/444c72c3e81eab0a2e7ee198f5574062dc1fc510/Hardware/V1.1
+
+use embassy_stm32::peripherals;
+
+pub const USB_DP_PULLUP: bool = true;
+
+/// PB1 is on channel 9 for STM32F103
+pub const PICKUP_ADC_CHANNEL: u8 = 9;
+pub type PickupPin = peripherals::PB1;
+
+/// Port and pin of the button, for waking from Stop mode (see `power::stop`): PB14.
+pub const WAKE_BUTTON: Option<crate::power::WakePin> = Some((1, 14));
+
+/// In the v1.1 schematic pins PA0--PA7 are wired up for waves 0,4, 1,5, 2,6, 3,7.
+pub const DRIVE_PINS: [u8; 8] = [0, 2, 4, 6, 1, 3, 5, 7];
+
+#[macro_export]
+macro_rules! board_pins {
+    ($p:ident) => {{
+        use embassy_stm32::gpio::Pin;
+        $crate::board::Pins {
+            drive: [
+                $p.PA0.degrade(),
+                $p.PA1.degrade(),
+                $p.PA2.degrade(),
+                $p.PA3.degrade(),
+                $p.PA4.degrade(),
+                $p.PA5.degrade(),
+                $p.PA6.degrade(),
+                $p.PA7.degrade(),
+            ],
+            pickup: $p.PB1,
+            button: Some($p.PB14.degrade()),
+            pga: Some($crate::board::PgaPins {
+                spi: $p.SPI2,
+                sck: $p.PB13,
+                si: $p.PB15,
+                cs: $p.PB12.degrade(),
+            }),
+        }
+    }};
+}
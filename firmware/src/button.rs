@@ -0,0 +1,39 @@
+use calipertron_core::button::{ButtonGestures, Gesture};
+use embassy_stm32::gpio::{AnyPin, Input, Pull};
+use embassy_time::{Instant, Timer};
+
+/// Fast enough to debounce and time gestures, see `calipertron_core::button::DEBOUNCE_MS`.
+const POLL_INTERVAL_MS: u64 = 5;
+
+/// The user button (pulled low when pressed), reporting debounced gestures rather than levels.
+pub struct Button<'d> {
+    input: Input<'d>,
+    gestures: ButtonGestures,
+}
+
+impl Button<'static> {
+    pub fn new(pin: AnyPin) -> Self {
+        Button {
+            input: Input::new(pin, Pull::None),
+            gestures: ButtonGestures::new(),
+        }
+    }
+}
+
+impl<'d> Button<'d> {
+    /// Wait for the next complete gesture. Safe to drop mid-gesture; polling picks up where it left off.
+    pub async fn gesture(&mut self) -> Gesture {
+        loop {
+            Timer::after_millis(POLL_INTERVAL_MS).await;
+            let now_ms = Instant::now().as_millis();
+            if let Some(gesture) = self.gestures.update(now_ms, self.input.is_low()) {
+                return gesture;
+            }
+        }
+    }
+
+    /// Don't count the press the button is in now, e.g. because it woke the chip from Stop mode.
+    pub fn ignore_press(&mut self) {
+        self.gestures.ignore_press(Instant::now().as_millis());
+    }
+}
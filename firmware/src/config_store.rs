@@ -0,0 +1,84 @@
+use defmt::*;
+use embassy_stm32::flash::{Blocking, Error, Flash, FLASH_SIZE};
+use schema::{DeviceConfig, CONFIG_RECORD_SIZE};
+
+// STM32F103C8 (medium-density) has 1kB flash pages.
+const PAGE_SIZE: u32 = 1024;
+
+// Records alternate between the last two pages, so a reset halfway through a save still leaves the previous record intact.
+const SLOTS: [u32; 2] = [
+    FLASH_SIZE as u32 - 2 * PAGE_SIZE,
+    FLASH_SIZE as u32 - PAGE_SIZE,
+];
+
+pub struct ConfigStore<'d> {
+    flash: Flash<'d, Blocking>,
+    /// Sequence number and slot index of the newest valid record, if any.
+    newest: Option<(u32, usize)>,
+}
+
+impl<'d> ConfigStore<'d> {
+    pub fn new(flash: Flash<'d, Blocking>) -> Self {
+        ConfigStore {
+            flash,
+            newest: None,
+        }
+    }
+
+    /// Returns the newest valid config in flash, if there is one.
+    pub fn load(&mut self) -> Option<DeviceConfig> {
+        let mut newest = None;
+
+        for (slot, &offset) in SLOTS.iter().enumerate() {
+            let mut record = [0u8; CONFIG_RECORD_SIZE];
+            if let Err(e) = self.flash.blocking_read(offset, &mut record) {
+                error!("Failed to read config slot {}: {:?}", slot, e);
+                continue;
+            }
+
+            match DeviceConfig::from_record(&record) {
+                Ok((sequence, config)) => {
+                    if newest.as_ref().map_or(true, |(s, _, _)| sequence > *s) {
+                        newest = Some((sequence, slot, config));
+                    }
+                }
+                Err(e) => debug!("Config slot {}: {:?}", slot, e),
+            }
+        }
+
+        self.newest = newest
+            .as_ref()
+            .map(|(sequence, slot, _)| (*sequence, *slot));
+        newest.map(|(_, _, config)| config)
+    }
+
+    pub fn save(&mut self, config: &DeviceConfig) -> Result<(), Error> {
+        let (sequence, slot) = match self.newest {
+            Some((sequence, slot)) => (sequence.wrapping_add(1), (slot + 1) % SLOTS.len()),
+            None => (0, 0),
+        };
+
+        let mut record = [0u8; CONFIG_RECORD_SIZE];
+        if config.to_record(sequence, &mut record).is_err() {
+            // Config has a fixed size that always fits in a record, so this shouldn't happen.
+            error!("Config doesn't fit in a flash record");
+            return Err(Error::Size);
+        }
+
+        let offset = SLOTS[slot];
+        self.flash.blocking_erase(offset, offset + PAGE_SIZE)?;
+        self.flash.blocking_write(offset, &record)?;
+
+        self.newest = Some((sequence, slot));
+        Ok(())
+    }
+
+    /// Erase all stored records; the next `load` returns `None`.
+    pub fn erase(&mut self) -> Result<(), Error> {
+        for &offset in SLOTS.iter() {
+            self.flash.blocking_erase(offset, offset + PAGE_SIZE)?;
+        }
+        self.newest = None;
+        Ok(())
+    }
+}
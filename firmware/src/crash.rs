@@ -0,0 +1,190 @@
+use core::fmt::Write;
+use core::mem::{size_of, MaybeUninit};
+use core::panic::PanicInfo;
+
+use cortex_m::peripheral::SCB;
+use cortex_m_rt::exception;
+use defmt::{error, warn, Display2Format};
+use schema::{
+    crc32, CrashMessage, CrashReport, ExceptionFrame, FaultStatus, MAX_CRASH_MESSAGE_LEN,
+};
+
+const MAGIC: u32 = 0xDEAD_C0DE;
+const KIND_HARD_FAULT: u32 = 1;
+const KIND_PANIC: u32 = 2;
+
+/// Plain words and bytes, so the fault handlers can fill it in without touching anything that might be what broke.
+#[repr(C)]
+struct Record {
+    magic: u32,
+    kind: u32,
+    /// r0, r1, r2, r3, r12, lr, pc, xpsr
+    frame: [u32; 8],
+    /// cfsr, hfsr, mmfar, bfar
+    status: [u32; 4],
+    message_len: u32,
+    message: [u8; MAX_CRASH_MESSAGE_LEN],
+    /// Of everything above; RAM holds garbage after power on.
+    crc: u32,
+}
+
+// The startup code doesn't zero `.uninit`, so a record written just before a reset is still there after it.
+#[link_section = ".uninit.CRASH_RECORD"]
+static mut RECORD: MaybeUninit<Record> = MaybeUninit::uninit();
+
+impl Record {
+    fn new(kind: u32) -> Self {
+        Record {
+            magic: MAGIC,
+            kind,
+            frame: [0; 8],
+            status: [0; 4],
+            message_len: 0,
+            message: [0; MAX_CRASH_MESSAGE_LEN],
+            crc: 0,
+        }
+    }
+
+    fn checksum(&self) -> u32 {
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                self as *const Record as *const u8,
+                size_of::<Record>() - size_of::<u32>(),
+            )
+        };
+        crc32(bytes)
+    }
+
+    fn save(mut self) {
+        self.crc = self.checksum();
+        unsafe {
+            core::ptr::write_volatile(core::ptr::addr_of_mut!(RECORD), MaybeUninit::new(self))
+        };
+    }
+
+    fn to_report(&self) -> Option<CrashReport> {
+        match self.kind {
+            KIND_HARD_FAULT => {
+                let [r0, r1, r2, r3, r12, lr, pc, xpsr] = self.frame;
+                let [cfsr, hfsr, mmfar, bfar] = self.status;
+                Some(CrashReport::HardFault {
+                    frame: ExceptionFrame {
+                        r0,
+                        r1,
+                        r2,
+                        r3,
+                        r12,
+                        lr,
+                        pc,
+                        xpsr,
+                    },
+                    status: FaultStatus {
+                        cfsr,
+                        hfsr,
+                        mmfar,
+                        bfar,
+                    },
+                })
+            }
+            KIND_PANIC => {
+                let len = (self.message_len as usize).min(MAX_CRASH_MESSAGE_LEN);
+                let bytes = &self.message[..len];
+                // truncation may have split a character
+                let valid = match core::str::from_utf8(bytes) {
+                    Ok(_) => len,
+                    Err(e) => e.valid_up_to(),
+                };
+                let text = core::str::from_utf8(&bytes[..valid]).unwrap_or_default();
+                let mut message = CrashMessage::new();
+                let _ = message.push_str(text);
+                Some(CrashReport::Panic { message })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The crash that caused the last reset, if there was one.
+/// Clears the record so it's only reported once per crash, so call it once at startup.
+pub fn take_crash_report() -> Option<CrashReport> {
+    let record = unsafe { core::ptr::read_volatile(core::ptr::addr_of!(RECORD)).assume_init() };
+    if record.magic != MAGIC || record.crc != record.checksum() {
+        return None;
+    }
+
+    unsafe {
+        core::ptr::addr_of_mut!(RECORD)
+            .cast::<u32>()
+            .write_volatile(0)
+    };
+
+    let report = record.to_report();
+    if let Some(report) = &report {
+        warn!("Recovered from crash: {:?}", report);
+    }
+    report
+}
+
+/// Keeps as much of the message as fits.
+struct Truncating<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for Truncating<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    let mut record = Record::new(KIND_PANIC);
+    let mut message = Truncating {
+        buf: &mut record.message,
+        len: 0,
+    };
+    let _ = write!(message, "{}", info);
+    record.message_len = message.len as u32;
+    record.save();
+
+    error!("{}", Display2Format(info));
+    SCB::sys_reset()
+}
+
+// `defmt::panic!` and friends have already logged their message, which is only available on the host.
+#[defmt::panic_handler]
+fn defmt_panic() -> ! {
+    core::panic!("defmt panic, see log")
+}
+
+#[exception]
+unsafe fn HardFault(frame: &cortex_m_rt::ExceptionFrame) -> ! {
+    let scb = &*SCB::PTR;
+
+    let mut record = Record::new(KIND_HARD_FAULT);
+    record.frame = [
+        frame.r0(),
+        frame.r1(),
+        frame.r2(),
+        frame.r3(),
+        frame.r12(),
+        frame.lr(),
+        frame.pc(),
+        frame.xpsr(),
+    ];
+    record.status = [
+        scb.cfsr.read(),
+        scb.hfsr.read(),
+        scb.mmfar.read(),
+        scb.bfar.read(),
+    ];
+    record.save();
+
+    SCB::sys_reset()
+}
@@ -0,0 +1,80 @@
+use defmt::*;
+use embassy_usb::driver::{Endpoint, EndpointError, EndpointIn, EndpointOut};
+use schema::{Command, CommandError, Response, MAX_RESPONSE_SIZE};
+
+// Full-speed bulk packet size. Commands always fit in one; longer responses are split.
+const PACKET_SIZE: usize = 64;
+
+/// Reads commands from the host and writes back a `Response` for each one.
+/// Anything the host sends that isn't a valid command is answered with an error here, so callers only ever see validated commands.
+pub struct Dispatcher<Out, In> {
+    read_ep: Out,
+    response_ep: In,
+}
+
+impl<Out: EndpointOut, In: EndpointIn> Dispatcher<Out, In> {
+    pub fn new(read_ep: Out, response_ep: In) -> Self {
+        Dispatcher {
+            read_ep,
+            response_ep,
+        }
+    }
+
+    pub async fn wait_enabled(&mut self) {
+        self.read_ep.wait_enabled().await;
+        self.response_ep.wait_enabled().await;
+    }
+
+    /// Wait for the next valid command.
+    pub async fn next_command(&mut self) -> Command {
+        loop {
+            let mut buf = [0u8; PACKET_SIZE];
+
+            let result = match self.read_ep.read(&mut buf).await {
+                Ok(size) => match Command::deserialize(&buf[..size]) {
+                    Some(command) => command.validate().map(|_| command),
+                    None => Err(CommandError::Malformed),
+                },
+                Err(EndpointError::BufferOverflow) => Err(CommandError::Malformed),
+                Err(EndpointError::Disabled) => {
+                    // nobody to respond to
+                    self.wait_enabled().await;
+                    continue;
+                }
+            };
+
+            match result {
+                Ok(command) => {
+                    info!("Received command: {:?}", command);
+                    return command;
+                }
+                Err(e) => {
+                    warn!("Rejected command: {:?}", e);
+                    self.respond(Response::Error(e)).await;
+                }
+            }
+        }
+    }
+
+    pub async fn respond(&mut self, response: impl Into<Response>) {
+        let response = response.into();
+        if let Response::Error(e) = &response {
+            warn!("Command failed: {:?}", e);
+        }
+
+        let mut buf = [0u8; MAX_RESPONSE_SIZE];
+        let Ok(bytes) = response.serialize(&mut buf) else {
+            error!("Failed to serialize response");
+            return;
+        };
+
+        // a short packet marks the end, so a response filling the last packet exactly is followed by an empty one
+        let zero_length_packet = (bytes.len() % PACKET_SIZE == 0).then_some(&[][..]);
+        for packet in bytes.chunks(PACKET_SIZE).chain(zero_length_packet) {
+            if let Err(e) = self.response_ep.write(packet).await {
+                error!("Failed to send response: {:?}", e);
+                return;
+            }
+        }
+    }
+}
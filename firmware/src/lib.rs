@@ -0,0 +1,13 @@
+#![no_std]
+
+pub mod adc;
+pub mod board;
+pub mod button;
+pub mod config_store;
+pub mod crash;
+pub mod dispatch;
+pub mod measure;
+pub mod pga;
+pub mod power;
+pub mod usb_state;
+pub mod watchdog;
@@ -0,0 +1,331 @@
+use calipertron_core::hopping::PhaseCombiner;
+use calipertron_core::measure::{self, Emitter, Sampler};
+use defmt::*;
+use embassy_stm32::adc::Adc;
+use embassy_stm32::dma::{Transfer, TransferOptions};
+use embassy_stm32::gpio::Flex;
+use embassy_stm32::peripherals::{ADC1, DMA1_CH1, DMA1_CH2, TIM2, TIM3};
+use embassy_stm32::time::Hertz;
+use embassy_stm32::timer::low_level::Timer;
+use embassy_stm32::Peripheral;
+use num_traits::Float;
+use schema::{
+    AdcSamplingPeriod, AdcTrigger, Gain, NOISE_SCAN_FREQUENCIES_kHz, NoiseScanReport,
+    MAX_HOP_FREQUENCIES, NOISE_SCAN_BINS, NOISE_SCAN_CAPTURES,
+};
+
+use crate::board::{PickupPin, PICKUP_ADC_CHANNEL};
+
+/// PDM waveform written to GPIOA's BSRR by DMA on every TIM2 update.
+///
+/// TIM2 must already be set up for update DMA requests at the PDM frequency.
+pub struct PdmEmitter<'a> {
+    timer: &'a Timer<'static, TIM2>,
+    trigger_timer: &'a Timer<'static, TIM3>,
+    dma: DMA1_CH2,
+    signal: &'static [u32],
+    transfer: Option<Transfer<'static>>,
+}
+
+impl<'a> PdmEmitter<'a> {
+    pub fn new(
+        timer: &'a Timer<'static, TIM2>,
+        trigger_timer: &'a Timer<'static, TIM3>,
+        dma: DMA1_CH2,
+        signal: &'static [u32],
+    ) -> Self {
+        PdmEmitter {
+            timer,
+            trigger_timer,
+            dma,
+            signal,
+            transfer: None,
+        }
+    }
+
+    /// Emit a different pattern from the next capture on, e.g. with some pads switched off for the self-test.
+    pub fn set_signal(&mut self, signal: &'static [u32]) {
+        self.signal = signal;
+    }
+
+    /// Change the PDM frequency from the next capture on.
+    /// The ADC trigger is clocked by the PDM timer, so the samples stay in step with the waveform.
+    pub fn set_frequency(&self, frequency: Hertz) {
+        self.timer.set_frequency(frequency);
+    }
+}
+
+impl Emitter for PdmEmitter<'_> {
+    fn start(&mut self) {
+        let mut opts = TransferOptions::default();
+        opts.circular = true;
+
+        // The transfer is always finished in `stop` before the channel is used again.
+        let dma_ch = unsafe { self.dma.clone_unchecked() };
+        let request = embassy_stm32::timer::UpDma::request(&dma_ch);
+
+        self.timer.reset();
+        crate::adc::restart_trigger(self.trigger_timer);
+
+        self.transfer = Some(unsafe {
+            Transfer::new_write(
+                dma_ch,
+                request,
+                self.signal,
+                embassy_stm32::pac::GPIOA.bsrr().as_ptr() as *mut u32,
+                opts,
+            )
+        });
+
+        self.timer.start();
+    }
+
+    async fn stop(&mut self) {
+        if let Some(mut transfer) = self.transfer.take() {
+            transfer.request_stop();
+            transfer.await;
+        }
+    }
+}
+
+/// ADC1 conversions of the regular sequence, read by DMA, triggered by `AdcTrigger::MEASURE` so they line up with the sine/cosine table.
+///
+/// The ADC must already be set up by `setup`.
+pub struct AdcSampler {
+    dma: DMA1_CH1,
+}
+
+impl AdcSampler {
+    pub fn new(dma: DMA1_CH1) -> Self {
+        AdcSampler { dma }
+    }
+}
+
+impl Sampler for AdcSampler {
+    async fn capture(&mut self, buf: &mut [u16]) {
+        let request = embassy_stm32::adc::RxDma::request(&self.dma);
+        let transfer = unsafe {
+            Transfer::new_read(
+                &mut self.dma,
+                request,
+                embassy_stm32::pac::ADC1.dr().as_ptr() as *mut u16,
+                buf,
+                TransferOptions::default(),
+            )
+        };
+
+        // With the PDM timer trigger this just arms the ADC; conversions start with the emitter.
+        crate::adc::start_conversions(&AdcTrigger::MEASURE);
+        transfer.await
+    }
+}
+
+/// TIM2, TIM3 and ADC1 as `setup` leaves them. The timers are lent to `PdmEmitter`;
+/// the rest keeps the ADC powered and the pickup pin analog for as long as this is kept around.
+pub struct MeasureHardware {
+    pub pdm_timer: Timer<'static, TIM2>,
+    pub trigger_timer: Timer<'static, TIM3>,
+    _adc: Adc<'static, ADC1>,
+    _pickup: Flex<'static>,
+}
+
+/// Set up TIM2 to step through the PDM table at `frequency` (one DMA request per update), TIM3 to count TIM2 updates,
+/// and ADC1 to convert the pickup with `sampling_period` on each `AdcTrigger::MEASURE` trigger, read by DMA.
+/// Then `PdmEmitter::new(&hardware.pdm_timer, &hardware.trigger_timer, ..)` and `AdcSampler::new(..)` take captures.
+pub async fn setup(
+    tim2: TIM2,
+    tim3: TIM3,
+    adc1: ADC1,
+    pickup: PickupPin,
+    frequency: Hertz,
+    sampling_period: &AdcSamplingPeriod,
+) -> MeasureHardware {
+    ////////////////////////
+    // Signal emission setup
+
+    let pdm_timer = Timer::new(tim2);
+    let timer_registers = pdm_timer.regs_gp16();
+    timer_registers
+        .cr2()
+        .modify(|w| w.set_ccds(embassy_stm32::pac::timer::vals::Ccds::ONUPDATE));
+    timer_registers.dier().modify(|w| {
+        // Enable update DMA request
+        w.set_ude(true);
+        // Enable update interrupt request
+        w.set_uie(true);
+    });
+
+    pdm_timer.set_frequency(frequency);
+
+    // Clocked by TIM2 to trigger ADC conversions in step with the PDM table.
+    let trigger_timer = Timer::new(tim3);
+
+    ////////////////////////
+    // ADC + DMA setup
+
+    // just need this to power on ADC
+    let adc = Adc::new(adc1);
+    crate::adc::setup().await;
+
+    // Configure ADC for timer-triggered conversion with DMA
+    let regs = embassy_stm32::pac::ADC1;
+
+    regs.cr1().modify(|w| {
+        w.set_scan(true);
+        w.set_eocie(true);
+    });
+
+    regs.cr2().modify(|w| w.set_dma(true));
+    crate::adc::set_trigger(&pdm_timer, &trigger_timer, &AdcTrigger::MEASURE);
+
+    // Configure channel and sampling time
+    regs.sqr1().modify(|w| w.set_l(0)); // one conversion.
+
+    // TODO: this may not be necessary
+    let mut pickup = Flex::new(pickup);
+    pickup.set_as_analog();
+
+    regs.sqr3().modify(|w| w.set_sq(0, PICKUP_ADC_CHANNEL));
+    regs.smpr2().modify(|w| {
+        w.set_smp(
+            PICKUP_ADC_CHANNEL as usize,
+            crate::adc::sample_time(sampling_period),
+        )
+    });
+
+    MeasureHardware {
+        pdm_timer,
+        trigger_timer,
+        _adc: adc,
+        _pickup: pickup,
+    }
+}
+
+/// Measures once at each hop frequency and combines the phases, see `calipertron_core::hopping`.
+/// With no hop frequencies it measures once at the base frequency.
+///
+/// With the `PdmTimer` trigger the ADC samples a fixed number of times per PDM cycle whatever the frequency,
+/// so the same correlation table matches every hop.
+pub struct Hopper {
+    base: Hertz,
+    /// As configured, to notice when they change.
+    configured_kHz: heapless::Vec<f32, MAX_HOP_FREQUENCIES>,
+    /// Configured frequencies the ADC keeps up with.
+    frequencies: heapless::Vec<Hertz, MAX_HOP_FREQUENCIES>,
+    combiner: PhaseCombiner<MAX_HOP_FREQUENCIES>,
+}
+
+impl Hopper {
+    pub fn new(base: Hertz) -> Self {
+        Hopper {
+            base,
+            configured_kHz: heapless::Vec::new(),
+            frequencies: heapless::Vec::new(),
+            combiner: PhaseCombiner::new(),
+        }
+    }
+
+    /// Hop across `frequencies_kHz` from the next measurement, leaving out any the ADC can't keep up with.
+    /// What the combiner learned is kept unless the frequencies changed.
+    #[allow(non_snake_case)]
+    pub fn set_frequencies(
+        &mut self,
+        frequencies_kHz: &[f32],
+        adc_sampling_period: &AdcSamplingPeriod,
+    ) {
+        if self.configured_kHz.as_slice() == frequencies_kHz {
+            return;
+        }
+        self.configured_kHz.clear();
+        self.frequencies.clear();
+        for &frequency_kHz in frequencies_kHz.iter().take(MAX_HOP_FREQUENCIES) {
+            let _ = self.configured_kHz.push(frequency_kHz);
+            if AdcTrigger::MEASURE
+                .sample_rate_Hz(frequency_kHz as f64, adc_sampling_period)
+                .is_none()
+            {
+                warn!(
+                    "Hop frequency {}kHz is too fast for the ADC, skipping it",
+                    frequency_kHz
+                );
+                continue;
+            }
+            let _ = self.frequencies.push(Hertz((frequency_kHz * 1000.) as u32));
+        }
+        self.combiner.reset();
+        info!("Hopping across {} frequencies", self.frequencies.len());
+    }
+
+    /// Capture at each frequency in turn, calling `each_capture` with the samples (e.g. for gain control), and return the combined phase.
+    pub async fn measure(
+        &mut self,
+        emitter: &mut PdmEmitter<'_>,
+        sampler: &mut AdcSampler,
+        buf: &mut [u16],
+        sine_cosine_table: &[(f32, f32)],
+        mut each_capture: impl FnMut(&[u16]),
+    ) -> f32 {
+        if self.frequencies.is_empty() {
+            emitter.set_frequency(self.base);
+            measure::capture(emitter, sampler, buf).await;
+            each_capture(buf);
+            return measure::phase(buf, sine_cosine_table);
+        }
+
+        let mut phases = heapless::Vec::<f32, MAX_HOP_FREQUENCIES>::new();
+        for &frequency in &self.frequencies {
+            emitter.set_frequency(frequency);
+            measure::capture(emitter, sampler, buf).await;
+            each_capture(buf);
+            let _ = phases.push(measure::phase(buf, sine_cosine_table));
+        }
+        self.combiner.update(&phases)
+    }
+
+    /// Log how much each frequency currently counts, to spot one being rejected.
+    pub fn log_shares(&self) {
+        for (i, frequency) in self.frequencies.iter().enumerate() {
+            info!(
+                "Hop {}Hz: {}% of the phase, lag {} rad",
+                frequency.0,
+                self.combiner.share(i) * 100.,
+                self.combiner.offset(i)
+            );
+        }
+    }
+}
+
+/// Run the capture and correlation at each of `NOISE_SCAN_FREQUENCIES_kHz` with every pad held low, see `schema::NoiseScanReport`.
+/// Leaves the emitter on the board's normal pattern at `base`.
+#[allow(non_snake_case)]
+pub async fn noise_scan(
+    emitter: &mut PdmEmitter<'_>,
+    sampler: &mut AdcSampler,
+    buf: &mut [u16],
+    sine_cosine_table: &[(f32, f32)],
+    gain: Gain,
+    base: Hertz,
+) -> NoiseScanReport {
+    emitter.set_signal(&crate::board::QUIET_PDM_SIGNAL);
+
+    let mut amplitudes = [0f32; NOISE_SCAN_BINS];
+    for (amplitude, &frequency_kHz) in amplitudes.iter_mut().zip(&NOISE_SCAN_FREQUENCIES_kHz) {
+        emitter.set_frequency(Hertz((frequency_kHz * 1000.) as u32));
+        let mut power = 0.;
+        for _ in 0..NOISE_SCAN_CAPTURES {
+            measure::capture(emitter, sampler, buf).await;
+            power += measure::amplitude(buf, sine_cosine_table).powi(2);
+        }
+        *amplitude = (power / NOISE_SCAN_CAPTURES as f32).sqrt();
+    }
+
+    emitter.set_signal(&crate::board::PDM_SIGNAL);
+    emitter.set_frequency(base);
+
+    let report = NoiseScanReport::new(gain, &amplitudes);
+    for (amplitude, frequency_kHz) in report.amplitudes.iter().zip(NOISE_SCAN_FREQUENCIES_kHz) {
+        info!("Noise at {}kHz: {}", frequency_kHz, amplitude);
+    }
+    info!("Quietest: {:?}kHz", report.suggested_kHz);
+    report
+}
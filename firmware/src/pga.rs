@@ -0,0 +1,54 @@
+use defmt::*;
+use embassy_stm32::gpio::{Level, Output, Speed};
+use embassy_stm32::mode::Blocking;
+use embassy_stm32::spi::{self, Spi};
+use embassy_stm32::time::Hertz;
+use schema::{Gain, PgaCommand};
+
+use crate::board::PgaPins;
+
+/// MCP6S21 programmable gain amplifier between the pickup and PB1.
+///
+/// Write-only: the chip has no SDO, so SPI2 runs transmit-only and PB14 stays free for the user button.
+pub struct Pga<'d> {
+    spi: Spi<'d, Blocking>,
+    cs: Output<'d>,
+}
+
+impl Pga<'static> {
+    pub fn new(pins: PgaPins) -> Self {
+        let mut config = spi::Config::default();
+        // datasheet max is 10 MHz; nothing here is timing critical
+        config.frequency = Hertz(1_000_000);
+        config.mode = spi::MODE_0;
+
+        Pga {
+            spi: Spi::new_blocking_txonly(pins.spi, pins.sck, pins.si, config),
+            cs: Output::new(pins.cs, Level::High, Speed::Low),
+        }
+    }
+
+    /// The amplifier on boards that have one, set to `gain`.
+    /// A failed write is only logged: the board still measures, at whatever gain the amplifier was left on.
+    pub fn setup(pins: Option<PgaPins>, gain: Gain) -> Option<Self> {
+        let mut pga = Pga::new(pins?);
+        if let Err(e) = pga.set_gain(gain) {
+            error!("Failed to set gain: {:?}", e);
+        }
+        Some(pga)
+    }
+
+    pub fn send(&mut self, command: PgaCommand) -> Result<(), spi::Error> {
+        // the command is latched on the rising edge of chip select
+        self.cs.set_low();
+        let result = self.spi.blocking_write(&command.encode());
+        self.cs.set_high();
+        result
+    }
+}
+
+impl<'d> Pga<'d> {
+    pub fn set_gain(&mut self, gain: Gain) -> Result<(), spi::Error> {
+        self.send(PgaCommand::SetGain(gain))
+    }
+}
@@ -0,0 +1,150 @@
+use cortex_m::peripheral::SCB;
+use defmt::*;
+use embassy_stm32::pac;
+
+use crate::watchdog;
+
+/// LSI frequency; the datasheet only promises 30--60 kHz, so Stop mode durations are rough.
+#[allow(non_upper_case_globals)]
+const LSI_Hz: u32 = 40_000;
+
+/// The IWDG keeps counting in Stop mode, so wake up well before it fires.
+const MAX_STOP_MS: u32 = watchdog::TIMEOUT_us / 1000 * 3 / 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum WakeReason {
+    Button,
+    /// RTC alarm, for a motion probe.
+    Probe,
+}
+
+/// GPIO port (0 for A, 1 for B, ...) and pin number of a button that can wake the chip from Stop mode.
+pub type WakePin = (u8, u8);
+
+/// Enter Stop mode until the button is pressed or `probe_after_ms` pass, then bring the 72 MHz clocks back.
+/// Returns why it woke and roughly how long it was stopped (in ms).
+///
+/// Timers, DMA and the ADC stop too, so only call it between captures. The embassy clock doesn't advance while stopped.
+pub fn stop(probe_after_ms: u32, button: Option<WakePin>) -> (WakeReason, u32) {
+    let exti = pac::EXTI;
+    let rtc = pac::RTC;
+    let rcc = pac::RCC;
+
+    setup_rtc();
+
+    let probe_after_ms = probe_after_ms.clamp(1, MAX_STOP_MS);
+    configure_rtc(|| {
+        rtc.cntl().write(|w| w.set_cntl(0));
+        rtc.cnth().write(|w| w.set_cnth(0));
+        rtc.alrl().write(|w| w.set_alrl(probe_after_ms as u16));
+        rtc.alrh()
+            .write(|w| w.set_alrh((probe_after_ms >> 16) as u16));
+    });
+    rtc.crl().modify(|w| w.set_alrf(false));
+
+    // wake on events rather than interrupts, so nothing needs a handler
+    const RTC_ALARM_LINE: usize = 17;
+    exti.rtsr(0).modify(|w| w.set_line(RTC_ALARM_LINE, true));
+    exti.emr(0).modify(|w| w.set_line(RTC_ALARM_LINE, true));
+    if let Some((port, pin)) = button {
+        let line = pin as usize;
+        rcc.apb2enr().modify(|w| w.set_afioen(true));
+        pac::AFIO
+            .exticr(line / 4)
+            .modify(|w| w.set_exti(line % 4, port));
+        // buttons pull low when pressed
+        exti.ftsr(0).modify(|w| w.set_line(line, true));
+        exti.emr(0).modify(|w| w.set_line(line, true));
+    }
+    exti.pr(0).write(|w| w.0 = !0);
+
+    pac::PWR.cr().modify(|w| {
+        w.set_pdds(pac::pwr::vals::Pdds::STOP_MODE);
+        w.set_lpds(true);
+        w.set_cwuf(true);
+    });
+
+    watchdog::pet_now();
+    unsafe { (*SCB::PTR).scr.modify(|scr| scr | SCB_SCR_SLEEPDEEP) };
+    cortex_m::asm::dsb();
+    // clear the event register, in case something set it since the last WFE, then stop
+    cortex_m::asm::sev();
+    cortex_m::asm::wfe();
+    cortex_m::asm::wfe();
+    unsafe { (*SCB::PTR).scr.modify(|scr| scr & !SCB_SCR_SLEEPDEEP) };
+
+    // Stop mode leaves the chip running on the HSI
+    restore_clocks();
+    watchdog::pet_now();
+
+    let stopped_ms = (rtc.cnth().read().cnth() as u32) << 16 | rtc.cntl().read().cntl() as u32;
+    let reason = match button {
+        Some((_, pin)) if exti.pr(0).read().line(pin as usize) => WakeReason::Button,
+        _ => WakeReason::Probe,
+    };
+    exti.emr(0).write(|w| w.0 = 0);
+    exti.pr(0).write(|w| w.0 = !0);
+
+    trace!("Woke from Stop mode after ~{}ms: {:?}", stopped_ms, reason);
+    (reason, stopped_ms)
+}
+
+const SCB_SCR_SLEEPDEEP: u32 = 1 << 2;
+
+/// Clock the RTC from the LSI (already running for the IWDG) at ~1 kHz, so counts are ms.
+fn setup_rtc() {
+    let rcc = pac::RCC;
+    let rtc = pac::RTC;
+
+    rcc.apb1enr().modify(|w| {
+        w.set_pwren(true);
+        w.set_bkpen(true);
+    });
+    pac::PWR.cr().modify(|w| w.set_dbp(true));
+
+    rcc.csr().modify(|w| w.set_lsion(true));
+    while !rcc.csr().read().lsirdy() {}
+
+    let bdcr = rcc.bdcr().read();
+    if bdcr.rtcen() && bdcr.rtcsel() == pac::rcc::vals::Rtcsel::LSI {
+        return;
+    }
+
+    // the clock source can only be changed by resetting the backup domain
+    rcc.bdcr().modify(|w| w.set_bdrst(true));
+    rcc.bdcr().modify(|w| w.set_bdrst(false));
+    rcc.bdcr().modify(|w| {
+        w.set_rtcsel(pac::rcc::vals::Rtcsel::LSI);
+        w.set_rtcen(true);
+    });
+
+    rtc.crl().modify(|w| w.set_rsf(false));
+    while !rtc.crl().read().rsf() {}
+
+    let prescaler = LSI_Hz / 1000 - 1;
+    configure_rtc(|| {
+        rtc.prll().write(|w| w.set_prll(prescaler as u16));
+        rtc.prlh().write(|w| w.set_prlh((prescaler >> 16) as u8));
+    });
+}
+
+/// RTC registers can only be written in configuration mode, and only once the previous write has finished.
+fn configure_rtc(f: impl FnOnce()) {
+    let rtc = pac::RTC;
+    while !rtc.crl().read().rtoff() {}
+    rtc.crl().modify(|w| w.set_cnf(true));
+    f();
+    rtc.crl().modify(|w| w.set_cnf(false));
+    while !rtc.crl().read().rtoff() {}
+}
+
+/// Back to the HSE and PLL set up by `board::config`; the PLL settings survive Stop mode, only the oscillators are off.
+fn restore_clocks() {
+    let rcc = pac::RCC;
+    rcc.cr().modify(|w| w.set_hseon(true));
+    while !rcc.cr().read().hserdy() {}
+    rcc.cr().modify(|w| w.set_pllon(true));
+    while !rcc.cr().read().pllrdy() {}
+    rcc.cfgr().modify(|w| w.set_sw(pac::rcc::vals::Sw::PLL1_P));
+    while rcc.cfgr().read().sws() != pac::rcc::vals::Sw::PLL1_P {}
+}
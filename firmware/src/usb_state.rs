@@ -0,0 +1,112 @@
+use core::cell::{Cell, RefCell};
+use core::future::{poll_fn, Future};
+use core::task::Poll;
+
+use defmt::*;
+use embassy_futures::select::{select, Either};
+use embassy_sync::waitqueue::MultiWakerRegistration;
+use embassy_usb::Handler;
+
+/// Whether the host can currently talk to us.
+///
+/// The F103 can't sense VBUS, so an unplugged cable just looks like the bus going quiet (a suspend).
+/// Endpoint writes then wait forever rather than failing, so anything talking to the host should go through `or_disconnect`.
+pub struct UsbState {
+    configured: Cell<bool>,
+    suspended: Cell<bool>,
+    wakers: RefCell<MultiWakerRegistration<4>>,
+}
+
+impl Default for UsbState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UsbState {
+    pub const fn new() -> Self {
+        UsbState {
+            configured: Cell::new(false),
+            suspended: Cell::new(false),
+            wakers: RefCell::new(MultiWakerRegistration::new()),
+        }
+    }
+
+    /// Register with `embassy_usb::Builder::handler` to track the bus state.
+    pub fn handler(&self) -> UsbStateHandler<'_> {
+        UsbStateHandler(self)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.configured.get() && !self.suspended.get()
+    }
+
+    fn wait_for(&self, connected: bool) -> impl Future<Output = ()> + '_ {
+        poll_fn(move |cx| {
+            if self.is_connected() == connected {
+                Poll::Ready(())
+            } else {
+                self.wakers.borrow_mut().register(cx.waker());
+                Poll::Pending
+            }
+        })
+    }
+
+    pub async fn wait_connected(&self) {
+        self.wait_for(true).await
+    }
+
+    pub async fn wait_disconnected(&self) {
+        self.wait_for(false).await
+    }
+
+    /// Run `fut` until it finishes, or return `None` if the host goes away first.
+    pub async fn or_disconnect<F: Future>(&self, fut: F) -> Option<F::Output> {
+        match select(fut, self.wait_disconnected()).await {
+            Either::First(output) => Some(output),
+            Either::Second(()) => None,
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&Self)) {
+        let was_connected = self.is_connected();
+        f(self);
+        let connected = self.is_connected();
+        if connected != was_connected {
+            info!(
+                "USB {}",
+                if connected {
+                    "connected"
+                } else {
+                    "disconnected"
+                }
+            );
+            self.wakers.borrow_mut().wake();
+        }
+    }
+}
+
+pub struct UsbStateHandler<'a>(&'a UsbState);
+
+impl Handler for UsbStateHandler<'_> {
+    fn enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.0.update(|s| s.configured.set(false));
+        }
+    }
+
+    fn reset(&mut self) {
+        self.0.update(|s| {
+            s.configured.set(false);
+            s.suspended.set(false);
+        });
+    }
+
+    fn configured(&mut self, configured: bool) {
+        self.0.update(|s| s.configured.set(configured));
+    }
+
+    fn suspended(&mut self, suspended: bool) {
+        self.0.update(|s| s.suspended.set(suspended));
+    }
+}
@@ -0,0 +1,83 @@
+use core::cell::Cell;
+
+use defmt::*;
+use embassy_stm32::peripherals::IWDG;
+use embassy_stm32::wdg::IndependentWatchdog;
+use embassy_time::{Duration, Instant, Timer};
+use schema::ResetCause;
+
+/// IWDG timeout. `run` pets it well within this, so it only fires if the executor stops running (e.g. an interrupt that never clears).
+#[allow(non_upper_case_globals)]
+pub(crate) const TIMEOUT_us: u32 = 1_000_000;
+const PET_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Why the chip last reset, from the RCC flags.
+/// Clears the flags so the next reset reads cleanly, so call it once at startup.
+pub fn take_reset_cause() -> ResetCause {
+    let rcc = embassy_stm32::pac::RCC;
+    let cause = ResetCause::from_rcc_csr(rcc.csr().read().0);
+    rcc.csr().modify(|w| w.set_rmvf(true));
+    cause
+}
+
+/// Pet the IWDG right now, bypassing `Watchdog::run`.
+/// Only for `power::stop`: the IWDG keeps counting in Stop mode, but nothing else runs.
+pub(crate) fn pet_now() {
+    embassy_stm32::pac::IWDG
+        .kr()
+        .write(|w| w.set_key(embassy_stm32::pac::iwdg::vals::Key::RESET));
+}
+
+/// Resets the chip when one of `N` tasks gets stuck waiting on the hardware (a DMA transfer that never completes, say).
+///
+/// Tasks `feed` as they make progress and go `idle` before waiting on something that may legitimately take forever,
+/// like the host sending a command or reading data.
+pub struct Watchdog<const N: usize> {
+    /// When each task last made progress, `None` while it's idle.
+    last_fed: [Cell<Option<Instant>>; N],
+    stall_timeout: Duration,
+}
+
+impl<const N: usize> Watchdog<N> {
+    pub fn new(stall_timeout: Duration) -> Self {
+        Watchdog {
+            last_fed: [const { Cell::new(None) }; N],
+            stall_timeout,
+        }
+    }
+
+    pub fn feed(&self, task: usize) {
+        self.last_fed[task].set(Some(Instant::now()));
+    }
+
+    pub fn idle(&self, task: usize) {
+        self.last_fed[task].set(None);
+    }
+
+    fn stalled_task(&self) -> Option<usize> {
+        self.last_fed.iter().position(|last_fed| {
+            last_fed
+                .get()
+                .is_some_and(|t| t.elapsed() > self.stall_timeout)
+        })
+    }
+
+    /// Start the IWDG and keep petting it while every task is making progress. Never returns.
+    pub async fn run(&self, iwdg: IWDG) -> ! {
+        // keep the IWDG from resetting the chip while it's halted in the debugger
+        embassy_stm32::pac::DBGMCU
+            .cr()
+            .modify(|w| w.set_dbg_iwdg_stop(true));
+
+        let mut wdg = IndependentWatchdog::new(iwdg, TIMEOUT_us);
+        wdg.unleash();
+
+        loop {
+            match self.stalled_task() {
+                None => wdg.pet(),
+                Some(task) => error!("Task {} stalled, waiting for watchdog reset", task),
+            }
+            Timer::after(PET_INTERVAL).await;
+        }
+    }
+}
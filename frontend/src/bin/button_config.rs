@@ -0,0 +1,55 @@
+// Sets what the button's short, long and double presses do, and saves it to flash.
+// Works with the "recorder" and "keyboard" firmware.
+//
+//     cargo run --release --bin button_config -- <short> <long> <double>
+//
+// Each action is one of none, zero, hold, units (next of mm, inch, fractional inch), send or mode (next display mode).
+
+use frontend::device::{self, send_command};
+use futures_lite::future::block_on;
+use schema::*;
+
+fn usage() -> ! {
+    eprintln!("Usage: button_config <short> <long> <double>");
+    eprintln!("actions: none, zero, hold, units, send, mode");
+    std::process::exit(2);
+}
+
+fn parse_action(s: &str) -> ButtonAction {
+    match s.to_ascii_lowercase().as_str() {
+        "none" => ButtonAction::None,
+        "zero" => ButtonAction::Zero,
+        "hold" => ButtonAction::Hold,
+        "units" => ButtonAction::ToggleUnits,
+        "send" => ButtonAction::Send,
+        "mode" => ButtonAction::NextMode,
+        _ => usage(),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [short, long, double] = &args[..] else {
+        usage();
+    };
+
+    let buttons = ButtonBindings {
+        short: parse_action(short),
+        long: parse_action(long),
+        double: parse_action(double),
+    };
+
+    let interface = device::open().expect("device should be connected");
+
+    for command in [Command::SetButtons(buttons), Command::SaveConfig] {
+        match block_on(send_command(&interface, &command)) {
+            Some(Response::Ok) => {}
+            response => {
+                eprintln!("Error: {command:?} failed: {response:?}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    println!("Saved");
+}
@@ -0,0 +1,26 @@
+// Prints the last crash the device recovered from, if any.
+// Works with the "recorder" and "usb_custom" firmware.
+
+use frontend::device::{self, send_command};
+use futures_lite::future::block_on;
+use schema::*;
+
+fn main() {
+    let interface = device::open().expect("device should be connected");
+
+    match block_on(send_command(&interface, &Command::GetCrashReport)) {
+        Some(Response::CrashReport(None)) => println!("No crash since power on"),
+        Some(Response::CrashReport(Some(CrashReport::Panic { message }))) => {
+            println!("Panic: {message}")
+        }
+        Some(Response::CrashReport(Some(CrashReport::HardFault { frame, status }))) => {
+            println!("HardFault at pc {:#010x}", frame.pc);
+            println!("{frame:#x?}");
+            println!("{status:#x?}");
+        }
+        response => {
+            eprintln!("Error: device didn't send a crash report: {response:?}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+#![allow(non_snake_case)]
+
+// Sets the PDM frequencies (kHz) the measuring firmware hops across, and saves them to flash.
+// Works with the "recorder" and "keyboard" firmware; no frequencies turns hopping off.
+//
+//     cargo run --release --bin hopping_config -- [frequency_kHz ...]
+
+use frontend::device::{self, send_command};
+use futures_lite::future::block_on;
+use schema::*;
+
+fn usage() -> ! {
+    eprintln!("Usage: hopping_config [frequency_kHz ...]");
+    eprintln!("Up to {MAX_HOP_FREQUENCIES} frequencies between {MIN_FREQUENCY_kHz} and {MAX_FREQUENCY_kHz}kHz; use at least three so a bad one can be told apart");
+    std::process::exit(2);
+}
+
+fn main() {
+    let mut frequencies_kHz = HopFrequencies::new();
+    for arg in std::env::args().skip(1) {
+        let frequency_kHz = arg.parse().unwrap_or_else(|_| usage());
+        frequencies_kHz
+            .push(frequency_kHz)
+            .unwrap_or_else(|_| usage());
+    }
+    let command = Command::SetHopping(frequencies_kHz);
+    if command.validate().is_err() {
+        usage();
+    }
+
+    let interface = device::open().expect("device should be connected");
+
+    for command in [command, Command::SaveConfig] {
+        match block_on(send_command(&interface, &command)) {
+            Some(Response::Ok) => {}
+            response => {
+                eprintln!("Error: {command:?} failed: {response:?}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    println!("Saved");
+}
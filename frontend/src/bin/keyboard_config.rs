@@ -0,0 +1,57 @@
+// Sets how the "keyboard" firmware types measurements, and saves it to flash.
+// Works with the "keyboard" and "recorder" firmware.
+//
+//     cargo run --release --bin keyboard_config -- <mm|inch|frac> <decimals> <none|tab|enter>
+
+use frontend::device::{self, send_command};
+use futures_lite::future::block_on;
+use schema::*;
+
+fn usage() -> ! {
+    eprintln!("Usage: keyboard_config <mm|inch|frac> <decimals> <none|tab|enter>");
+    std::process::exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [units, decimals, terminator] = &args[..] else {
+        usage()
+    };
+
+    let units = match units.as_str() {
+        "mm" => Units::Millimeter,
+        "inch" => Units::Inch,
+        "frac" => Units::FractionalInch,
+        _ => usage(),
+    };
+    let decimals = decimals.parse().unwrap_or_else(|_| usage());
+    let terminator = match terminator.as_str() {
+        "none" => Terminator::None,
+        "tab" => Terminator::Tab,
+        "enter" => Terminator::Enter,
+        _ => usage(),
+    };
+
+    let commands = [
+        Command::SetUnits(units),
+        Command::SetKeyboard(KeyboardConfig {
+            decimals,
+            terminator,
+        }),
+        Command::SaveConfig,
+    ];
+
+    let interface = device::open().expect("device should be connected");
+
+    for command in commands {
+        match block_on(send_command(&interface, &command)) {
+            Some(Response::Ok) => {}
+            response => {
+                eprintln!("Error: {command:?} failed: {response:?}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    println!("Saved");
+}
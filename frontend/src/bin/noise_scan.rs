@@ -0,0 +1,72 @@
+#![allow(non_snake_case)]
+
+// Listens with the emitter off and prints the interference the measurement would pick up at each drive frequency,
+// with the quietest ones to use. Works with the "self_test" firmware.
+//
+//     cargo run --release --bin noise_scan
+//
+// `pick on` makes the measuring firmware run the scan at startup and measure at the quietest frequency
+// (when not hopping); `pick off` goes back to the build.rs frequency. Works with the "recorder" and "keyboard" firmware.
+//
+//     cargo run --release --bin noise_scan -- pick on|off
+
+use frontend::device::{self, send_command};
+use futures_lite::future::block_on;
+use schema::*;
+
+fn usage() -> ! {
+    eprintln!("Usage: noise_scan [pick on|off]");
+    std::process::exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let commands = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => vec![Command::NoiseScan],
+        ["pick", "on"] => vec![Command::SetPickQuietFrequency(true), Command::SaveConfig],
+        ["pick", "off"] => vec![Command::SetPickQuietFrequency(false), Command::SaveConfig],
+        _ => usage(),
+    };
+
+    let interface = device::open().expect("device should be connected");
+
+    for command in commands {
+        match block_on(send_command(&interface, &command)) {
+            Some(Response::Ok) => {}
+            Some(Response::NoiseScan(report)) => print_report(&report),
+            response => {
+                eprintln!("Error: {command:?} failed: {response:?}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn print_report(report: &NoiseScanReport) {
+    println!("Gain {:?}", report.gain);
+    let loudest = report.amplitudes.iter().copied().fold(f32::MIN, f32::max);
+    println!("  PDM kHz  signal Hz  noise (ADC counts)");
+    for (amplitude, frequency_kHz) in report.amplitudes.iter().zip(NOISE_SCAN_FREQUENCIES_kHz) {
+        let bar = if loudest > 0. {
+            (amplitude / loudest * 40.).round() as usize
+        } else {
+            0
+        };
+        println!(
+            "  {frequency_kHz:>7.0}  {:>9.0}  {amplitude:>8.2} {}",
+            frequency_kHz * 1000. / 128.,
+            "#".repeat(bar)
+        );
+    }
+
+    let suggested: Vec<String> = report
+        .suggested_kHz
+        .iter()
+        .map(|f| format!("{f:.0}"))
+        .collect();
+    println!("Quietest: {} kHz", suggested.join(", "));
+    println!(
+        "Hop across them with: cargo run --release --bin hopping_config -- {}",
+        suggested.join(" ")
+    );
+}
@@ -3,112 +3,297 @@
 // This binary sweeps the frequency of the PDM signal and records the ADC values to a file.
 // Use with "Recorder" firmware.
 
+use frontend::device::{self, send_command};
+use nusb::Interface;
 use schema::*;
 use std::io::{BufWriter, Write};
 use tokio::time::timeout;
 
+// Back to back conversions, so every sampling period (including dual interleaved) can be swept at every frequency.
+const TRIGGER: AdcTrigger = AdcTrigger::FreeRunning;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let file = std::fs::File::create("parameter_sweep.csv")?;
     let mut csv_writer = BufWriter::new(file);
-    writeln!(csv_writer, "pdm_frequency,sampling_frequency,n,sample")?;
-
-    for frequency_kHz in (32..256).step_by(2) {
-        use AdcSamplingPeriod::*;
-        for adc_sampling_period in &[
-            CYCLES1_5,
-            CYCLES7_5,
-            CYCLES13_5,
-            CYCLES28_5,
-            CYCLES41_5,
-            CYCLES55_5,
-            CYCLES71_5,
-            CYCLES239_5,
-        ] {
-            'connection: loop {
-                let Some(di) = nusb::list_devices()?
-                    .find(|d| d.vendor_id() == 0xc0de && d.product_id() == 0xcafe)
-                else {
-                    continue 'connection;
-                };
-
-                let Ok(device) = di.open() else {
-                    continue 'connection;
-                };
-
-                let interface = device.claim_interface(0)?;
-
-                let endpoint_addr = 1;
-                let mut out_queue = interface.bulk_out_queue(endpoint_addr);
-
-                let mut queue = interface.bulk_in_queue(0x80 + endpoint_addr);
-                let transfer_size = 64;
-
-                send_command(
-                    &mut out_queue,
-                    Command::SetFrequency {
-                        frequency_kHz: frequency_kHz as f64,
-                        adc_sampling_period: adc_sampling_period.clone(),
-                    },
-                );
-
-                send_command(&mut out_queue, Command::Record);
-
-                let num_recorded_packets = 128;
-                let mut samples = Vec::new();
-                for _packet_idx in 0..num_recorded_packets {
-                    if queue.pending() == 0 {
-                        queue.submit(nusb::transfer::RequestBuffer::new(transfer_size));
+    writeln!(
+        csv_writer,
+        "pdm_frequency,sampling_frequency,n,sample,gain,vrefint"
+    )?;
+
+    // The device resets itself (via its watchdog) when it freezes, so just keep trying to reconnect.
+    let mut reconnecting = false;
+
+    // Boards without the amplifier reject `SetGain`. The pickup goes straight into the ADC there, so there's no gain axis:
+    // they're swept once, recorded as 1x.
+    let mut has_amplifier = true;
+
+    for gain in Gain::ALL {
+        if !has_amplifier {
+            break;
+        }
+        for frequency_kHz in (32..256).step_by(2) {
+            use AdcSamplingPeriod::*;
+            for adc_sampling_period in &[
+                CYCLES1_5,
+                CYCLES7_5,
+                CYCLES13_5,
+                CYCLES28_5,
+                CYCLES41_5,
+                CYCLES55_5,
+                CYCLES71_5,
+                CYCLES239_5,
+                CYCLES1_5_DUAL_INTERLEAVED,
+            ] {
+                'connection: loop {
+                    let Some(interface) = device::open() else {
+                        continue 'connection;
+                    };
+                    let mut queue = interface.bulk_in_queue(device::DATA_ENDPOINT);
+                    let transfer_size = 64;
+
+                    if reconnecting {
+                        match send_with_timeout(&interface, &Command::GetDeviceInfo).await {
+                            Some(Response::DeviceInfo(info)) => {
+                                println!("Device back, reset cause: {:?}", info.reset_cause);
+                            }
+                            // older firmware
+                            Some(_) => {}
+                            None => continue 'connection,
+                        }
+                        reconnecting = false;
                     }
 
-                    let completion =
-                        match timeout(std::time::Duration::from_secs(1), queue.next_complete())
+                    // The recorder starts out triggered like the measuring firmware, which dual interleaving can't use
+                    // and the longest sampling period can't keep up with at the top of the sweep.
+                    match send_with_timeout(&interface, &Command::SetAdcTrigger(TRIGGER)).await {
+                        Some(Response::Ok) => {}
+                        Some(Response::Error(e)) => {
+                            eprintln!("Error: device rejected trigger: {e:?}");
+                            std::process::exit(1);
+                        }
+                        Some(response) => {
+                            eprintln!("Error: unexpected response {response:?}");
+                            std::process::exit(1);
+                        }
+                        None => {
+                            println!("Device not responding, waiting for it to restart");
+                            reconnecting = true;
+                            continue 'connection;
+                        }
+                    }
+
+                    match send_with_timeout(
+                        &interface,
+                        &Command::SetFrequency {
+                            frequency_kHz: frequency_kHz as f64,
+                            adc_sampling_period: adc_sampling_period.clone(),
+                        },
+                    )
+                    .await
+                    {
+                        Some(Response::Ok) => {}
+                        Some(Response::Error(e)) => {
+                            eprintln!("Error: device rejected frequency: {e:?}");
+                            std::process::exit(1);
+                        }
+                        Some(response) => {
+                            eprintln!("Error: unexpected response {response:?}");
+                            std::process::exit(1);
+                        }
+                        None => {
+                            println!("Device not responding, waiting for it to restart");
+                            reconnecting = true;
+                            continue 'connection;
+                        }
+                    }
+
+                    if has_amplifier {
+                        match send_with_timeout(&interface, &Command::SetGain(gain)).await {
+                            Some(Response::Ok) => {}
+                            Some(Response::Error(CommandError::Unsupported)) => {
+                                println!("No amplifier on this board, sweeping at 1x only");
+                                has_amplifier = false;
+                            }
+                            Some(Response::Error(e)) => {
+                                eprintln!("Error: device rejected gain: {e:?}");
+                                std::process::exit(1);
+                            }
+                            Some(response) => {
+                                eprintln!("Error: unexpected response {response:?}");
+                                std::process::exit(1);
+                            }
+                            None => {
+                                println!("Device not responding, waiting for it to restart");
+                                reconnecting = true;
+                                continue 'connection;
+                            }
+                        }
+                    }
+                    let gain = if has_amplifier { gain } else { Gain::X1 };
+
+                    let (samples, vrefint) = 'record: loop {
+                        match send_with_timeout(&interface, &Command::Record).await {
+                            Some(Response::Ok) => {}
+                            Some(Response::Error(CommandError::Busy)) => {
+                                // still sending a recording from before we (re)connected
+                                drain(&mut queue).await;
+                                continue 'record;
+                            }
+                            Some(Response::Error(e)) => {
+                                eprintln!("Error: device rejected record: {e:?}");
+                                std::process::exit(1);
+                            }
+                            Some(response) => {
+                                eprintln!("Error: unexpected response {response:?}");
+                                std::process::exit(1);
+                            }
+                            None => {
+                                println!("Device not responding, waiting for it to restart");
+                                reconnecting = true;
+                                continue 'connection;
+                            }
+                        }
+
+                        let mut tracker =
+                            SequenceTracker::with_packets_per_capture(RECORD_NUM_PACKETS as u16);
+                        let mut samples = Vec::with_capacity(RECORD_NUM_SAMPLES);
+                        let mut vrefint = None;
+                        let mut intact = true;
+
+                        // Ends with the last packet of the recording, or with a timeout if it was dropped or damaged.
+                        loop {
+                            if queue.pending() == 0 {
+                                queue.submit(nusb::transfer::RequestBuffer::new(transfer_size));
+                            }
+
+                            let completion = match timeout(
+                                std::time::Duration::from_secs(1),
+                                queue.next_complete(),
+                            )
                             .await
-                        {
-                            Ok(completion) => completion,
-                            Err(_) => {
-                                println!("Device frozen, please reset");
+                            {
+                                Ok(completion) => completion,
+                                Err(_) => match tracker.end() {
+                                    // the device was sending, so the end of the recording went missing
+                                    Some(SequenceEvent::Gap {
+                                        capture_id,
+                                        first,
+                                        missing,
+                                        corrupted,
+                                    }) => {
+                                        println!(
+                                            "Capture {capture_id}: lost packets {first}..{} ({corrupted} corrupted)",
+                                            first.wrapping_add(missing)
+                                        );
+                                        intact = false;
+                                        break;
+                                    }
+                                    _ => {
+                                        println!(
+                                            "Device not responding, waiting for it to restart"
+                                        );
+                                        reconnecting = true;
+                                        continue 'connection;
+                                    }
+                                },
+                            };
+
+                            // e.g. the device reset while the transfer was pending
+                            if let Err(e) = completion.status {
+                                println!(
+                                    "Transfer failed ({e}), waiting for the device to restart"
+                                );
+                                reconnecting = true;
                                 continue 'connection;
                             }
-                        };
 
-                    let data = completion.data.as_slice();
+                            // a zero-length packet only ends a transfer; there's nothing in it to decode
+                            if completion.data.is_empty() {
+                                queue.submit(nusb::transfer::RequestBuffer::reuse(
+                                    completion.data,
+                                    transfer_size,
+                                ));
+                                continue;
+                            }
 
-                    // Not sure what's going on here, but after device freezes and we reset it and reconnect, we end up getting some 0 length packets.
-                    // in that case, just start over
-                    if data.len() == 0 {
-                        continue 'connection;
-                    }
+                            match DataPacket::decode(&completion.data) {
+                                Ok(packet) => {
+                                    if let Some(v) = packet.vrefint() {
+                                        vrefint = Some(v);
+                                    }
+                                    match tracker.received(packet.capture_id, packet.sequence) {
+                                        SequenceEvent::InOrder => samples.extend(packet.samples()),
+                                        SequenceEvent::NewCapture => {
+                                            samples.clear();
+                                            samples.extend(packet.samples());
+                                        }
+                                        SequenceEvent::Gap {
+                                            capture_id,
+                                            first,
+                                            missing,
+                                            corrupted,
+                                        } => {
+                                            println!(
+                                            "Capture {capture_id}: lost packets {first}..{} ({corrupted} corrupted)",
+                                            first.wrapping_add(missing)
+                                        );
+                                            intact = false;
+                                        }
+                                        SequenceEvent::Stale => {}
+                                    }
+                                }
+                                Err(e) => {
+                                    println!("Corrupted packet: {e:?}");
+                                    tracker.corrupted();
+                                    intact = false;
+                                }
+                            }
+
+                            queue.submit(nusb::transfer::RequestBuffer::reuse(
+                                completion.data,
+                                transfer_size,
+                            ));
 
-                    for chunk in data.chunks_exact(2) {
-                        if let [low, high] = chunk {
-                            let adc_value = u16::from_le_bytes([*low, *high]);
-                            samples.push(adc_value);
+                            if tracker.complete() {
+                                break;
+                            }
                         }
-                    }
-                    queue.submit(nusb::transfer::RequestBuffer::reuse(
-                        completion.data,
-                        transfer_size,
-                    ));
-                }
 
-                println!(
-                    "Recorded {} samples at {} kHz",
-                    samples.len(),
-                    frequency_kHz
-                );
-
-                for (idx, sample) in samples.iter().enumerate() {
-                    writeln!(
-                        csv_writer,
-                        "{},{},{},{}",
-                        frequency_kHz * 1000,
-                        adc_sampling_period.to_Hz(),
-                        idx,
-                        sample
-                    )?;
+                        if intact && samples.len() == RECORD_NUM_SAMPLES {
+                            if let Some(vrefint) = vrefint {
+                                break 'record (samples, vrefint);
+                            }
+                        }
+                        println!("Incomplete recording, trying again");
+                    };
+
+                    println!(
+                        "Recorded {} samples at {} kHz, gain {}",
+                        samples.len(),
+                        frequency_kHz,
+                        gain.factor()
+                    );
+
+                    // the device only accepted these settings if the ADC keeps up with them
+                    let adc_sample_rate_Hz = TRIGGER
+                        .sample_rate_Hz(frequency_kHz as f64, adc_sampling_period)
+                        .expect("device accepted the ADC settings");
+
+                    for (idx, sample) in samples.iter().enumerate() {
+                        writeln!(
+                            csv_writer,
+                            "{},{},{},{},{},{}",
+                            frequency_kHz * 1000,
+                            adc_sample_rate_Hz,
+                            idx,
+                            sample,
+                            gain.factor(),
+                            vrefint
+                        )?;
+                    }
+                    break 'connection;
                 }
-                break 'connection;
             }
         }
     }
@@ -117,12 +302,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn send_command(out_queue: &mut nusb::transfer::Queue<Vec<u8>>, command: Command) {
-    let mut buf = [0u8; 64]; // Assuming MAX_PACKET_SIZE is 64
-    if let Ok(serialized) = command.serialize(&mut buf) {
-        out_queue.submit(serialized.into());
-    } else {
-        eprintln!("Error: Failed to serialize command");
-        std::process::exit(1);
+/// Throw away sample data until the device goes quiet.
+async fn drain(queue: &mut nusb::transfer::Queue<nusb::transfer::RequestBuffer>) {
+    loop {
+        if queue.pending() == 0 {
+            queue.submit(nusb::transfer::RequestBuffer::new(64));
+        }
+
+        match timeout(std::time::Duration::from_millis(200), queue.next_complete()).await {
+            Ok(completion) => {
+                queue.submit(nusb::transfer::RequestBuffer::reuse(completion.data, 64))
+            }
+            Err(_) => return,
+        }
     }
 }
+
+/// `send_command`, or `None` if the device doesn't answer in time.
+async fn send_with_timeout(interface: &Interface, command: &Command) -> Option<Response> {
+    timeout(
+        std::time::Duration::from_secs(1),
+        send_command(interface, command),
+    )
+    .await
+    .ok()
+    .flatten()
+}
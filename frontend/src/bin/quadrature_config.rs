@@ -0,0 +1,48 @@
+#![allow(non_snake_case)]
+
+// Sets the resolution, maximum edge rate and index spacing of the "quadrature" firmware's output, and saves it to flash.
+// Works with the "recorder" firmware; flash "quadrature" afterwards.
+//
+//     cargo run --release --bin quadrature_config -- <resolution_mm> <max_edge_rate_Hz> [index_every]
+
+use frontend::device::{self, send_command};
+use futures_lite::future::block_on;
+use schema::*;
+
+fn usage() -> ! {
+    eprintln!("Usage: quadrature_config <resolution_mm> <max_edge_rate_Hz> [index_every]");
+    eprintln!("max_edge_rate_Hz is at most {MAX_QUADRATURE_EDGE_RATE_Hz}; index_every is in counts, 0 (the default) for no index");
+    std::process::exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (resolution_mm, max_edge_rate_Hz, index_every) = match &args[..] {
+        [r, m] => (r, m, "0"),
+        [r, m, i] => (r, m, i.as_str()),
+        _ => usage(),
+    };
+
+    let config = QuadratureConfig {
+        resolution_mm: resolution_mm.parse().unwrap_or_else(|_| usage()),
+        max_edge_rate_Hz: max_edge_rate_Hz.parse().unwrap_or_else(|_| usage()),
+        index_every: index_every.parse().unwrap_or_else(|_| usage()),
+    };
+    if !config.is_valid() {
+        usage();
+    }
+
+    let interface = device::open().expect("device should be connected");
+
+    for command in [Command::SetQuadrature(config), Command::SaveConfig] {
+        match block_on(send_command(&interface, &command)) {
+            Some(Response::Ok) => {}
+            response => {
+                eprintln!("Error: {command:?} failed: {response:?}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    println!("Saved");
+}
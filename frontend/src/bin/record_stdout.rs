@@ -1,46 +1,70 @@
 #![allow(non_snake_case)]
 
-use schema::Command;
+use frontend::device::{self, send_command};
+use schema::*;
+use tokio::time::timeout;
 
-fn main() {
+#[tokio::main]
+async fn main() {
     // Parse command-line argument for frequency
     let frequency_kHz = parse_frequency_arg();
 
-    let di = nusb::list_devices()
-        .unwrap()
-        .find(|d| d.vendor_id() == 0xc0de && d.product_id() == 0xcafe)
-        .expect("device should be connected");
-
-    eprintln!("Device info: {di:?}");
-
-    let device = di.open().unwrap();
-
-    let interface = device.claim_interface(0).unwrap();
-
-    // Send frequency command to firmware
-    let endpoint_addr = 1;
-    let mut out_queue = interface.bulk_out_queue(endpoint_addr);
-
-    send_command(&mut out_queue, Command::SetFrequency { frequency_kHz });
-
-    send_command(&mut out_queue, Command::Record);
+    let interface = device::open().expect("device should be connected");
+
+    // Send frequency command to firmware, then start recording
+    let commands = [
+        Command::SetFrequency {
+            frequency_kHz,
+            adc_sampling_period: AdcSamplingPeriod::CYCLES41_5,
+        },
+        Command::Record,
+    ];
+    for command in commands {
+        match send_command(&interface, &command).await {
+            Some(Response::Ok) => {}
+            response => {
+                eprintln!("Error: device rejected {command:?}: {response:?}");
+                std::process::exit(1);
+            }
+        }
+    }
 
     // Read and print ADC values
-    let mut queue = interface.bulk_in_queue(0x80 + endpoint_addr);
+    let mut queue = interface.bulk_in_queue(device::DATA_ENDPOINT);
     let transfer_size = 64;
+    let mut tracker = SequenceTracker::with_packets_per_capture(RECORD_NUM_PACKETS as u16);
 
-    loop {
+    // Ends with the last packet of the recording, or when the stream stops without it.
+    while !tracker.complete() {
         while queue.pending() < 1 {
             queue.submit(nusb::transfer::RequestBuffer::new(transfer_size));
         }
 
-        let completion = futures_lite::future::block_on(queue.next_complete());
-
-        let data = completion.data.as_slice();
-        for chunk in data.chunks_exact(2) {
-            if let [low, high] = chunk {
-                let adc_value = u16::from_le_bytes([*low, *high]);
-                println!("{}", adc_value);
+        let Ok(completion) =
+            timeout(std::time::Duration::from_secs(1), queue.next_complete()).await
+        else {
+            if let Some(event) = tracker.end() {
+                report(event);
+            }
+            break;
+        };
+
+        match DataPacket::decode(completion.data.as_slice()) {
+            Ok(packet) => {
+                report(tracker.received(packet.capture_id, packet.sequence));
+                if let Some(vrefint) = packet.vrefint() {
+                    eprintln!(
+                        "VREFINT: {vrefint} (full scale {} mV)",
+                        to_millivolts(4095, vrefint)
+                    );
+                }
+                for adc_value in packet.samples() {
+                    println!("{}", adc_value);
+                }
+            }
+            Err(e) => {
+                eprintln!("Corrupted packet: {e:?}");
+                tracker.corrupted();
             }
         }
         queue.submit(nusb::transfer::RequestBuffer::reuse(
@@ -58,20 +82,25 @@ fn parse_frequency_arg() -> f64 {
     }
 
     match args[1].parse::<f64>() {
-        Ok(freq) if freq >= 0.0 && freq <= 100.0 => freq,
+        Ok(freq) if (MIN_FREQUENCY_kHz..=100.0).contains(&freq) => freq,
         _ => {
-            eprintln!("Error: Frequency must be a number between 0 and 100 kHz");
+            eprintln!("Error: Frequency must be a number between 1 and 100 kHz");
             std::process::exit(1);
         }
     }
 }
 
-fn send_command(out_queue: &mut nusb::transfer::Queue<Vec<u8>>, command: Command) {
-    let mut buf = [0u8; 64]; // Assuming MAX_PACKET_SIZE is 64
-    if let Ok(serialized) = command.serialize(&mut buf) {
-        out_queue.submit(serialized.into());
-    } else {
-        eprintln!("Error: Failed to serialize command");
-        std::process::exit(1);
+fn report(event: SequenceEvent) {
+    if let SequenceEvent::Gap {
+        capture_id,
+        first,
+        missing,
+        corrupted,
+    } = event
+    {
+        eprintln!(
+            "Capture {capture_id}: lost packets {first}..{} ({corrupted} corrupted)",
+            first.wrapping_add(missing)
+        );
     }
 }
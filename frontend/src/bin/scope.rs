@@ -1,8 +1,11 @@
 use eframe::egui;
 use egui_plot::{Line, Plot, PlotPoints};
 use flume::{Receiver, Sender};
+use frontend::device::{self, send_command};
+use futures_lite::future::block_on;
 use nusb::transfer::{Queue, RequestBuffer};
-use schema::Command;
+use nusb::Interface;
+use schema::*;
 use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -11,19 +14,8 @@ const MAX_SAMPLES: usize = 1000;
 const MAX_PACKET_SIZE: usize = 64;
 
 fn main() -> Result<(), eframe::Error> {
-    let di = nusb::list_devices()
-        .unwrap()
-        .find(|d| d.vendor_id() == 0xc0de && d.product_id() == 0xcafe)
-        .expect("device should be connected");
-
-    eprintln!("Device info: {di:?}");
-
-    let device = di.open().unwrap();
-    let interface = device.claim_interface(0).unwrap();
-
-    let endpoint_addr = 1;
-    let in_queue = interface.bulk_in_queue(0x80 + endpoint_addr);
-    let out_queue = interface.bulk_out_queue(endpoint_addr);
+    let interface = device::open().expect("device should be connected");
+    let in_queue = interface.bulk_in_queue(device::DATA_ENDPOINT);
 
     let samples = Arc::new(Mutex::new(VecDeque::with_capacity(MAX_SAMPLES)));
     let samples_clone = Arc::clone(&samples);
@@ -35,7 +27,7 @@ fn main() -> Result<(), eframe::Error> {
 
     // Start USB reading thread
     thread::spawn(move || {
-        usb_reading_thread(in_queue, out_queue, samples_clone, threshold_clone, rx);
+        usb_reading_thread(in_queue, interface, samples_clone, threshold_clone, rx);
     });
 
     let options = eframe::NativeOptions::default();
@@ -54,20 +46,21 @@ fn main() -> Result<(), eframe::Error> {
 
 fn usb_reading_thread(
     mut in_queue: Queue<RequestBuffer>,
-    mut out_queue: Queue<Vec<u8>>,
+    interface: Interface,
     samples: Arc<Mutex<VecDeque<u16>>>,
     threshold: Arc<Mutex<Option<u16>>>,
     rx: Receiver<Command>, // Add this parameter
 ) {
     let mut triggered = false;
     let mut prev_value = 0;
+    let mut continuity = ContinuityChecker::new();
 
     loop {
         // Send any pending commands
         if let Ok(command) = rx.try_recv() {
-            let mut buf = [0u8; MAX_PACKET_SIZE];
-            if let Ok(serialized) = command.serialize(&mut buf) {
-                out_queue.submit(serialized.into());
+            match block_on(send_command(&interface, &command)) {
+                Some(Response::Ok) => {}
+                response => eprintln!("Device rejected {command:?}: {response:?}"),
             }
         }
 
@@ -75,35 +68,67 @@ fn usb_reading_thread(
             in_queue.submit(nusb::transfer::RequestBuffer::new(MAX_PACKET_SIZE));
         }
 
-        let completion = futures_lite::future::block_on(in_queue.next_complete());
-        let data = completion.data.as_slice();
+        let completion = block_on(in_queue.next_complete());
+        let packet = match DataPacket::decode(completion.data.as_slice()) {
+            Ok(packet) => packet,
+            Err(e) => {
+                eprintln!("Corrupted packet: {e:?}");
+                continuity.corrupted();
+                in_queue.submit(RequestBuffer::reuse(completion.data, MAX_PACKET_SIZE));
+                continue;
+            }
+        };
+
+        match continuity.check(&packet) {
+            Continuity::Continuous => {}
+            Continuity::Stale => {
+                in_queue.submit(RequestBuffer::reuse(completion.data, MAX_PACKET_SIZE));
+                continue;
+            }
+            Continuity::Break {
+                lost_packets,
+                overrun,
+            } => {
+                eprintln!(
+                    "Stream break: {lost_packets} packets lost, device overrun: {overrun} ({} breaks, {} device overruns so far)",
+                    continuity.breaks, continuity.device_overruns
+                );
+                // Don't splice samples from either side of the break into one trace.
+                if triggered {
+                    triggered = false;
+                    samples.lock().unwrap().clear();
+                }
+            }
+        }
+
+        // samples can't be converted to millivolts until the device has sent its VREFINT reading
+        let Some(vrefint) = continuity.vrefint else {
+            in_queue.submit(RequestBuffer::reuse(completion.data, MAX_PACKET_SIZE));
+            continue;
+        };
 
         let threshold = *threshold.lock().unwrap();
         let mut samples = samples.lock().unwrap();
-        for chunk in data.chunks_exact(2) {
-            if let [low, high] = chunk {
-                let adc_value = u16::from_le_bytes([*low, *high]);
-
-                match threshold {
-                    Some(threshold) => {
-                        if triggered {
-                            samples.push_back(adc_value);
-                            if samples.len() >= MAX_SAMPLES {
-                                triggered = false;
-                            }
-                        } else {
-                            if prev_value <= threshold && adc_value > threshold {
-                                triggered = true;
-                                samples.clear();
-                            }
-                            prev_value = adc_value;
-                        }
-                    }
-                    None => {
+        for adc_value in packet.samples().map(|s| to_millivolts(s, vrefint)) {
+            match threshold {
+                Some(threshold) => {
+                    if triggered {
                         samples.push_back(adc_value);
                         if samples.len() >= MAX_SAMPLES {
-                            samples.pop_front();
+                            triggered = false;
                         }
+                    } else {
+                        if prev_value <= threshold && adc_value > threshold {
+                            triggered = true;
+                            samples.clear();
+                        }
+                        prev_value = adc_value;
+                    }
+                }
+                None => {
+                    samples.push_back(adc_value);
+                    if samples.len() >= MAX_SAMPLES {
+                        samples.pop_front();
                     }
                 }
             }
@@ -146,6 +171,7 @@ impl eframe::App for ADCApp {
                 self.tx
                     .send(Command::SetFrequency {
                         frequency_kHz: self.frequency_kHz,
+                        adc_sampling_period: AdcSamplingPeriod::CYCLES239_5,
                     })
                     .unwrap()
             }
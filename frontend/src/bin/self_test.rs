@@ -0,0 +1,49 @@
+// Runs the emitter self-test and prints what the pickup saw from each pad and adjacent pair.
+// Works with the "self_test" firmware; leave the slider on the scale while it runs.
+// Exits non-zero if any pad or pair is out of line with the rest.
+
+use frontend::device::{self, send_command};
+use futures_lite::future::block_on;
+use schema::*;
+
+fn main() {
+    let interface = device::open().expect("device should be connected");
+
+    let report = match block_on(send_command(&interface, &Command::SelfTest)) {
+        Some(Response::SelfTest(report)) => report,
+        response => {
+            eprintln!("Error: device didn't send a self-test report: {response:?}");
+            std::process::exit(1);
+        }
+    };
+
+    println!("Gain {:?}", report.gain);
+    println!("pad  amplitude  status   | pair  amplitude  status");
+    for (i, (pad, pair)) in report.pads.iter().zip(&report.pairs).enumerate() {
+        println!(
+            "{i:>3}  {:>9.1}  {:<8} | {i}+{}  {:>9.1}  {:?}",
+            pad.amplitude,
+            format!("{:?}", pad.status),
+            (i + 1) % SELF_TEST_PADS,
+            pair.amplitude,
+            pair.status
+        );
+    }
+
+    if report.passed() {
+        println!("Passed");
+    } else {
+        if report
+            .pads
+            .iter()
+            .chain(&report.pairs)
+            .any(|r| r.status == PadStatus::Clipped)
+        {
+            println!(
+                "Some captures clipped; lower the gain with Command::SetGain and run it again"
+            );
+        }
+        println!("Failed");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,125 @@
+#![allow(non_snake_case)]
+#![allow(non_upper_case_globals)]
+
+// Runs the firmware's measurement pipeline against a simulated scale, moving at a constant speed.
+// Prints the true and measured position of each capture as CSV.
+
+use calipertron_core::measure::{self, Emitter, Sampler};
+use calipertron_core::PhaseAccumulator;
+use schema::DeviceConfig;
+use std::cell::Cell;
+use std::f32::consts::PI;
+use std::rc::Rc;
+use std::time::Duration;
+
+// Same timing as the `local` firmware, see firmware/build.rs
+const PDM_FREQUENCY_Hz: f32 = 222_000.;
+const PDM_LENGTH: usize = 128;
+const ADC_DECIMATION: usize = 2;
+const NUM_SAMPLES: usize = 128;
+
+const SIGNAL_FREQUENCY_Hz: f32 = PDM_FREQUENCY_Hz / PDM_LENGTH as f32;
+const SAMPLING_FREQUENCY_Hz: f32 = PDM_FREQUENCY_Hz / ADC_DECIMATION as f32;
+
+const NUM_CAPTURES: usize = 200;
+/// How far the scale moves between captures.
+const STEP_mm: f32 = 0.05;
+
+struct SimulatedEmitter {
+    running: Rc<Cell<bool>>,
+}
+
+impl Emitter for SimulatedEmitter {
+    fn start(&mut self) {
+        self.running.set(true);
+    }
+
+    async fn stop(&mut self) {
+        self.running.set(false);
+    }
+}
+
+/// Pickup over a scale at `position_mm`, seeing the emitter waveform phase shifted by the position within the scale pitch.
+struct SimulatedPickup {
+    running: Rc<Cell<bool>>,
+    position_mm: Rc<Cell<f32>>,
+    pitch_mm: f32,
+    amplitude: f32,
+    noise: f32,
+    rng: u32,
+}
+
+impl SimulatedPickup {
+    /// Uniform noise in [-1, 1) from a xorshift generator, so runs are repeatable.
+    fn next_noise(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+impl Sampler for SimulatedPickup {
+    async fn capture(&mut self, buf: &mut [u16]) {
+        // armed now, but nothing to see until the emitter starts
+        tokio::task::yield_now().await;
+        assert!(
+            self.running.get(),
+            "emitter should be running during a capture"
+        );
+
+        let offset = 2.0 * PI * self.position_mm.get() / self.pitch_mm;
+        for (i, sample) in buf.iter_mut().enumerate() {
+            let t = i as f32 / SAMPLING_FREQUENCY_Hz;
+            let signal = (2.0 * PI * SIGNAL_FREQUENCY_Hz * t - offset).cos();
+            let value = 2048.0 + self.amplitude * signal + self.noise * self.next_noise();
+            *sample = value.clamp(0.0, 4095.0) as u16;
+        }
+
+        tokio::time::sleep(Duration::from_secs_f32(
+            buf.len() as f32 / SAMPLING_FREQUENCY_Hz,
+        ))
+        .await;
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let config = DeviceConfig::default();
+
+    let sine_cosine_table: Vec<(f32, f32)> = (0..NUM_SAMPLES)
+        .map(|i| {
+            let angle = 2.0 * PI * SIGNAL_FREQUENCY_Hz * (i as f32 / SAMPLING_FREQUENCY_Hz);
+            (angle.sin(), angle.cos())
+        })
+        .collect();
+
+    let running = Rc::new(Cell::new(false));
+    let position_mm = Rc::new(Cell::new(0.0));
+    let mut emitter = SimulatedEmitter {
+        running: running.clone(),
+    };
+    let mut pickup = SimulatedPickup {
+        running,
+        position_mm: position_mm.clone(),
+        pitch_mm: config.scale_pitch_mm,
+        amplitude: 500.0,
+        noise: 50.0,
+        rng: 0x1234_5678,
+    };
+
+    let mut phase_accumulator = PhaseAccumulator::new(0.0, 0.1);
+    let mut buf = [0u16; NUM_SAMPLES];
+
+    println!("true_position_mm,measured_position_mm");
+    for n in 0..NUM_CAPTURES {
+        position_mm.set(n as f32 * STEP_mm);
+
+        measure::capture(&mut emitter, &mut pickup, &mut buf).await;
+        let phase = measure::phase(&buf, &sine_cosine_table);
+
+        phase_accumulator.update(phase);
+        let measured_mm = phase_accumulator.unwrapped_phase * config.scale_pitch_mm / (2.0 * PI);
+        println!("{},{}", position_mm.get(), measured_mm);
+    }
+}
@@ -1,44 +1,63 @@
 #![allow(non_snake_case)]
 
-use schema::Command;
+use frontend::device::{self, send_command};
+use futures_lite::future::block_on;
+use schema::*;
 
 fn main() {
     // Parse command-line argument for frequency
     let frequency_kHz = parse_frequency_arg();
 
-    let di = nusb::list_devices()
-        .unwrap()
-        .find(|d| d.vendor_id() == 0xc0de && d.product_id() == 0xcafe)
-        .expect("device should be connected");
-
-    eprintln!("Device info: {di:?}");
-
-    let device = di.open().unwrap();
-
-    let interface = device.claim_interface(0).unwrap();
+    let interface = device::open().expect("device should be connected");
 
     // Send frequency command to firmware
-    let endpoint_addr = 1;
-    let mut out_queue = interface.bulk_out_queue(endpoint_addr);
-    send_frequency_command(&mut out_queue, frequency_kHz);
+    let command = Command::SetFrequency {
+        frequency_kHz,
+        adc_sampling_period: AdcSamplingPeriod::CYCLES239_5,
+    };
+    match block_on(send_command(&interface, &command)) {
+        Some(Response::Ok) => {}
+        response => {
+            eprintln!("Error: device rejected frequency: {response:?}");
+            std::process::exit(1);
+        }
+    }
 
     // Read and print ADC values
-    let mut queue = interface.bulk_in_queue(0x80 + endpoint_addr);
+    let mut queue = interface.bulk_in_queue(device::DATA_ENDPOINT);
     let transfer_size = 64;
+    let mut continuity = ContinuityChecker::new();
 
     loop {
         while queue.pending() < 1 {
             queue.submit(nusb::transfer::RequestBuffer::new(transfer_size));
         }
 
-        let completion = futures_lite::future::block_on(queue.next_complete());
+        let completion = block_on(queue.next_complete());
 
-        let data = completion.data.as_slice();
-        for chunk in data.chunks_exact(2) {
-            if let [low, high] = chunk {
-                let adc_value = u16::from_le_bytes([*low, *high]);
-                //println!("ADC value: {} mV", adc_value);
-                println!("{}", adc_value);
+        match DataPacket::decode(completion.data.as_slice()) {
+            Ok(packet) => {
+                match continuity.check(&packet) {
+                    Continuity::Continuous => {}
+                    Continuity::Stale => continue,
+                    Continuity::Break {
+                        lost_packets,
+                        overrun,
+                    } => eprintln!(
+                        "Stream break: {lost_packets} packets lost, device overrun: {overrun} ({} device overruns so far)",
+                        continuity.device_overruns
+                    ),
+                }
+                // samples can't be converted to millivolts until the device has sent its VREFINT reading
+                if let Some(vrefint) = continuity.vrefint {
+                    for adc_value in packet.samples() {
+                        println!("{}", to_millivolts(adc_value, vrefint));
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("Corrupted packet: {e:?}");
+                continuity.corrupted();
             }
         }
         queue.submit(nusb::transfer::RequestBuffer::reuse(
@@ -56,21 +75,10 @@ fn parse_frequency_arg() -> f64 {
     }
 
     match args[1].parse::<f64>() {
-        Ok(freq) if freq >= 0.0 && freq <= 100.0 => freq,
+        Ok(freq) if (MIN_FREQUENCY_kHz..=100.0).contains(&freq) => freq,
         _ => {
-            eprintln!("Error: Frequency must be a number between 0 and 100 kHz");
+            eprintln!("Error: Frequency must be a number between 1 and 100 kHz");
             std::process::exit(1);
         }
     }
 }
-
-fn send_frequency_command(out_queue: &mut nusb::transfer::Queue<Vec<u8>>, frequency_kHz: f64) {
-    let command = Command::SetFrequency { frequency_kHz };
-    let mut buf = [0u8; 64]; // Assuming MAX_PACKET_SIZE is 64
-    if let Ok(serialized) = command.serialize(&mut buf) {
-        out_queue.submit(serialized.into());
-    } else {
-        eprintln!("Error: Failed to serialize frequency command");
-        std::process::exit(1);
-    }
-}
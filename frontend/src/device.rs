@@ -0,0 +1,44 @@
+// Talking to the custom USB class firmware ("recorder", "self_test", ...).
+
+use nusb::transfer::RequestBuffer;
+use nusb::Interface;
+use schema::{Command, Response, MAX_RESPONSE_SIZE};
+
+const VENDOR_ID: u16 = 0xc0de;
+const PRODUCT_ID: u16 = 0xcafe;
+
+// Commands go out on bulk endpoint 1 and responses come back on 0x82, separate from sample data.
+const COMMAND_ENDPOINT: u8 = 1;
+const RESPONSE_ENDPOINT: u8 = 0x82;
+
+/// Bulk IN endpoint the device sends `schema::DataPacket`s on.
+pub const DATA_ENDPOINT: u8 = 0x81;
+
+/// Claims the connected device's interface, or `None` if it isn't connected (or can't be opened yet, e.g. while it restarts).
+pub fn open() -> Option<Interface> {
+    let di = nusb::list_devices()
+        .ok()?
+        .find(|d| d.vendor_id() == VENDOR_ID && d.product_id() == PRODUCT_ID)?;
+    di.open().ok()?.claim_interface(0).ok()
+}
+
+/// Sends `command` and waits for the device's response; the device won't take another command until it's been read.
+/// `None` if a transfer fails (e.g. the device went away) or the response doesn't parse.
+pub async fn send_command(interface: &Interface, command: &Command) -> Option<Response> {
+    let mut buf = [0u8; 64];
+    let serialized = command
+        .serialize(&mut buf)
+        .expect("command should serialize");
+    interface
+        .bulk_out(COMMAND_ENDPOINT, serialized.to_vec())
+        .await
+        .into_result()
+        .ok()?;
+
+    let response = interface
+        .bulk_in(RESPONSE_ENDPOINT, RequestBuffer::new(MAX_RESPONSE_SIZE))
+        .await
+        .into_result()
+        .ok()?;
+    Response::deserialize(&response)
+}
@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+
+use calipertron_core::button::Gesture;
+
+////////////////////////
+// Button bindings
+//
+// The saved `ButtonBindings` say what each gesture from `calipertron_core::button` does.
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, defmt::Format)]
+pub enum ButtonAction {
+    None,
+    /// Zero the position where the slider is now.
+    Zero,
+    /// Freeze the reading until the next hold (`MeasurementMode::Hold`).
+    Hold,
+    /// Step through mm, decimal inches and fractional inches.
+    ToggleUnits,
+    /// Output the current measurement, e.g. type it in the `keyboard` firmware.
+    Send,
+    /// Step through the display modes: absolute, hold, min/max, relative.
+    NextMode,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, defmt::Format)]
+pub struct ButtonBindings {
+    pub short: ButtonAction,
+    pub long: ButtonAction,
+    pub double: ButtonAction,
+}
+
+impl Default for ButtonBindings {
+    fn default() -> Self {
+        // zeroing keeps the single press it always had
+        ButtonBindings {
+            short: ButtonAction::Zero,
+            long: ButtonAction::ToggleUnits,
+            double: ButtonAction::Send,
+        }
+    }
+}
+
+impl ButtonBindings {
+    pub fn action(&self, gesture: Gesture) -> ButtonAction {
+        match gesture {
+            Gesture::Short => self.short,
+            Gesture::Long => self.long,
+            Gesture::Double => self.double,
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use calipertron_core::within_pitch;
+use serde::{Deserialize, Serialize};
+
+use crate::{crc32, AdcSamplingPeriod, AdcTrigger, ButtonBindings, Gain, KeyboardConfig};
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, defmt::Format)]
+pub enum Units {
+    Millimeter,
+    Inch,
+    /// Inches to the nearest 1/64, e.g. "1 3/16".
+    FractionalInch,
+}
+
+impl Units {
+    /// Unit for `calipertron_core::units`, which does the formatting.
+    pub fn unit(self) -> calipertron_core::units::Unit {
+        use calipertron_core::units::Unit;
+        match self {
+            Units::Millimeter => Unit::Millimeter,
+            Units::Inch => Unit::Inch,
+            Units::FractionalInch => Unit::FractionalInch,
+        }
+    }
+
+    /// Next units when toggling through them with the button.
+    pub fn next(self) -> Units {
+        match self {
+            Units::Millimeter => Units::Inch,
+            Units::Inch => Units::FractionalInch,
+            Units::FractionalInch => Units::Millimeter,
+        }
+    }
+}
+
+pub const CALIBRATION_POINTS: usize = 8;
+
+/// Most PDM frequencies a measurement can hop across.
+pub const MAX_HOP_FREQUENCIES: usize = 4;
+
+/// PDM frequencies in kHz, see `DeviceConfig::hop_frequencies_kHz`.
+pub type HopFrequencies = heapless::Vec<f32, MAX_HOP_FREQUENCIES>;
+
+/// Fastest quadrature output the firmware will generate, in edges per second.
+/// Edges are timed by the 32768 Hz time driver, so this is kept to a few ticks per edge.
+#[allow(non_upper_case_globals)]
+pub const MAX_QUADRATURE_EDGE_RATE_Hz: u32 = 8192;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, defmt::Format)]
+#[allow(non_snake_case)]
+pub struct QuadratureConfig {
+    /// Distance per count, i.e. per edge on A or B.
+    pub resolution_mm: f32,
+    /// Receivers miss edges closer together than they can count, so bigger moves are spread out at this rate.
+    /// At most `MAX_QUADRATURE_EDGE_RATE_Hz`.
+    pub max_edge_rate_Hz: u32,
+    /// Counts between index pulses, 0 for no index.
+    pub index_every: u16,
+}
+
+impl Default for QuadratureConfig {
+    fn default() -> Self {
+        // a typical 5um glass scale
+        QuadratureConfig {
+            resolution_mm: 0.005,
+            max_edge_rate_Hz: MAX_QUADRATURE_EDGE_RATE_Hz,
+            index_every: 0,
+        }
+    }
+}
+
+impl QuadratureConfig {
+    pub fn is_valid(&self) -> bool {
+        // written this way round so NaN is rejected too
+        (0.0001..=10.).contains(&self.resolution_mm)
+            && (1..=MAX_QUADRATURE_EDGE_RATE_Hz).contains(&self.max_edge_rate_Hz)
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, defmt::Format)]
+#[allow(non_snake_case)]
+pub struct DeviceConfig {
+    pub frequency_kHz: f64,
+    pub adc_sampling_period: AdcSamplingPeriod,
+    pub adc_trigger: AdcTrigger,
+    /// MCP6S21 amplifier gain; firmware with automatic gain control starts from this.
+    pub gain: Gain,
+    /// Distance covered by one full phase cycle across all 8 emission pads.
+    pub scale_pitch_mm: f32,
+    pub units: Units,
+    /// Position that reads as zero. Only the part within one scale pitch goes into a flash record (see `calipertron_core::within_pitch`):
+    /// the position count restarts at every power-up, so whole pitches wouldn't mean anything after a reset.
+    pub zero_offset_mm: f32,
+    /// Phase correction (radians) at evenly spaced points across one phase cycle, starting at -PI.
+    pub calibration: [f32; CALIBRATION_POINTS],
+    /// How the keyboard firmware types measurements (in `units`).
+    pub keyboard: KeyboardConfig,
+    pub quadrature: QuadratureConfig,
+    /// What the button's short, long and double presses do.
+    pub buttons: ButtonBindings,
+    /// PDM frequencies the measuring firmware cycles through, one capture each, combined by `calipertron_core::hopping`.
+    /// Empty to measure at the build.rs frequency only.
+    pub hop_frequencies_kHz: HopFrequencies,
+    /// Scan for background noise at startup (`NoiseScanReport`) and measure at the quietest frequency rather than the build.rs one.
+    /// Only used when not hopping.
+    pub pick_quiet_frequency: bool,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        DeviceConfig {
+            frequency_kHz: 222.,
+            adc_sampling_period: AdcSamplingPeriod::CYCLES41_5,
+            adc_trigger: AdcTrigger::MEASURE,
+            gain: Gain::X1,
+            // 9.4mm spacing across all 8 emission pads on the v1.1 PCB.
+            scale_pitch_mm: 9.4,
+            units: Units::Millimeter,
+            zero_offset_mm: 0.,
+            calibration: [0.; CALIBRATION_POINTS],
+            keyboard: KeyboardConfig::default(),
+            quadrature: QuadratureConfig::default(),
+            buttons: ButtonBindings::default(),
+            hop_frequencies_kHz: HopFrequencies::new(),
+            pick_quiet_frequency: false,
+        }
+    }
+}
+
+////////////////////////
+// Flash record format
+//
+// | magic (2) | version (1) | payload len (1) | sequence (4) | payload (postcard) | crc32 (4) | 0xFF padding |
+//
+// The CRC covers everything before it. Padding matches erased flash, so a record can be written in one go after a page erase.
+
+pub const CONFIG_RECORD_SIZE: usize = 128;
+// Version 2 added `adc_trigger`, version 3 `gain`, version 4 `keyboard`, version 5 `quadrature`, version 6 `buttons`,
+// version 7 `hop_frequencies_kHz`, version 8 `pick_quiet_frequency`.
+// Older records are ignored and the defaults used.
+pub const CONFIG_RECORD_VERSION: u8 = 8;
+
+const CONFIG_RECORD_MAGIC: u16 = 0xCA1F;
+const HEADER_SIZE: usize = 8;
+const CRC_SIZE: usize = 4;
+
+#[derive(PartialEq, Debug, Clone, Copy, defmt::Format)]
+pub enum ConfigError {
+    /// Record area is blank (erased flash).
+    Erased,
+    BadMagic,
+    UnsupportedVersion(u8),
+    BadCrc,
+    /// Header and CRC are fine but the payload couldn't be (de)serialized.
+    Malformed,
+}
+
+impl DeviceConfig {
+    /// ADC sample rate this config gives, or `None` if the ADC can't keep up with its trigger.
+    #[allow(non_snake_case)]
+    pub fn adc_sample_rate_Hz(&self) -> Option<f64> {
+        self.adc_trigger
+            .sample_rate_Hz(self.frequency_kHz, &self.adc_sampling_period)
+    }
+
+    /// Write this config as a complete flash record into `buf`, with the zero offset reduced to within one scale pitch.
+    /// When there are several valid records, the one with the highest `sequence` wins.
+    pub fn to_record(
+        &self,
+        sequence: u32,
+        buf: &mut [u8; CONFIG_RECORD_SIZE],
+    ) -> Result<(), ConfigError> {
+        buf.fill(0xFF);
+
+        let stored = DeviceConfig {
+            zero_offset_mm: within_pitch(self.zero_offset_mm, self.scale_pitch_mm),
+            ..self.clone()
+        };
+        let payload_len = postcard::to_slice(
+            &stored,
+            &mut buf[HEADER_SIZE..CONFIG_RECORD_SIZE - CRC_SIZE],
+        )
+        .map_err(|_| ConfigError::Malformed)?
+        .len();
+
+        buf[0..2].copy_from_slice(&CONFIG_RECORD_MAGIC.to_le_bytes());
+        buf[2] = CONFIG_RECORD_VERSION;
+        buf[3] = payload_len as u8;
+        buf[4..8].copy_from_slice(&sequence.to_le_bytes());
+
+        let crc_offset = HEADER_SIZE + payload_len;
+        let crc = crc32(&buf[..crc_offset]);
+        buf[crc_offset..crc_offset + CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+
+        Ok(())
+    }
+
+    /// Parse a flash record, returning its sequence number and config.
+    pub fn from_record(record: &[u8]) -> Result<(u32, DeviceConfig), ConfigError> {
+        if record.len() < HEADER_SIZE + CRC_SIZE {
+            return Err(ConfigError::Malformed);
+        }
+
+        if record[..HEADER_SIZE].iter().all(|&b| b == 0xFF) {
+            return Err(ConfigError::Erased);
+        }
+
+        if u16::from_le_bytes([record[0], record[1]]) != CONFIG_RECORD_MAGIC {
+            return Err(ConfigError::BadMagic);
+        }
+
+        let version = record[2];
+        if version != CONFIG_RECORD_VERSION {
+            return Err(ConfigError::UnsupportedVersion(version));
+        }
+
+        let crc_offset = HEADER_SIZE + record[3] as usize;
+        if crc_offset + CRC_SIZE > record.len() {
+            return Err(ConfigError::Malformed);
+        }
+
+        let mut stored_crc = [0u8; CRC_SIZE];
+        stored_crc.copy_from_slice(&record[crc_offset..crc_offset + CRC_SIZE]);
+        if crc32(&record[..crc_offset]) != u32::from_le_bytes(stored_crc) {
+            return Err(ConfigError::BadCrc);
+        }
+
+        let sequence = u32::from_le_bytes([record[4], record[5], record[6], record[7]]);
+        let mut config: DeviceConfig = postcard::from_bytes(&record[HEADER_SIZE..crc_offset])
+            .map_err(|_| ConfigError::Malformed)?;
+        // records written before offsets were reduced may hold whole pitches too
+        config.zero_offset_mm = within_pitch(config.zero_offset_mm, config.scale_pitch_mm);
+
+        Ok((sequence, config))
+    }
+}
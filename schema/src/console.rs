@@ -0,0 +1,218 @@
+use core::fmt::Write;
+
+use crate::{Command, MeasurementMode, Units};
+
+////////////////////////
+// Line-oriented text console, for talking to the device from a serial terminal (screen, minicom, ...)
+//
+// Input is echoed as it's typed and handled a line at a time; output lines end in "\r\n" since terminals don't translate "\n".
+
+pub const CONSOLE_HELP: &str = "\
+Commands:\r
+  help              this text\r
+  zero              zero the position here\r
+  units <mm|inch|frac>\r
+                    units positions are printed in\r
+  mode <abs|hold|minmax|rel>\r
+                    display mode; repeat to restart it\r
+  freq <kHz>        PDM timer frequency\r
+  stream <on|off>   print positions continuously\r
+  record            print the raw ADC samples of one capture\r
+  save              save settings and zero to flash\r
+";
+
+#[derive(PartialEq, Debug, Clone, Copy, defmt::Format)]
+#[allow(non_snake_case)]
+pub enum ConsoleCommand {
+    Help,
+    Zero,
+    Units(Units),
+    Mode(MeasurementMode),
+    Frequency { frequency_kHz: f64 },
+    Stream(bool),
+    Record,
+    Save,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, defmt::Format)]
+pub enum ParseError {
+    UnknownCommand,
+    MissingArgument,
+    InvalidArgument,
+    TooManyArguments,
+    /// Frequency outside `MIN_FREQUENCY_kHz..=MAX_FREQUENCY_kHz`.
+    OutOfRange,
+}
+
+impl ParseError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            ParseError::UnknownCommand => "unknown command, try `help`",
+            ParseError::MissingArgument => "missing argument, try `help`",
+            ParseError::InvalidArgument => "invalid argument, try `help`",
+            ParseError::TooManyArguments => "too many arguments, try `help`",
+            ParseError::OutOfRange => "frequency out of range",
+        }
+    }
+}
+
+/// Parse one line of console input. Blank lines parse to `None`.
+/// Command names and arguments are case insensitive.
+#[allow(non_snake_case)]
+pub fn parse_console_line(line: &str) -> Result<Option<ConsoleCommand>, ParseError> {
+    let mut words = line.split_ascii_whitespace();
+    let Some(name) = words.next() else {
+        return Ok(None);
+    };
+    let argument = words.next();
+    if words.next().is_some() {
+        return Err(ParseError::TooManyArguments);
+    }
+
+    let is = |a: &str, b: &str| a.eq_ignore_ascii_case(b);
+    let no_argument = |command| match argument {
+        None => Ok(command),
+        Some(_) => Err(ParseError::TooManyArguments),
+    };
+
+    let command = match name {
+        n if is(n, "help") || n == "?" => no_argument(ConsoleCommand::Help)?,
+        n if is(n, "zero") => no_argument(ConsoleCommand::Zero)?,
+        n if is(n, "record") => no_argument(ConsoleCommand::Record)?,
+        n if is(n, "save") => no_argument(ConsoleCommand::Save)?,
+
+        n if is(n, "units") => match argument.ok_or(ParseError::MissingArgument)? {
+            a if is(a, "mm") => ConsoleCommand::Units(Units::Millimeter),
+            a if is(a, "inch") || is(a, "in") => ConsoleCommand::Units(Units::Inch),
+            a if is(a, "frac") => ConsoleCommand::Units(Units::FractionalInch),
+            _ => return Err(ParseError::InvalidArgument),
+        },
+
+        n if is(n, "mode") => match argument.ok_or(ParseError::MissingArgument)? {
+            a if is(a, "abs") => ConsoleCommand::Mode(MeasurementMode::Absolute),
+            a if is(a, "hold") => ConsoleCommand::Mode(MeasurementMode::Hold),
+            a if is(a, "minmax") => ConsoleCommand::Mode(MeasurementMode::MinMax),
+            a if is(a, "rel") || is(a, "inc") => ConsoleCommand::Mode(MeasurementMode::Relative),
+            _ => return Err(ParseError::InvalidArgument),
+        },
+
+        n if is(n, "stream") => match argument.ok_or(ParseError::MissingArgument)? {
+            a if is(a, "on") => ConsoleCommand::Stream(true),
+            a if is(a, "off") => ConsoleCommand::Stream(false),
+            _ => return Err(ParseError::InvalidArgument),
+        },
+
+        n if is(n, "freq") => {
+            let frequency_kHz: f64 = argument
+                .ok_or(ParseError::MissingArgument)?
+                .parse()
+                .map_err(|_| ParseError::InvalidArgument)?;
+            // same limits as the USB command
+            let command = Command::SetFrequency {
+                frequency_kHz,
+                adc_sampling_period: crate::AdcSamplingPeriod::CYCLES41_5,
+            };
+            command.validate().map_err(|_| ParseError::OutOfRange)?;
+            ConsoleCommand::Frequency { frequency_kHz }
+        }
+
+        _ => return Err(ParseError::UnknownCommand),
+    };
+    Ok(Some(command))
+}
+
+/// What to send back to the terminal for a byte pushed into a `LineBuffer`.
+#[derive(PartialEq, Debug, Clone, Copy, defmt::Format)]
+pub enum LineEvent {
+    /// Printable character added to the line; echo it.
+    Echo(u8),
+    /// Last character removed; send `ERASE`.
+    Erase,
+    /// Line finished; send "\r\n" and handle `LineBuffer::line`.
+    Done,
+    /// Control character, or the line is full; send nothing.
+    Ignored,
+}
+
+/// Moves the terminal cursor back over the last character and blanks it.
+pub const ERASE: &[u8] = b"\x08 \x08";
+
+/// Collects typed bytes into a line, with backspace editing.
+/// Lines end at CR, LF or CRLF, whichever the terminal sends.
+pub struct LineBuffer<const N: usize> {
+    buf: heapless::Vec<u8, N>,
+    last_was_cr: bool,
+}
+
+impl<const N: usize> Default for LineBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub const fn new() -> Self {
+        LineBuffer {
+            buf: heapless::Vec::new(),
+            last_was_cr: false,
+        }
+    }
+
+    pub fn push(&mut self, byte: u8) -> LineEvent {
+        let last_was_cr = core::mem::replace(&mut self.last_was_cr, byte == b'\r');
+        match byte {
+            b'\n' if last_was_cr => LineEvent::Ignored,
+            b'\r' | b'\n' => LineEvent::Done,
+            // backspace and delete; terminals differ in which one the key sends
+            0x08 | 0x7F => match self.buf.pop() {
+                Some(_) => LineEvent::Erase,
+                None => LineEvent::Ignored,
+            },
+            0x20..=0x7E => match self.buf.push(byte) {
+                Ok(()) => LineEvent::Echo(byte),
+                Err(_) => LineEvent::Ignored,
+            },
+            _ => LineEvent::Ignored,
+        }
+    }
+
+    /// The line so far. Only printable ASCII is kept, so it's always valid.
+    pub fn line(&self) -> &str {
+        core::str::from_utf8(&self.buf).unwrap_or_default()
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+}
+
+pub type ConsoleLine = heapless::String<64>;
+
+/// A reading as printed on the console, at the resolution of `calipertron_core::units` and tagged with the display mode unless it's absolute,
+/// e.g. "12.35 mm\r\n", "0.4860 in HOLD\r\n", "1 3/16 in\r\n" or "2.00 mm MIN 1.50 MAX 3.00\r\n".
+/// `min_max_mm` is only printed in `MinMax` mode.
+pub fn format_position(
+    value_mm: f32,
+    units: Units,
+    mode: MeasurementMode,
+    min_max_mm: Option<(f32, f32)>,
+) -> ConsoleLine {
+    let unit = units.unit();
+    let number = |mm| calipertron_core::units::format(mm, unit);
+    let suffix = unit.suffix();
+
+    let mut line = ConsoleLine::new();
+    // the longest numbers still fit
+    let _ = write!(line, "{} {}", number(value_mm), suffix);
+    let _ = match (mode, min_max_mm) {
+        (MeasurementMode::Absolute, _) => Ok(()),
+        (MeasurementMode::Hold, _) => line.push_str(" HOLD"),
+        (MeasurementMode::Relative, _) => line.push_str(" INC"),
+        (MeasurementMode::MinMax, Some((min, max))) => {
+            write!(line, " MIN {} MAX {}", number(min), number(max)).map_err(|_| ())
+        }
+        (MeasurementMode::MinMax, None) => line.push_str(" MINMAX"),
+    };
+    let _ = line.push_str("\r\n");
+    line
+}
@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+
+/// Longest panic message kept in a crash report; anything after this is cut off.
+pub const MAX_CRASH_MESSAGE_LEN: usize = 128;
+pub type CrashMessage = heapless::String<MAX_CRASH_MESSAGE_LEN>;
+
+/// Registers the Cortex-M3 pushes onto the stack on exception entry.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Default, defmt::Format)]
+pub struct ExceptionFrame {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    /// Address of the faulting instruction.
+    pub pc: u32,
+    pub xpsr: u32,
+}
+
+/// System control block fault registers, see PM0056 section 4.4.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Default, defmt::Format)]
+pub struct FaultStatus {
+    /// Configurable fault status: which memory management, bus or usage fault happened.
+    pub cfsr: u32,
+    /// HardFault status: whether the fault was escalated from one of the above.
+    pub hfsr: u32,
+    /// Faulting address, valid when `cfsr` has MMARVALID set.
+    pub mmfar: u32,
+    /// Faulting address, valid when `cfsr` has BFARVALID set.
+    pub bfar: u32,
+}
+
+/// What the firmware was doing when it last crashed, kept in RAM across the reset that followed.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, defmt::Format)]
+pub enum CrashReport {
+    HardFault {
+        frame: ExceptionFrame,
+        status: FaultStatus,
+    },
+    Panic {
+        /// Panic location and message, truncated to `MAX_CRASH_MESSAGE_LEN` bytes.
+        message: CrashMessage,
+    },
+}
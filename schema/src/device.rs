@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+////////////////////////
+// Reset cause
+//
+// Flags in the STM32F1 RCC_CSR register, see reference manual section 7.3.10.
+// They're sticky until cleared, and a reset from any source also pulls NRST low, so PINRSTF is set alongside the others.
+
+const LPWRRSTF: u32 = 1 << 31;
+const WWDGRSTF: u32 = 1 << 30;
+const IWDGRSTF: u32 = 1 << 29;
+const SFTRSTF: u32 = 1 << 28;
+const PORRSTF: u32 = 1 << 27;
+const PINRSTF: u32 = 1 << 26;
+
+/// Why the device last reset.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, defmt::Format)]
+pub enum ResetCause {
+    PowerOn,
+    /// NRST pin, e.g. the reset button or a debug probe.
+    Pin,
+    Software,
+    /// The firmware stopped making progress and was restarted.
+    IndependentWatchdog,
+    WindowWatchdog,
+    /// Entered standby or stop mode when that's configured to reset.
+    LowPower,
+    Unknown,
+}
+
+impl ResetCause {
+    /// Decode the RCC_CSR register, taking the most specific cause when several flags are set.
+    pub fn from_rcc_csr(csr: u32) -> Self {
+        if csr & IWDGRSTF != 0 {
+            ResetCause::IndependentWatchdog
+        } else if csr & WWDGRSTF != 0 {
+            ResetCause::WindowWatchdog
+        } else if csr & LPWRRSTF != 0 {
+            ResetCause::LowPower
+        } else if csr & SFTRSTF != 0 {
+            ResetCause::Software
+        } else if csr & PORRSTF != 0 {
+            ResetCause::PowerOn
+        } else if csr & PINRSTF != 0 {
+            ResetCause::Pin
+        } else {
+            ResetCause::Unknown
+        }
+    }
+
+    pub fn is_watchdog(&self) -> bool {
+        matches!(
+            self,
+            ResetCause::IndependentWatchdog | ResetCause::WindowWatchdog
+        )
+    }
+}
+
+/// Sent in reply to `Command::GetDeviceInfo`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, defmt::Format)]
+pub struct DeviceInfo {
+    pub reset_cause: ResetCause,
+}
@@ -0,0 +1,111 @@
+use core::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+
+use calipertron_core::units::{format_decimals, MM_PER_INCH};
+
+use crate::Units;
+
+////////////////////////
+// Typing measurements as a USB HID keyboard
+//
+// Reports follow the boot keyboard protocol (HID 1.11 appendix B): | modifiers (1) | reserved (1) | up to 6 key usages |.
+// Usage codes are from the HID usage tables, keyboard/keypad page (0x07).
+
+pub const KEYBOARD_REPORT_SIZE: usize = 8;
+pub type KeyboardReport = [u8; KEYBOARD_REPORT_SIZE];
+
+const USAGE_1: u8 = 0x1E;
+const USAGE_0: u8 = 0x27;
+const USAGE_ENTER: u8 = 0x28;
+const USAGE_TAB: u8 = 0x2B;
+const USAGE_SPACE: u8 = 0x2C;
+const USAGE_MINUS: u8 = 0x2D;
+const USAGE_PERIOD: u8 = 0x37;
+const USAGE_SLASH: u8 = 0x38;
+
+/// Most decimals a measurement can be typed with; more than the scale can resolve in either unit.
+pub const MAX_DECIMALS: u8 = 5;
+
+/// Key pressed after the number, to move to the next spreadsheet cell.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, defmt::Format)]
+pub enum Terminator {
+    None,
+    Tab,
+    Enter,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, defmt::Format)]
+pub struct KeyboardConfig {
+    /// Digits after the decimal point, at most `MAX_DECIMALS`.
+    pub decimals: u8,
+    pub terminator: Terminator,
+}
+
+impl Default for KeyboardConfig {
+    fn default() -> Self {
+        KeyboardConfig {
+            decimals: 2,
+            terminator: Terminator::Enter,
+        }
+    }
+}
+
+/// Longest text `format_measurement` produces: sign, 7 integer digits, point and decimals.
+pub type MeasurementText = heapless::String<16>;
+
+/// The position as typed: a plain number in `units`, with `config.decimals` decimals and a `.` decimal point.
+/// Fractional inches ignore `config.decimals` and are typed like "1 3/16", which spreadsheets read as a mixed number.
+pub fn format_measurement(
+    position_mm: f32,
+    units: Units,
+    config: &KeyboardConfig,
+) -> MeasurementText {
+    let decimals = config.decimals.min(MAX_DECIMALS);
+    let formatted = match units {
+        Units::Millimeter => format_decimals(position_mm, decimals),
+        Units::Inch => format_decimals(position_mm / MM_PER_INCH, decimals),
+        Units::FractionalInch => calipertron_core::units::format(position_mm, units.unit()),
+    };
+
+    let mut text = MeasurementText::new();
+    // only fails for values far beyond anything the scale can measure, which are cut short
+    let _ = write!(text, "{}", formatted);
+    text
+}
+
+/// HID usage for a character `format_measurement` can produce.
+pub fn key_usage(c: char) -> Option<u8> {
+    match c {
+        '0' => Some(USAGE_0),
+        '1'..='9' => Some(USAGE_1 + (c as u8 - b'1')),
+        '.' => Some(USAGE_PERIOD),
+        '-' => Some(USAGE_MINUS),
+        ' ' => Some(USAGE_SPACE),
+        '/' => Some(USAGE_SLASH),
+        '\t' => Some(USAGE_TAB),
+        '\n' => Some(USAGE_ENTER),
+        _ => None,
+    }
+}
+
+fn press(usage: u8) -> KeyboardReport {
+    [0, 0, usage, 0, 0, 0, 0, 0]
+}
+
+const RELEASE: KeyboardReport = [0; KEYBOARD_REPORT_SIZE];
+
+/// Reports that type `text` followed by the terminator: a press and a release for each key, so repeated characters register.
+/// Characters without a key usage are skipped.
+pub fn keystrokes(text: &str, terminator: Terminator) -> impl Iterator<Item = KeyboardReport> + '_ {
+    let terminator = match terminator {
+        Terminator::None => None,
+        Terminator::Tab => Some(USAGE_TAB),
+        Terminator::Enter => Some(USAGE_ENTER),
+    };
+
+    text.chars()
+        .filter_map(key_usage)
+        .chain(terminator)
+        .flat_map(|usage| [press(usage), RELEASE])
+}
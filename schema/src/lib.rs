@@ -2,6 +2,36 @@
 
 use serde::{Deserialize, Serialize};
 
+mod button;
+pub use button::*;
+
+mod config;
+pub use config::*;
+
+mod console;
+pub use console::*;
+
+mod crash;
+pub use crash::*;
+
+mod device;
+pub use device::*;
+
+mod keyboard;
+pub use keyboard::*;
+
+mod noise;
+pub use noise::*;
+
+mod packet;
+pub use packet::*;
+
+mod pga;
+pub use pga::*;
+
+mod self_test;
+pub use self_test::*;
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone, defmt::Format)]
 pub enum AdcSamplingPeriod {
     CYCLES1_5,
@@ -12,6 +42,10 @@ pub enum AdcSamplingPeriod {
     CYCLES55_5,
     CYCLES71_5,
     CYCLES239_5,
+    /// ADC1 and ADC2 in fast interleaved mode on the same pin, each at 1.5 cycles and offset by 7 ADC clocks.
+    /// Twice the rate of `CYCLES1_5`; the only sampling period the hardware allows in this mode.
+    #[allow(non_camel_case_types)]
+    CYCLES1_5_DUAL_INTERLEAVED,
 }
 
 impl AdcSamplingPeriod {
@@ -27,11 +61,101 @@ impl AdcSamplingPeriod {
             CYCLES55_5 => 55.5,
             CYCLES71_5 => 71.5,
             CYCLES239_5 => 239.5,
+            CYCLES1_5_DUAL_INTERLEAVED => 1.5,
         };
 
         let adc_frequency = 12_000_000.;
         let adc_sample_overhead_cycles = 12.5; // see reference manual section 11.6
-        adc_frequency / (sample_cycles + adc_sample_overhead_cycles)
+        let single_adc_Hz = adc_frequency / (sample_cycles + adc_sample_overhead_cycles);
+
+        // combined rate of both ADCs
+        if self.is_dual_interleaved() {
+            2. * single_adc_Hz
+        } else {
+            single_adc_Hz
+        }
+    }
+
+    pub fn is_dual_interleaved(&self) -> bool {
+        *self == AdcSamplingPeriod::CYCLES1_5_DUAL_INTERLEAVED
+    }
+}
+
+/// What starts each ADC conversion.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, defmt::Format)]
+pub enum AdcTrigger {
+    /// Convert back to back as fast as the sampling period allows.
+    /// Phase relative to the PDM signal depends on start-up timing.
+    FreeRunning,
+    /// Convert once every `decimation` steps of the PDM timer, so samples are phase locked to the drive signal.
+    /// At least `MIN_DECIMATION`.
+    PdmTimer { decimation: u16 },
+}
+
+/// Smallest `AdcTrigger::PdmTimer` decimation. TIM3 counts PDM steps up to `decimation - 1`, and with a reload value of 0
+/// it never updates, so there would be no trigger output at all.
+pub const MIN_DECIMATION: u16 = 2;
+
+/// `AdcTrigger::PdmTimer` decimation of the measuring firmware. Its sine/cosine table (`firmware/build.rs`) is generated for this.
+pub const MEASURE_ADC_DECIMATION: u16 = 2;
+
+impl AdcTrigger {
+    /// How the measuring firmware samples; phase is only right for samples taken in step with its table.
+    pub const MEASURE: AdcTrigger = AdcTrigger::PdmTimer {
+        decimation: MEASURE_ADC_DECIMATION,
+    };
+
+    /// Sample rate with the PDM timer running at `frequency_kHz`, or `None` if the ADC can't keep up with (or can't use) the trigger.
+    #[allow(non_snake_case)]
+    pub fn sample_rate_Hz(
+        &self,
+        frequency_kHz: f64,
+        adc_sampling_period: &AdcSamplingPeriod,
+    ) -> Option<f64> {
+        let max_rate = adc_sampling_period.to_Hz();
+        match self {
+            AdcTrigger::FreeRunning => Some(max_rate),
+            // the trigger would only start ADC1; interleaving needs both running continuously
+            AdcTrigger::PdmTimer { .. } if adc_sampling_period.is_dual_interleaved() => None,
+            AdcTrigger::PdmTimer { decimation } if *decimation < MIN_DECIMATION => None,
+            AdcTrigger::PdmTimer { decimation } => {
+                let rate = frequency_kHz * 1000. / *decimation as f64;
+                (rate <= max_rate).then_some(rate)
+            }
+        }
+    }
+}
+
+/// Caliper display mode for commands and output; `calipertron_core::readout::Mode` does the work. Not saved; firmware starts in `Absolute`.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, defmt::Format)]
+pub enum MeasurementMode {
+    Absolute,
+    Hold,
+    MinMax,
+    Relative,
+}
+
+impl From<MeasurementMode> for calipertron_core::readout::Mode {
+    fn from(mode: MeasurementMode) -> Self {
+        use calipertron_core::readout::Mode;
+        match mode {
+            MeasurementMode::Absolute => Mode::Absolute,
+            MeasurementMode::Hold => Mode::Hold,
+            MeasurementMode::MinMax => Mode::MinMax,
+            MeasurementMode::Relative => Mode::Relative,
+        }
+    }
+}
+
+impl From<calipertron_core::readout::Mode> for MeasurementMode {
+    fn from(mode: calipertron_core::readout::Mode) -> Self {
+        use calipertron_core::readout::Mode;
+        match mode {
+            Mode::Absolute => MeasurementMode::Absolute,
+            Mode::Hold => MeasurementMode::Hold,
+            Mode::MinMax => MeasurementMode::MinMax,
+            Mode::Relative => MeasurementMode::Relative,
+        }
     }
 }
 
@@ -43,8 +167,35 @@ pub enum Command {
         adc_sampling_period: AdcSamplingPeriod,
     },
     Record,
+    SaveConfig,
+    LoadConfig,
+    FactoryReset,
+    SetAdcTrigger(AdcTrigger),
+    SetGain(Gain),
+    GetDeviceInfo,
+    GetCrashReport,
+    SetUnits(Units),
+    SetKeyboard(KeyboardConfig),
+    SetQuadrature(QuadratureConfig),
+    SetButtons(ButtonBindings),
+    /// PDM frequencies (kHz) to hop across while measuring, see `DeviceConfig::hop_frequencies_kHz`.
+    SetHopping(HopFrequencies),
+    /// Start (or restart) a display mode at the current position.
+    SetMode(MeasurementMode),
+    /// Drive each emitter pad alone and in adjacent pairs and report what the pickup sees, see `SelfTestReport`.
+    SelfTest,
+    /// Listen with the emitter off across `NOISE_SCAN_FREQUENCIES_kHz`, see `NoiseScanReport`.
+    NoiseScan,
+    /// Whether the measuring firmware scans for noise at startup and measures at the quietest frequency, see `DeviceConfig::pick_quiet_frequency`.
+    SetPickQuietFrequency(bool),
 }
 
+// PDM timer frequencies the firmware will accept. Zero would trip a divide-by-zero in the timer setup and anything above ~1 MHz outruns the GPIO DMA.
+#[allow(non_upper_case_globals)]
+pub const MIN_FREQUENCY_kHz: f64 = 1.;
+#[allow(non_upper_case_globals)]
+pub const MAX_FREQUENCY_kHz: f64 = 1000.;
+
 impl Command {
     pub fn serialize<'a>(&self, buf: &'a mut [u8]) -> Result<&'a mut [u8], postcard::Error> {
         postcard::to_slice(self, buf)
@@ -53,4 +204,99 @@ impl Command {
     pub fn deserialize(bs: &[u8]) -> Option<Self> {
         postcard::from_bytes(bs).ok()
     }
+
+    /// Check parameters are within what the device can do.
+    pub fn validate(&self) -> Result<(), CommandError> {
+        match self {
+            Command::SetFrequency { frequency_kHz, .. } => {
+                // written this way round so NaN is rejected too
+                if (MIN_FREQUENCY_kHz..=MAX_FREQUENCY_kHz).contains(frequency_kHz) {
+                    Ok(())
+                } else {
+                    Err(CommandError::FrequencyOutOfRange)
+                }
+            }
+            Command::SetAdcTrigger(AdcTrigger::PdmTimer { decimation })
+                if *decimation < MIN_DECIMATION =>
+            {
+                Err(CommandError::Malformed)
+            }
+            Command::SetKeyboard(config) if config.decimals > MAX_DECIMALS => {
+                Err(CommandError::Malformed)
+            }
+            Command::SetQuadrature(config) if !config.is_valid() => Err(CommandError::Malformed),
+            Command::SetHopping(frequencies)
+                if !frequencies
+                    .iter()
+                    .all(|&f| (MIN_FREQUENCY_kHz..=MAX_FREQUENCY_kHz).contains(&(f as f64))) =>
+            {
+                Err(CommandError::FrequencyOutOfRange)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, defmt::Format)]
+pub enum CommandError {
+    /// Command is valid but this firmware doesn't implement it.
+    Unsupported,
+    FrequencyOutOfRange,
+    /// A recording is in progress; try again once it has been sent.
+    Busy,
+    /// Packet couldn't be read or deserialized.
+    Malformed,
+    /// Reading or writing the config flash failed.
+    Storage,
+    /// ADC conversions at this sampling period take longer than the time between triggers.
+    TriggerTooFast,
+}
+
+/// Largest serialized `Response`.
+/// Responses that don't fit in one 64 byte bulk packet are split over several, ending with a short (possibly empty) packet,
+/// so read them with a transfer this big.
+pub const MAX_RESPONSE_SIZE: usize = 256;
+
+/// Sent by the device in reply to every command, on its own bulk IN endpoint so it never interleaves with sample data.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, defmt::Format)]
+pub enum Response {
+    Ok,
+    Error(CommandError),
+    DeviceInfo(DeviceInfo),
+    /// `None` if the device hasn't crashed since it was powered on.
+    CrashReport(Option<CrashReport>),
+    SelfTest(SelfTestReport),
+    NoiseScan(NoiseScanReport),
+}
+
+impl From<Result<(), CommandError>> for Response {
+    fn from(result: Result<(), CommandError>) -> Self {
+        match result {
+            Ok(()) => Response::Ok,
+            Err(e) => Response::Error(e),
+        }
+    }
+}
+
+impl Response {
+    pub fn serialize<'a>(&self, buf: &'a mut [u8]) -> Result<&'a mut [u8], postcard::Error> {
+        postcard::to_slice(self, buf)
+    }
+
+    pub fn deserialize(bs: &[u8]) -> Option<Self> {
+        postcard::from_bytes(bs).ok()
+    }
+}
+
+/// CRC-32 (IEEE 802.3, as used by zlib). Bitwise rather than table-driven to keep flash usage down on the device.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
 }
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Gain;
+
+////////////////////////
+// Background noise scan
+//
+// With every pad held low, the capture and correlation of a normal measurement are run at each PDM frequency on a fixed grid.
+// Whatever the correlation picks up then is interference that would land on the phase at that drive frequency,
+// so the quietest frequencies are the ones to measure at.
+
+pub const NOISE_SCAN_BINS: usize = 16;
+/// PDM frequencies scanned, 100 to 400 kHz (signal at 1/128 of that, 0.78 to 3.1 kHz).
+/// All of them are slow enough for the ADC at the measuring firmware's decimation of 2.
+#[allow(non_upper_case_globals)]
+pub const NOISE_SCAN_FREQUENCIES_kHz: [f32; NOISE_SCAN_BINS] = {
+    let mut frequencies = [0.; NOISE_SCAN_BINS];
+    let mut i = 0;
+    while i < NOISE_SCAN_BINS {
+        frequencies[i] = 100. + 20. * i as f32;
+        i += 1;
+    }
+    frequencies
+};
+/// Captures averaged at each frequency.
+pub const NOISE_SCAN_CAPTURES: usize = 8;
+/// How many of the quietest frequencies a scan suggests; enough to hop across, see `Command::SetHopping`.
+pub const NOISE_SCAN_SUGGESTIONS: usize = 3;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, defmt::Format)]
+#[allow(non_snake_case)]
+pub struct NoiseScanReport {
+    /// Amplifier gain the captures were taken at.
+    pub gain: Gain,
+    /// RMS amplitude (ADC counts) the correlation picked up at each of `NOISE_SCAN_FREQUENCIES_kHz`.
+    pub amplitudes: [f32; NOISE_SCAN_BINS],
+    /// Quietest frequencies (kHz), quietest first.
+    pub suggested_kHz: [f32; NOISE_SCAN_SUGGESTIONS],
+}
+
+impl NoiseScanReport {
+    pub fn new(gain: Gain, amplitudes: &[f32; NOISE_SCAN_BINS]) -> Self {
+        let mut order: [usize; NOISE_SCAN_BINS] = core::array::from_fn(|i| i);
+        // ties go to the lower frequency
+        order.sort_unstable_by(|&a, &b| amplitudes[a].total_cmp(&amplitudes[b]).then(a.cmp(&b)));
+        NoiseScanReport {
+            gain,
+            amplitudes: *amplitudes,
+            suggested_kHz: core::array::from_fn(|i| NOISE_SCAN_FREQUENCIES_kHz[order[i]]),
+        }
+    }
+
+    /// The quietest frequency (kHz).
+    #[allow(non_snake_case)]
+    pub fn quietest_kHz(&self) -> f32 {
+        self.suggested_kHz[0]
+    }
+}
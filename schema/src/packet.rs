@@ -0,0 +1,350 @@
+use crate::crc32;
+
+////////////////////////
+// Sample data packets
+//
+// | capture id (2) | sequence (2) | samples (2 each) | crc32 (4) |
+//
+// All fields little endian. The CRC covers everything before it.
+// Sequence restarts at 0 for every capture and wraps; the last packet of a capture may hold fewer samples.
+
+pub const DATA_PACKET_SIZE: usize = 64;
+const HEADER_SIZE: usize = 4;
+const CRC_SIZE: usize = 4;
+pub const SAMPLES_PER_DATA_PACKET: usize = (DATA_PACKET_SIZE - HEADER_SIZE - CRC_SIZE) / 2;
+
+/// Number of samples taken by `Command::Record`.
+pub const RECORD_NUM_SAMPLES: usize = 4096;
+
+/// Number of data packets sent for `Command::Record`: a scale marker (sequence 0), then the samples.
+pub const RECORD_NUM_PACKETS: usize = 1 + RECORD_NUM_SAMPLES.div_ceil(SAMPLES_PER_DATA_PACKET);
+
+// Gap markers use the same layout with three words in place of samples: 0xFFFF then the device's running overrun count (low word first).
+// Scale markers carry two: 0xFFFE then the raw VREFINT reading that applies to the samples after it.
+// ADC samples are 12 bit, so neither marker can be the first sample of a real data packet.
+const GAP_MARKER: u16 = 0xFFFF;
+const SCALE_MARKER: u16 = 0xFFFE;
+
+/// Nominal internal reference voltage of the STM32F103, see datasheet table 12.
+#[allow(non_upper_case_globals)]
+pub const VREFINT_mV: u32 = 1200;
+
+/// Convert a raw sample to millivolts using a VREFINT reading taken with the same supply.
+pub fn to_millivolts(sample: u16, vrefint: u16) -> u16 {
+    (sample as u32 * VREFINT_mV / vrefint.max(1) as u32) as u16
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, defmt::Format)]
+pub enum PacketError {
+    /// Shorter than a header and CRC, or samples don't divide evenly.
+    BadLength,
+    BadCrc,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct DataPacket<'a> {
+    pub capture_id: u16,
+    pub sequence: u16,
+    payload: &'a [u8],
+}
+
+impl<'a> DataPacket<'a> {
+    /// Encode up to `SAMPLES_PER_DATA_PACKET` samples into `buf`, returning the bytes to send.
+    pub fn encode<'b>(
+        capture_id: u16,
+        sequence: u16,
+        samples: &[u16],
+        buf: &'b mut [u8; DATA_PACKET_SIZE],
+    ) -> &'b [u8] {
+        let samples = &samples[..samples.len().min(SAMPLES_PER_DATA_PACKET)];
+
+        buf[0..2].copy_from_slice(&capture_id.to_le_bytes());
+        buf[2..4].copy_from_slice(&sequence.to_le_bytes());
+        for (i, sample) in samples.iter().enumerate() {
+            let offset = HEADER_SIZE + 2 * i;
+            buf[offset..offset + 2].copy_from_slice(&sample.to_le_bytes());
+        }
+
+        let crc_offset = HEADER_SIZE + 2 * samples.len();
+        let crc = crc32(&buf[..crc_offset]);
+        buf[crc_offset..crc_offset + CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+
+        &buf[..crc_offset + CRC_SIZE]
+    }
+
+    /// Encode a marker saying samples were dropped on the device just before this point in the stream.
+    /// `overruns` is the total number of times that has happened.
+    pub fn encode_gap(
+        capture_id: u16,
+        sequence: u16,
+        overruns: u32,
+        buf: &mut [u8; DATA_PACKET_SIZE],
+    ) -> &[u8] {
+        let words = [GAP_MARKER, overruns as u16, (overruns >> 16) as u16];
+        Self::encode(capture_id, sequence, &words, buf)
+    }
+
+    /// Encode a marker giving the VREFINT reading for the samples that follow it, so they can be converted to millivolts.
+    pub fn encode_scale(
+        capture_id: u16,
+        sequence: u16,
+        vrefint: u16,
+        buf: &mut [u8; DATA_PACKET_SIZE],
+    ) -> &[u8] {
+        Self::encode(capture_id, sequence, &[SCALE_MARKER, vrefint], buf)
+    }
+
+    pub fn decode(bytes: &'a [u8]) -> Result<Self, PacketError> {
+        // header and CRC are both even, so an odd length means a partial sample
+        if bytes.len() < HEADER_SIZE + CRC_SIZE || bytes.len() & 1 != 0 {
+            return Err(PacketError::BadLength);
+        }
+
+        let crc_offset = bytes.len() - CRC_SIZE;
+        let stored_crc = u32::from_le_bytes([
+            bytes[crc_offset],
+            bytes[crc_offset + 1],
+            bytes[crc_offset + 2],
+            bytes[crc_offset + 3],
+        ]);
+        if crc32(&bytes[..crc_offset]) != stored_crc {
+            return Err(PacketError::BadCrc);
+        }
+
+        Ok(DataPacket {
+            capture_id: u16::from_le_bytes([bytes[0], bytes[1]]),
+            sequence: u16::from_le_bytes([bytes[2], bytes[3]]),
+            payload: &bytes[HEADER_SIZE..crc_offset],
+        })
+    }
+
+    /// If this is a gap marker, the device's total overrun count.
+    pub fn gap(&self) -> Option<u32> {
+        if self.payload.len() != 6 {
+            return None;
+        }
+
+        let mut words = words(self.payload);
+        match (words.next(), words.next(), words.next()) {
+            (Some(GAP_MARKER), Some(low), Some(high)) => Some(low as u32 | (high as u32) << 16),
+            _ => None,
+        }
+    }
+
+    /// If this is a scale marker, the device's VREFINT reading.
+    pub fn vrefint(&self) -> Option<u16> {
+        if self.payload.len() != 4 {
+            return None;
+        }
+
+        let mut words = words(self.payload);
+        match (words.next(), words.next()) {
+            (Some(SCALE_MARKER), Some(vrefint)) => Some(vrefint),
+            _ => None,
+        }
+    }
+
+    /// Samples in this packet; empty for gap and scale markers.
+    pub fn samples(&self) -> impl Iterator<Item = u16> + 'a {
+        if self.gap().is_some() || self.vrefint().is_some() {
+            words(&self.payload[..0])
+        } else {
+            words(self.payload)
+        }
+    }
+}
+
+fn words(bytes: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    bytes
+        .chunks_exact(2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+////////////////////////
+// Host-side loss detection
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SequenceEvent {
+    /// Packet is the one we expected.
+    InOrder,
+    /// First packet of a capture we haven't seen before. Anything still missing from the previous capture is lost.
+    NewCapture,
+    /// Packets `first..first + missing` (wrapping) of this capture never arrived intact; `corrupted` of them arrived but failed their CRC.
+    Gap {
+        capture_id: u16,
+        first: u16,
+        missing: u16,
+        corrupted: u16,
+    },
+    /// Sequence is behind what we expected (e.g. a duplicate); ignore it.
+    Stale,
+}
+
+/// Tracks capture ids and sequence numbers of received data packets, resyncing to whatever arrives after a gap.
+#[derive(Default, Debug)]
+pub struct SequenceTracker {
+    /// Capture id and the sequence number we expect next.
+    expected: Option<(u16, u16)>,
+    corrupted: u16,
+    /// Number of packets in a capture, if captures have a fixed length.
+    packets_per_capture: Option<u16>,
+}
+
+impl SequenceTracker {
+    /// For open-ended streams, where a capture only ends when the next one starts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// For captures of exactly `packets` packets (e.g. `Command::Record`), so that losing the end of one can be reported by `end`.
+    pub fn with_packets_per_capture(packets: u16) -> Self {
+        Self {
+            packets_per_capture: Some(packets),
+            ..Self::default()
+        }
+    }
+
+    /// Whether the last packet of the current capture has been received (always false for open-ended streams).
+    pub fn complete(&self) -> bool {
+        matches!(
+            (self.expected, self.packets_per_capture),
+            (Some((_, next)), Some(packets)) if next >= packets
+        )
+    }
+
+    /// Call when no more packets are coming for the current capture, e.g. after a timeout.
+    /// Returns a `Gap` for the packets that never arrived at the end of it, if any.
+    pub fn end(&mut self) -> Option<SequenceEvent> {
+        let (capture_id, next) = self.expected?;
+        let packets = self.packets_per_capture?;
+        if next >= packets {
+            return None;
+        }
+        let missing = packets - next;
+        let corrupted = core::mem::take(&mut self.corrupted).min(missing);
+        self.expected = Some((capture_id, packets));
+        Some(SequenceEvent::Gap {
+            capture_id,
+            first: next,
+            missing,
+            corrupted,
+        })
+    }
+
+    /// Note a packet that failed to decode. Its sequence number can't be trusted, so it's reported as part of the next gap.
+    pub fn corrupted(&mut self) {
+        self.corrupted = self.corrupted.saturating_add(1);
+    }
+
+    pub fn received(&mut self, capture_id: u16, sequence: u16) -> SequenceEvent {
+        let corrupted = core::mem::take(&mut self.corrupted);
+
+        let event = match self.expected {
+            Some((expected_capture, expected_sequence)) if expected_capture == capture_id => {
+                let missing = sequence.wrapping_sub(expected_sequence);
+                if missing == 0 {
+                    SequenceEvent::InOrder
+                } else if missing < 0x8000 {
+                    SequenceEvent::Gap {
+                        capture_id,
+                        first: expected_sequence,
+                        missing,
+                        corrupted: corrupted.min(missing),
+                    }
+                } else {
+                    self.corrupted = corrupted;
+                    return SequenceEvent::Stale;
+                }
+            }
+            _ => {
+                if sequence == 0 {
+                    SequenceEvent::NewCapture
+                } else {
+                    // joined partway through a capture
+                    SequenceEvent::Gap {
+                        capture_id,
+                        first: 0,
+                        missing: sequence,
+                        corrupted: corrupted.min(sequence),
+                    }
+                }
+            }
+        };
+
+        self.expected = Some((capture_id, sequence.wrapping_add(1)));
+        event
+    }
+}
+
+/// Whether samples from a stream can be treated as one contiguous signal.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Continuity {
+    /// Samples in this packet directly follow the previous packet's.
+    Continuous,
+    /// Samples were lost just before this packet: `lost_packets` in transit and/or dropped on the device (`overrun`).
+    /// Scale markers also break the stream, since the device pauses sampling to re-read VREFINT.
+    Break { lost_packets: u16, overrun: bool },
+    /// Duplicate or out-of-order packet; drop it.
+    Stale,
+}
+
+/// Checks a stream of data packets for anything that would splice non-adjacent samples together.
+#[derive(Default, Debug)]
+pub struct ContinuityChecker {
+    tracker: SequenceTracker,
+    started: bool,
+    /// Number of breaks seen so far.
+    pub breaks: u32,
+    /// Latest overrun count reported by the device.
+    pub device_overruns: u32,
+    /// Latest VREFINT reading reported by the device.
+    pub vrefint: Option<u16>,
+}
+
+impl ContinuityChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Note a packet that failed to decode.
+    pub fn corrupted(&mut self) {
+        self.tracker.corrupted();
+    }
+
+    pub fn check(&mut self, packet: &DataPacket) -> Continuity {
+        let event = self.tracker.received(packet.capture_id, packet.sequence);
+        let first_packet = !core::mem::replace(&mut self.started, true);
+
+        let (follows_on, lost_packets) = match event {
+            SequenceEvent::Stale => return Continuity::Stale,
+            SequenceEvent::InOrder => (true, 0),
+            // the device restarted the stream: nothing was lost in transit, but it doesn't follow on either
+            SequenceEvent::NewCapture => (first_packet, 0),
+            SequenceEvent::Gap { missing, .. } => (false, missing),
+        };
+
+        // nothing to break if the stream starts with one
+        let rescaled = packet.vrefint().is_some() && !first_packet;
+        if let Some(vrefint) = packet.vrefint() {
+            self.vrefint = Some(vrefint);
+        }
+
+        let overrun = match packet.gap() {
+            Some(overruns) => {
+                self.device_overruns = overruns;
+                true
+            }
+            None => false,
+        };
+
+        if follows_on && !overrun && !rescaled {
+            Continuity::Continuous
+        } else {
+            self.breaks += 1;
+            Continuity::Break {
+                lost_packets,
+                overrun,
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+
+////////////////////////
+// MCP6S21 programmable gain amplifier
+//
+// SPI mode 0,0, one 16-bit word per command: | instruction (8) | data (8) |
+// Instruction is | command (3) | unused (4) | register address (1) |, see datasheet section 5.
+
+const INSTRUCTION_NOP: u8 = 0x00;
+const INSTRUCTION_SHUTDOWN: u8 = 0x20;
+const INSTRUCTION_WRITE_GAIN: u8 = 0x40;
+const INSTRUCTION_WRITE_CHANNEL: u8 = 0x41;
+
+/// Amplifier gain steps, in register code order.
+#[derive(
+    Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, defmt::Format,
+)]
+pub enum Gain {
+    X1,
+    X2,
+    X4,
+    X5,
+    X8,
+    X10,
+    X16,
+    X32,
+}
+
+impl Gain {
+    pub const ALL: [Gain; 8] = [
+        Gain::X1,
+        Gain::X2,
+        Gain::X4,
+        Gain::X5,
+        Gain::X8,
+        Gain::X10,
+        Gain::X16,
+        Gain::X32,
+    ];
+
+    pub fn factor(&self) -> u8 {
+        match self {
+            Gain::X1 => 1,
+            Gain::X2 => 2,
+            Gain::X4 => 4,
+            Gain::X5 => 5,
+            Gain::X8 => 8,
+            Gain::X10 => 10,
+            Gain::X16 => 16,
+            Gain::X32 => 32,
+        }
+    }
+
+    /// Value of the gain register for this gain.
+    pub fn code(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn higher(&self) -> Option<Gain> {
+        Gain::ALL.get(self.code() as usize + 1).copied()
+    }
+
+    pub fn lower(&self) -> Option<Gain> {
+        (self.code() as usize)
+            .checked_sub(1)
+            .map(|code| Gain::ALL[code])
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, defmt::Format)]
+pub enum PgaCommand {
+    Nop,
+    /// Low power mode until the next command.
+    Shutdown,
+    SetGain(Gain),
+    /// The MCP6S21 has a single input, so only channel 0 is meaningful.
+    SetChannel(u8),
+}
+
+impl PgaCommand {
+    /// Bytes to clock out (most significant first) with chip select held low.
+    pub fn encode(&self) -> [u8; 2] {
+        match self {
+            PgaCommand::Nop => [INSTRUCTION_NOP, 0],
+            PgaCommand::Shutdown => [INSTRUCTION_SHUTDOWN, 0],
+            PgaCommand::SetGain(gain) => [INSTRUCTION_WRITE_GAIN, gain.code()],
+            PgaCommand::SetChannel(channel) => [INSTRUCTION_WRITE_CHANNEL, channel & 0b111],
+        }
+    }
+}
+
+// Automatic gain control is `calipertron_core::gain`; the amplifier's steps are the ladder it climbs.
+impl calipertron_core::gain::GainStep for Gain {
+    fn factor(&self) -> u8 {
+        Gain::factor(self)
+    }
+
+    fn higher(&self) -> Option<Gain> {
+        Gain::higher(self)
+    }
+
+    fn lower(&self) -> Option<Gain> {
+        Gain::lower(self)
+    }
+}
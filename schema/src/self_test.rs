@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Gain;
+
+////////////////////////
+// Emitter self-test
+//
+// Each of the 8 drive pads is driven on its own, then each adjacent pair together, with everything else held low,
+// and the amplitude picked up on PB1 at the drive frequency is compared against the other pads.
+// A cracked trace or dead pin reads low; a pad shorted to its neighbour shows up in the pairs.
+//
+// How strongly a pad couples depends on where the slider sits over the scale, so the limits are loose and relative:
+// run it with the slider on the scale and look for the outlier.
+
+pub const SELF_TEST_PADS: usize = 8;
+
+/// Below this (ADC counts at the drive frequency) nothing is coupling at all, whatever the other pads read.
+pub const SELF_TEST_MIN_AMPLITUDE: f32 = 10.;
+/// Allowed spread around the median of the pads (or of the pairs).
+pub const SELF_TEST_LOW_RATIO: f32 = 0.3;
+pub const SELF_TEST_HIGH_RATIO: f32 = 3.;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, defmt::Format)]
+pub enum PadStatus {
+    Ok,
+    Low,
+    High,
+    /// The capture hit the ADC rails, so the amplitude is meaningless; lower the gain.
+    Clipped,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, defmt::Format)]
+pub struct PadResult {
+    pub amplitude: f32,
+    pub status: PadStatus,
+}
+
+/// One capture with some pads driven.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct PadMeasurement {
+    pub amplitude: f32,
+    pub clipped: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, defmt::Format)]
+pub struct SelfTestReport {
+    /// Amplifier gain the captures were taken at.
+    pub gain: Gain,
+    /// Pad `i` (emitter wave `i`, on the pin given by the board's `DRIVE_PINS`) driven alone.
+    pub pads: [PadResult; SELF_TEST_PADS],
+    /// Pads `i` and `i + 1` (wrapping round) driven together.
+    pub pairs: [PadResult; SELF_TEST_PADS],
+}
+
+impl SelfTestReport {
+    pub fn new(
+        gain: Gain,
+        pads: &[PadMeasurement; SELF_TEST_PADS],
+        pairs: &[PadMeasurement; SELF_TEST_PADS],
+    ) -> Self {
+        SelfTestReport {
+            gain,
+            pads: grade(pads),
+            pairs: grade(pairs),
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.pads
+            .iter()
+            .chain(&self.pairs)
+            .all(|r| r.status == PadStatus::Ok)
+    }
+}
+
+fn grade(measurements: &[PadMeasurement; SELF_TEST_PADS]) -> [PadResult; SELF_TEST_PADS] {
+    let mut sorted = measurements.map(|m| m.amplitude);
+    sorted.sort_unstable_by(|a, b| a.total_cmp(b));
+    // upper median, so a single dead pad doesn't drag it down
+    let median = sorted[SELF_TEST_PADS / 2];
+
+    measurements.map(|m| {
+        let status = if m.clipped {
+            PadStatus::Clipped
+        } else if m.amplitude < SELF_TEST_MIN_AMPLITUDE
+            || m.amplitude < median * SELF_TEST_LOW_RATIO
+        {
+            PadStatus::Low
+        } else if m.amplitude > median * SELF_TEST_HIGH_RATIO {
+            PadStatus::High
+        } else {
+            PadStatus::Ok
+        };
+        PadResult {
+            amplitude: m.amplitude,
+            status,
+        }
+    })
+}
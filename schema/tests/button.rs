@@ -0,0 +1,10 @@
+use calipertron_core::button::Gesture;
+use schema::*;
+
+#[test]
+fn bindings() {
+    let bindings = ButtonBindings::default();
+    assert_eq!(bindings.action(Gesture::Short), ButtonAction::Zero);
+    assert_eq!(bindings.action(Gesture::Long), ButtonAction::ToggleUnits);
+    assert_eq!(bindings.action(Gesture::Double), ButtonAction::Send);
+}
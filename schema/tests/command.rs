@@ -0,0 +1,89 @@
+#![allow(non_snake_case)]
+
+use schema::*;
+
+fn set_frequency(frequency_kHz: f64) -> Command {
+    Command::SetFrequency {
+        frequency_kHz,
+        adc_sampling_period: AdcSamplingPeriod::CYCLES41_5,
+    }
+}
+
+#[test]
+fn frequency_range_is_inclusive() {
+    for frequency in [MIN_FREQUENCY_kHz, 100., MAX_FREQUENCY_kHz] {
+        assert_eq!(set_frequency(frequency).validate(), Ok(()), "{frequency}");
+    }
+    for frequency in [0., -100., 0.999, 1000.001, f64::INFINITY] {
+        assert_eq!(
+            set_frequency(frequency).validate(),
+            Err(CommandError::FrequencyOutOfRange),
+            "{frequency}"
+        );
+    }
+}
+
+#[test]
+fn nan_frequency_is_rejected() {
+    assert_eq!(
+        set_frequency(f64::NAN).validate(),
+        Err(CommandError::FrequencyOutOfRange)
+    );
+}
+
+#[test]
+fn decimation_is_checked() {
+    assert_eq!(
+        Command::SetAdcTrigger(AdcTrigger::PdmTimer { decimation: 0 }).validate(),
+        Err(CommandError::Malformed)
+    );
+    // TIM3 would never update, so there'd be no trigger at all
+    assert_eq!(
+        Command::SetAdcTrigger(AdcTrigger::PdmTimer { decimation: 1 }).validate(),
+        Err(CommandError::Malformed)
+    );
+    assert_eq!(
+        Command::SetAdcTrigger(AdcTrigger::PdmTimer {
+            decimation: MIN_DECIMATION
+        })
+        .validate(),
+        Ok(())
+    );
+    assert_eq!(
+        Command::SetAdcTrigger(AdcTrigger::PdmTimer { decimation: 4 }).validate(),
+        Ok(())
+    );
+    assert_eq!(
+        Command::SetAdcTrigger(AdcTrigger::FreeRunning).validate(),
+        Ok(())
+    );
+}
+
+#[test]
+fn commands_without_parameters_are_valid() {
+    for command in [Command::Record, Command::SaveConfig, Command::GetDeviceInfo] {
+        assert_eq!(command.validate(), Ok(()));
+    }
+}
+
+#[test]
+fn ok_and_error_round_trip() {
+    let mut buf = [0u8; MAX_RESPONSE_SIZE];
+    for response in [
+        Response::Ok,
+        Response::Error(CommandError::FrequencyOutOfRange),
+        Response::Error(CommandError::TriggerTooFast),
+    ] {
+        let bytes = response.serialize(&mut buf).unwrap();
+        assert_eq!(Response::deserialize(bytes), Some(response));
+    }
+}
+
+#[test]
+fn response_from_result() {
+    assert_eq!(Response::from(Ok(())), Response::Ok);
+    assert_eq!(
+        Response::from(Err(CommandError::Busy)),
+        Response::Error(CommandError::Busy)
+    );
+}
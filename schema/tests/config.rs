@@ -0,0 +1,175 @@
+use schema::*;
+
+#[test]
+fn crc32_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn config_record_round_trip() {
+    let config = DeviceConfig {
+        frequency_kHz: 100.,
+        adc_sampling_period: AdcSamplingPeriod::CYCLES239_5,
+        adc_trigger: AdcTrigger::PdmTimer { decimation: 4 },
+        gain: Gain::X10,
+        scale_pitch_mm: 9.4,
+        units: Units::Inch,
+        zero_offset_mm: -1.25,
+        calibration: [0.01, -0.02, 0.03, 0., 0., 0.04, -0.05, 0.06],
+        keyboard: KeyboardConfig {
+            decimals: 3,
+            terminator: Terminator::Tab,
+        },
+        quadrature: QuadratureConfig {
+            resolution_mm: 0.001,
+            max_edge_rate_Hz: 4000,
+            index_every: 1000,
+        },
+        buttons: ButtonBindings {
+            short: ButtonAction::Send,
+            long: ButtonAction::Hold,
+            double: ButtonAction::None,
+        },
+        hop_frequencies_kHz: HopFrequencies::from_slice(&[200., 222., 250., 1000.]).unwrap(),
+        pick_quiet_frequency: true,
+    };
+
+    let mut record = [0u8; CONFIG_RECORD_SIZE];
+    config.to_record(42, &mut record).unwrap();
+
+    assert_eq!(DeviceConfig::from_record(&record), Ok((42, config)));
+}
+
+#[test]
+fn record_keeps_zero_offset_within_one_pitch() {
+    let config = DeviceConfig {
+        zero_offset_mm: 3. * 9.4 + 1.,
+        ..DeviceConfig::default()
+    };
+
+    let mut record = [0u8; CONFIG_RECORD_SIZE];
+    config.to_record(1, &mut record).unwrap();
+
+    let (_, loaded) = DeviceConfig::from_record(&record).unwrap();
+    assert!((loaded.zero_offset_mm - 1.).abs() < 1e-4);
+}
+
+#[test]
+fn erased_flash_is_not_a_config() {
+    let record = [0xFFu8; CONFIG_RECORD_SIZE];
+    assert_eq!(DeviceConfig::from_record(&record), Err(ConfigError::Erased));
+}
+
+#[test]
+fn corrupted_record_fails_crc() {
+    let mut record = [0u8; CONFIG_RECORD_SIZE];
+    DeviceConfig::default().to_record(1, &mut record).unwrap();
+
+    record[10] ^= 0x01;
+    assert_eq!(DeviceConfig::from_record(&record), Err(ConfigError::BadCrc));
+}
+
+#[test]
+fn unknown_version_is_rejected() {
+    let mut record = [0u8; CONFIG_RECORD_SIZE];
+    DeviceConfig::default().to_record(1, &mut record).unwrap();
+
+    record[2] = CONFIG_RECORD_VERSION + 1;
+    assert_eq!(
+        DeviceConfig::from_record(&record),
+        Err(ConfigError::UnsupportedVersion(CONFIG_RECORD_VERSION + 1))
+    );
+}
+
+#[test]
+fn record_is_padded_like_erased_flash() {
+    let mut record = [0u8; CONFIG_RECORD_SIZE];
+    DeviceConfig::default().to_record(1, &mut record).unwrap();
+
+    assert_eq!(record[CONFIG_RECORD_SIZE - 1], 0xFF);
+}
+
+#[test]
+fn pdm_timer_trigger_rate_is_exact_and_checked() {
+    let mut config = DeviceConfig {
+        adc_trigger: AdcTrigger::PdmTimer { decimation: 2 },
+        ..DeviceConfig::default()
+    };
+    assert_eq!(config.adc_sample_rate_Hz(), Some(111_000.));
+
+    // 239.5 cycle conversions take ~21us, far longer than the 9us between triggers
+    config.adc_sampling_period = AdcSamplingPeriod::CYCLES239_5;
+    assert_eq!(config.adc_sample_rate_Hz(), None);
+
+    config.adc_trigger = AdcTrigger::PdmTimer { decimation: 0 };
+    assert_eq!(config.adc_sample_rate_Hz(), None);
+    // slow enough for the ADC, but TIM3 would never trigger
+    config.adc_trigger = AdcTrigger::PdmTimer { decimation: 1 };
+    config.frequency_kHz = 10.;
+    assert_eq!(config.adc_sample_rate_Hz(), None);
+}
+
+#[test]
+fn default_config_samples_like_the_measuring_firmware() {
+    let config = DeviceConfig::default();
+    assert_eq!(config.adc_trigger, AdcTrigger::MEASURE);
+    assert_eq!(
+        config.adc_sample_rate_Hz(),
+        Some(222_000. / MEASURE_ADC_DECIMATION as f64)
+    );
+}
+
+#[test]
+fn dual_interleaved_reports_combined_rate() {
+    let dual = AdcSamplingPeriod::CYCLES1_5_DUAL_INTERLEAVED;
+    assert_eq!(dual.to_Hz(), 2. * AdcSamplingPeriod::CYCLES1_5.to_Hz());
+
+    assert_eq!(
+        AdcTrigger::FreeRunning.sample_rate_Hz(222., &dual),
+        Some(dual.to_Hz())
+    );
+    assert_eq!(
+        AdcTrigger::PdmTimer { decimation: 4 }.sample_rate_Hz(222., &dual),
+        None
+    );
+}
+
+#[test]
+fn quadrature_config_is_checked() {
+    let config = QuadratureConfig::default();
+    assert_eq!(Command::SetQuadrature(config).validate(), Ok(()));
+
+    for bad in [
+        QuadratureConfig {
+            resolution_mm: 0.,
+            ..config
+        },
+        QuadratureConfig {
+            resolution_mm: f32::NAN,
+            ..config
+        },
+        QuadratureConfig {
+            max_edge_rate_Hz: 0,
+            ..config
+        },
+        QuadratureConfig {
+            max_edge_rate_Hz: MAX_QUADRATURE_EDGE_RATE_Hz + 1,
+            ..config
+        },
+    ] {
+        assert_eq!(
+            Command::SetQuadrature(bad).validate(),
+            Err(CommandError::Malformed)
+        );
+    }
+}
+
+#[test]
+fn hop_frequencies_are_checked() {
+    let hops = |f: &[f32]| Command::SetHopping(HopFrequencies::from_slice(f).unwrap()).validate();
+    assert_eq!(hops(&[]), Ok(()));
+    assert_eq!(hops(&[200., 222., 250.]), Ok(()));
+    assert_eq!(hops(&[222., 0.]), Err(CommandError::FrequencyOutOfRange));
+    assert_eq!(hops(&[f32::NAN]), Err(CommandError::FrequencyOutOfRange));
+    assert_eq!(hops(&[2000.]), Err(CommandError::FrequencyOutOfRange));
+}
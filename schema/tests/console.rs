@@ -0,0 +1,165 @@
+use schema::*;
+
+#[test]
+fn parses_commands() {
+    assert_eq!(parse_console_line(""), Ok(None));
+    assert_eq!(parse_console_line("   "), Ok(None));
+    assert_eq!(parse_console_line("zero"), Ok(Some(ConsoleCommand::Zero)));
+    assert_eq!(parse_console_line(" ZERO "), Ok(Some(ConsoleCommand::Zero)));
+    assert_eq!(
+        parse_console_line("units mm"),
+        Ok(Some(ConsoleCommand::Units(Units::Millimeter)))
+    );
+    assert_eq!(
+        parse_console_line("units Inch"),
+        Ok(Some(ConsoleCommand::Units(Units::Inch)))
+    );
+    assert_eq!(
+        parse_console_line("units frac"),
+        Ok(Some(ConsoleCommand::Units(Units::FractionalInch)))
+    );
+    assert_eq!(
+        parse_console_line("freq 222"),
+        Ok(Some(ConsoleCommand::Frequency {
+            frequency_kHz: 222.
+        }))
+    );
+    assert_eq!(
+        parse_console_line("stream off"),
+        Ok(Some(ConsoleCommand::Stream(false)))
+    );
+    assert_eq!(
+        parse_console_line("record"),
+        Ok(Some(ConsoleCommand::Record))
+    );
+    assert_eq!(parse_console_line("?"), Ok(Some(ConsoleCommand::Help)));
+    assert_eq!(
+        parse_console_line("mode minmax"),
+        Ok(Some(ConsoleCommand::Mode(MeasurementMode::MinMax)))
+    );
+    assert_eq!(
+        parse_console_line("mode INC"),
+        Ok(Some(ConsoleCommand::Mode(MeasurementMode::Relative)))
+    );
+}
+
+#[test]
+fn rejects_bad_input() {
+    assert_eq!(
+        parse_console_line("launch"),
+        Err(ParseError::UnknownCommand)
+    );
+    assert_eq!(
+        parse_console_line("units"),
+        Err(ParseError::MissingArgument)
+    );
+    assert_eq!(
+        parse_console_line("units furlong"),
+        Err(ParseError::InvalidArgument)
+    );
+    assert_eq!(
+        parse_console_line("zero now"),
+        Err(ParseError::TooManyArguments)
+    );
+    assert_eq!(
+        parse_console_line("freq 222 kHz"),
+        Err(ParseError::TooManyArguments)
+    );
+    assert_eq!(
+        parse_console_line("freq fast"),
+        Err(ParseError::InvalidArgument)
+    );
+    assert_eq!(parse_console_line("freq 0"), Err(ParseError::OutOfRange));
+    assert_eq!(parse_console_line("freq NaN"), Err(ParseError::OutOfRange));
+}
+
+fn type_into<const N: usize>(buf: &mut LineBuffer<N>, input: &[u8]) -> Vec<LineEvent> {
+    input.iter().map(|&b| buf.push(b)).collect()
+}
+
+#[test]
+fn line_editing() {
+    let mut buf = LineBuffer::<16>::new();
+    let events = type_into(&mut buf, b"zx\x7fero\r");
+    assert_eq!(
+        events,
+        vec![
+            LineEvent::Echo(b'z'),
+            LineEvent::Echo(b'x'),
+            LineEvent::Erase,
+            LineEvent::Echo(b'e'),
+            LineEvent::Echo(b'r'),
+            LineEvent::Echo(b'o'),
+            LineEvent::Done,
+        ]
+    );
+    assert_eq!(buf.line(), "zero");
+
+    buf.clear();
+    // LF following CR is the same line ending, but a lone LF ends a line too
+    assert_eq!(buf.push(b'\n'), LineEvent::Ignored);
+    assert_eq!(buf.push(b'\n'), LineEvent::Done);
+    assert_eq!(buf.line(), "");
+
+    // nothing to erase
+    assert_eq!(buf.push(0x08), LineEvent::Ignored);
+    // no escape sequences (arrow keys), but the line can still be typed
+    assert_eq!(buf.push(0x1B), LineEvent::Ignored);
+}
+
+#[test]
+fn full_line_ignores_extra_input() {
+    let mut buf = LineBuffer::<4>::new();
+    let events = type_into(&mut buf, b"recorded");
+    assert_eq!(events[4..], [LineEvent::Ignored; 4]);
+    assert_eq!(buf.line(), "reco");
+}
+
+#[test]
+fn position_formatting() {
+    use MeasurementMode::*;
+    assert_eq!(
+        format_position(12.3456, Units::Millimeter, Absolute, None),
+        "12.35 mm\r\n"
+    );
+    assert_eq!(
+        format_position(-25.4, Units::Inch, Absolute, None),
+        "-1.0000 in\r\n"
+    );
+    assert_eq!(
+        format_position(12.345, Units::Millimeter, Hold, None),
+        "12.35 mm HOLD\r\n"
+    );
+    assert_eq!(
+        format_position(-2.5, Units::Millimeter, Relative, None),
+        "-2.50 mm INC\r\n"
+    );
+    assert_eq!(
+        format_position(2., Units::Millimeter, MinMax, Some((1.5, 3.))),
+        "2.00 mm MIN 1.50 MAX 3.00\r\n"
+    );
+
+    assert_eq!(
+        format_position(25.4 * 1.1875, Units::FractionalInch, Absolute, None),
+        "1 3/16 in\r\n"
+    );
+
+    // longest possible line
+    let max = -9_999_999.;
+    let line = format_position(max, Units::Inch, MinMax, Some((max, max)));
+    assert!(line.ends_with("\r\n"));
+}
+
+#[test]
+fn measurement_modes_map_onto_readout_modes() {
+    use calipertron_core::readout::Mode;
+    for (mode, readout_mode) in [
+        (MeasurementMode::Absolute, Mode::Absolute),
+        (MeasurementMode::Hold, Mode::Hold),
+        (MeasurementMode::MinMax, Mode::MinMax),
+        (MeasurementMode::Relative, Mode::Relative),
+    ] {
+        assert_eq!(Mode::from(mode), readout_mode);
+        assert_eq!(MeasurementMode::from(readout_mode), mode);
+    }
+}
@@ -0,0 +1,35 @@
+use schema::*;
+
+#[test]
+fn largest_crash_report_fits_in_a_response() {
+    let mut message = CrashMessage::new();
+    for _ in 0..MAX_CRASH_MESSAGE_LEN {
+        message.push('x').unwrap();
+    }
+    let frame = ExceptionFrame {
+        r0: u32::MAX,
+        r1: u32::MAX,
+        r2: u32::MAX,
+        r3: u32::MAX,
+        r12: u32::MAX,
+        lr: u32::MAX,
+        pc: u32::MAX,
+        xpsr: u32::MAX,
+    };
+    let status = FaultStatus {
+        cfsr: u32::MAX,
+        hfsr: u32::MAX,
+        mmfar: u32::MAX,
+        bfar: u32::MAX,
+    };
+
+    for report in [
+        CrashReport::Panic { message },
+        CrashReport::HardFault { frame, status },
+    ] {
+        let response = Response::CrashReport(Some(report));
+        let mut buf = [0u8; MAX_RESPONSE_SIZE];
+        let bytes = response.serialize(&mut buf).unwrap();
+        assert_eq!(Response::deserialize(bytes), Some(response));
+    }
+}
@@ -0,0 +1,30 @@
+use schema::*;
+
+#[test]
+fn reset_cause_from_rcc_flags() {
+    assert_eq!(ResetCause::from_rcc_csr(0), ResetCause::Unknown);
+    // power on also sets the pin flag
+    assert_eq!(ResetCause::from_rcc_csr(0x0C00_0000), ResetCause::PowerOn);
+    assert_eq!(ResetCause::from_rcc_csr(0x0400_0000), ResetCause::Pin);
+    assert_eq!(ResetCause::from_rcc_csr(0x1400_0000), ResetCause::Software);
+
+    let watchdog = ResetCause::from_rcc_csr(0x2400_0000);
+    assert_eq!(watchdog, ResetCause::IndependentWatchdog);
+    assert!(watchdog.is_watchdog());
+
+    // flags are sticky, so a watchdog reset after power on shows both
+    assert_eq!(
+        ResetCause::from_rcc_csr(0x2C00_0000),
+        ResetCause::IndependentWatchdog
+    );
+}
+
+#[test]
+fn device_info_round_trip() {
+    let response = Response::DeviceInfo(DeviceInfo {
+        reset_cause: ResetCause::IndependentWatchdog,
+    });
+    let mut buf = [0u8; 64];
+    let bytes = response.serialize(&mut buf).unwrap();
+    assert_eq!(Response::deserialize(bytes), Some(response));
+}
@@ -0,0 +1,82 @@
+use schema::*;
+
+#[test]
+fn measurement_formatting() {
+    let config = KeyboardConfig {
+        decimals: 2,
+        terminator: Terminator::Enter,
+    };
+    assert_eq!(
+        format_measurement(12.345, Units::Millimeter, &config),
+        "12.35"
+    );
+    assert_eq!(
+        format_measurement(-3.1, Units::Millimeter, &config),
+        "-3.10"
+    );
+    assert_eq!(format_measurement(25.4, Units::Inch, &config), "1.00");
+    assert_eq!(
+        format_measurement(-0.001, Units::Millimeter, &config),
+        "0.00"
+    );
+
+    let config = KeyboardConfig {
+        decimals: 0,
+        ..config
+    };
+    assert_eq!(format_measurement(7.6, Units::Millimeter, &config), "8");
+
+    // decimals don't apply to fractions
+    assert_eq!(
+        format_measurement(-25.4 * 2.25, Units::FractionalInch, &config),
+        "-2 1/4"
+    );
+}
+
+#[test]
+fn decimals_are_capped() {
+    let config = KeyboardConfig {
+        decimals: 200,
+        terminator: Terminator::None,
+    };
+    assert_eq!(
+        format_measurement(1.0, Units::Millimeter, &config),
+        "1.00000"
+    );
+    assert_eq!(
+        Command::SetKeyboard(config).validate(),
+        Err(CommandError::Malformed)
+    );
+}
+
+#[test]
+fn measurement_text_has_key_usages() {
+    let usages: Vec<_> = "-1234567890.".chars().map(key_usage).collect();
+    assert_eq!(
+        usages,
+        [0x2D, 0x1E, 0x1F, 0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x37]
+            .map(Some)
+            .to_vec()
+    );
+    assert_eq!(key_usage(' '), Some(0x2C));
+    assert_eq!(key_usage('/'), Some(0x38));
+}
+
+#[test]
+fn keystrokes_press_and_release_each_key() {
+    let reports: Vec<_> = keystrokes("11", Terminator::Tab).collect();
+    let release = [0; KEYBOARD_REPORT_SIZE];
+    assert_eq!(
+        reports,
+        vec![
+            [0, 0, 0x1E, 0, 0, 0, 0, 0],
+            release,
+            [0, 0, 0x1E, 0, 0, 0, 0, 0],
+            release,
+            [0, 0, 0x2B, 0, 0, 0, 0, 0],
+            release,
+        ]
+    );
+
+    assert_eq!(keystrokes("5", Terminator::None).count(), 2);
+}
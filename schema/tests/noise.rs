@@ -0,0 +1,37 @@
+use schema::*;
+
+#[test]
+fn scan_covers_frequencies_the_adc_keeps_up_with() {
+    assert_eq!(NOISE_SCAN_FREQUENCIES_kHz[0], 100.);
+    assert_eq!(NOISE_SCAN_FREQUENCIES_kHz[NOISE_SCAN_BINS - 1], 400.);
+    let trigger = AdcTrigger::PdmTimer { decimation: 2 };
+    for frequency in NOISE_SCAN_FREQUENCIES_kHz {
+        assert!(trigger
+            .sample_rate_Hz(frequency as f64, &AdcSamplingPeriod::CYCLES41_5)
+            .is_some());
+    }
+}
+
+#[test]
+fn suggests_the_quietest_frequencies() {
+    let mut amplitudes = [5.; NOISE_SCAN_BINS];
+    amplitudes[3] = 1.;
+    amplitudes[10] = 0.5;
+    // an interferer near 222kHz / 128
+    amplitudes[6] = 80.;
+    let report = NoiseScanReport::new(Gain::X4, &amplitudes);
+
+    // the rest tie, so the lowest of them comes next
+    assert_eq!(report.suggested_kHz, [300., 160., 100.]);
+    assert_eq!(report.quietest_kHz(), 300.);
+    assert_eq!(report.amplitudes, amplitudes);
+}
+
+#[test]
+fn report_fits_in_a_response() {
+    let report = NoiseScanReport::new(Gain::X32, &[f32::MAX; NOISE_SCAN_BINS]);
+    let response = Response::NoiseScan(report);
+    let mut buf = [0u8; MAX_RESPONSE_SIZE];
+    let bytes = response.serialize(&mut buf).unwrap();
+    assert_eq!(Response::deserialize(bytes), Some(response));
+}
@@ -0,0 +1,234 @@
+use schema::*;
+
+#[test]
+fn data_packet_round_trip() {
+    let samples: Vec<u16> = (0..SAMPLES_PER_DATA_PACKET as u16)
+        .map(|x| x * 100)
+        .collect();
+    let mut buf = [0u8; DATA_PACKET_SIZE];
+    let bytes = DataPacket::encode(7, 300, &samples, &mut buf);
+    assert_eq!(bytes.len(), DATA_PACKET_SIZE);
+
+    let packet = DataPacket::decode(bytes).unwrap();
+    assert_eq!(packet.capture_id, 7);
+    assert_eq!(packet.sequence, 300);
+    assert_eq!(packet.samples().collect::<Vec<_>>(), samples);
+}
+
+#[test]
+fn short_final_packet() {
+    let mut buf = [0u8; DATA_PACKET_SIZE];
+    let bytes = DataPacket::encode(1, 2, &[1, 2, 3], &mut buf);
+
+    let packet = DataPacket::decode(bytes).unwrap();
+    assert_eq!(packet.samples().collect::<Vec<_>>(), vec![1, 2, 3]);
+}
+
+#[test]
+fn corrupted_packet_is_rejected() {
+    let mut buf = [0u8; DATA_PACKET_SIZE];
+    let bytes = DataPacket::encode(1, 2, &[1, 2, 3], &mut buf).to_vec();
+
+    let mut flipped = bytes.clone();
+    flipped[5] ^= 0x80;
+    assert_eq!(DataPacket::decode(&flipped), Err(PacketError::BadCrc));
+    assert_eq!(
+        DataPacket::decode(&bytes[..bytes.len() - 1]),
+        Err(PacketError::BadLength)
+    );
+    assert_eq!(DataPacket::decode(&[]), Err(PacketError::BadLength));
+}
+
+#[test]
+fn tracker_reports_lost_and_corrupted_packets() {
+    let mut tracker = SequenceTracker::new();
+    assert_eq!(tracker.received(3, 0), SequenceEvent::NewCapture);
+    assert_eq!(tracker.received(3, 1), SequenceEvent::InOrder);
+
+    // 2 corrupted, 3 and 4 lost
+    tracker.corrupted();
+    assert_eq!(
+        tracker.received(3, 5),
+        SequenceEvent::Gap {
+            capture_id: 3,
+            first: 2,
+            missing: 3,
+            corrupted: 1
+        }
+    );
+
+    // resynced
+    assert_eq!(tracker.received(3, 6), SequenceEvent::InOrder);
+    assert_eq!(tracker.received(3, 6), SequenceEvent::Stale);
+    assert_eq!(tracker.received(3, 7), SequenceEvent::InOrder);
+}
+
+#[test]
+fn tracker_follows_new_captures_and_wraparound() {
+    let mut tracker = SequenceTracker::new();
+    assert_eq!(
+        tracker.received(1, 4),
+        SequenceEvent::Gap {
+            capture_id: 1,
+            first: 0,
+            missing: 4,
+            corrupted: 0
+        }
+    );
+
+    assert_eq!(tracker.received(2, 0), SequenceEvent::NewCapture);
+    assert_eq!(tracker.received(2, 1), SequenceEvent::InOrder);
+
+    let mut tracker = SequenceTracker::new();
+    tracker.received(1, 0);
+    for sequence in 1..=u16::MAX {
+        tracker.received(1, sequence);
+    }
+    assert_eq!(tracker.received(1, 0), SequenceEvent::InOrder);
+}
+
+#[test]
+fn tracker_reports_lost_final_packet() {
+    let mut tracker = SequenceTracker::with_packets_per_capture(4);
+    assert_eq!(tracker.received(5, 0), SequenceEvent::NewCapture);
+    assert_eq!(tracker.received(5, 1), SequenceEvent::InOrder);
+    assert_eq!(tracker.received(5, 2), SequenceEvent::InOrder);
+    assert!(!tracker.complete());
+
+    // 3 never arrives
+    assert_eq!(
+        tracker.end(),
+        Some(SequenceEvent::Gap {
+            capture_id: 5,
+            first: 3,
+            missing: 1,
+            corrupted: 0
+        })
+    );
+    // reported once
+    assert_eq!(tracker.end(), None);
+
+    let mut tracker = SequenceTracker::with_packets_per_capture(4);
+    for sequence in 0..4 {
+        tracker.received(6, sequence);
+    }
+    assert!(tracker.complete());
+    assert_eq!(tracker.end(), None);
+
+    // open-ended streams have no tail to lose
+    let mut tracker = SequenceTracker::new();
+    tracker.received(6, 0);
+    assert!(!tracker.complete());
+    assert_eq!(tracker.end(), None);
+}
+
+#[test]
+fn gap_marker_round_trip() {
+    let mut buf = [0u8; DATA_PACKET_SIZE];
+    let bytes = DataPacket::encode_gap(1, 9, 70_000, &mut buf);
+
+    let packet = DataPacket::decode(bytes).unwrap();
+    assert_eq!(packet.gap(), Some(70_000));
+    assert_eq!(packet.samples().count(), 0);
+
+    // three real samples aren't a gap marker
+    let bytes = DataPacket::encode(1, 9, &[4095, 1, 0], &mut buf);
+    assert_eq!(DataPacket::decode(bytes).unwrap().gap(), None);
+}
+
+#[test]
+fn continuity_checker_flags_lost_and_dropped_samples() {
+    let mut checker = ContinuityChecker::new();
+    let mut buf = [0u8; DATA_PACKET_SIZE];
+
+    let check = |checker: &mut ContinuityChecker, bytes: &[u8]| {
+        checker.check(&DataPacket::decode(bytes).unwrap())
+    };
+
+    assert_eq!(
+        check(&mut checker, DataPacket::encode(1, 0, &[1], &mut buf)),
+        Continuity::Continuous
+    );
+    assert_eq!(
+        check(&mut checker, DataPacket::encode(1, 1, &[2], &mut buf)),
+        Continuity::Continuous
+    );
+
+    // device dropped samples
+    assert_eq!(
+        check(&mut checker, DataPacket::encode_gap(1, 2, 1, &mut buf)),
+        Continuity::Break {
+            lost_packets: 0,
+            overrun: true
+        }
+    );
+    assert_eq!(checker.device_overruns, 1);
+
+    // host lost packets 3 and 4
+    assert_eq!(
+        check(&mut checker, DataPacket::encode(1, 5, &[3], &mut buf)),
+        Continuity::Break {
+            lost_packets: 2,
+            overrun: false
+        }
+    );
+    assert_eq!(
+        check(&mut checker, DataPacket::encode(1, 5, &[3], &mut buf)),
+        Continuity::Stale
+    );
+
+    // stream restarted
+    assert_eq!(
+        check(&mut checker, DataPacket::encode(2, 0, &[4], &mut buf)),
+        Continuity::Break {
+            lost_packets: 0,
+            overrun: false
+        }
+    );
+    assert_eq!(checker.breaks, 3);
+}
+
+#[test]
+fn scale_marker_round_trip() {
+    let mut buf = [0u8; DATA_PACKET_SIZE];
+    let packet = DataPacket::decode(DataPacket::encode_scale(3, 0, 1490, &mut buf)).unwrap();
+    assert_eq!(packet.vrefint(), Some(1490));
+    assert_eq!(packet.gap(), None);
+    assert_eq!(packet.samples().count(), 0);
+
+    // ordinary samples aren't mistaken for a marker
+    let packet = DataPacket::decode(DataPacket::encode(3, 1, &[1490, 12], &mut buf)).unwrap();
+    assert_eq!(packet.vrefint(), None);
+
+    assert_eq!(to_millivolts(1490, 1490), 1200);
+    assert_eq!(to_millivolts(2980, 1490), 2400);
+}
+
+#[test]
+fn continuity_checker_breaks_on_rescale() {
+    let mut buf = [0u8; DATA_PACKET_SIZE];
+    let mut checker = ContinuityChecker::new();
+
+    let scale = DataPacket::encode_scale(1, 0, 1500, &mut buf).to_vec();
+    assert_eq!(
+        checker.check(&DataPacket::decode(&scale).unwrap()),
+        Continuity::Continuous
+    );
+    assert_eq!(checker.vrefint, Some(1500));
+
+    let samples = DataPacket::encode(1, 1, &[1, 2], &mut buf).to_vec();
+    assert_eq!(
+        checker.check(&DataPacket::decode(&samples).unwrap()),
+        Continuity::Continuous
+    );
+
+    let scale = DataPacket::encode_scale(1, 2, 1510, &mut buf).to_vec();
+    assert_eq!(
+        checker.check(&DataPacket::decode(&scale).unwrap()),
+        Continuity::Break {
+            lost_packets: 0,
+            overrun: false
+        }
+    );
+    assert_eq!(checker.vrefint, Some(1510));
+}
@@ -0,0 +1,32 @@
+use calipertron_core::gain::AutoGain;
+use schema::*;
+
+#[test]
+fn pga_commands_match_datasheet() {
+    assert_eq!(PgaCommand::Nop.encode(), [0x00, 0x00]);
+    assert_eq!(PgaCommand::Shutdown.encode(), [0x20, 0x00]);
+    assert_eq!(PgaCommand::SetChannel(0).encode(), [0x41, 0x00]);
+
+    let codes: Vec<_> = Gain::ALL
+        .iter()
+        .map(|&gain| PgaCommand::SetGain(gain).encode())
+        .collect();
+    assert_eq!(codes, (0..8).map(|code| [0x40, code]).collect::<Vec<_>>());
+}
+
+#[test]
+fn gain_steps() {
+    assert_eq!(Gain::X1.lower(), None);
+    assert_eq!(Gain::X4.higher(), Some(Gain::X5));
+    assert_eq!(Gain::X5.lower(), Some(Gain::X4));
+    assert_eq!(Gain::X32.higher(), None);
+}
+
+#[test]
+fn auto_gain_climbs_the_amplifier_steps() {
+    let mut agc = AutoGain::new(Gain::X4);
+    // ~10% of full scale
+    assert_eq!(agc.update(&[1800, 2200]), Some(Gain::X5));
+    // small swing, but pinned against the top rail by the amplified DC offset
+    assert_eq!(agc.update(&[3900, 4095]), Some(Gain::X4));
+}
@@ -0,0 +1,78 @@
+use schema::*;
+
+fn measured(amplitudes: [f32; SELF_TEST_PADS]) -> [PadMeasurement; SELF_TEST_PADS] {
+    amplitudes.map(|amplitude| PadMeasurement {
+        amplitude,
+        clipped: false,
+    })
+}
+
+fn statuses(results: &[PadResult; SELF_TEST_PADS]) -> [PadStatus; SELF_TEST_PADS] {
+    results.map(|r| r.status)
+}
+
+#[test]
+fn healthy_board_passes() {
+    // coupling varies with the slider position, so pads don't all read the same
+    let pads = measured([120., 95., 60., 80., 130., 110., 70., 90.]);
+    let pairs = measured([200., 150., 130., 190., 220., 170., 150., 190.]);
+    let report = SelfTestReport::new(Gain::X4, &pads, &pairs);
+
+    assert!(report.passed());
+    assert_eq!(report.pads[2].amplitude, 60.);
+}
+
+#[test]
+fn dead_pad_reads_low() {
+    let pads = measured([120., 95., 3., 80., 130., 110., 70., 90.]);
+    let pairs = measured([200., 95., 90., 190., 220., 170., 150., 190.]);
+    let report = SelfTestReport::new(Gain::X4, &pads, &pairs);
+
+    assert!(!report.passed());
+    assert_eq!(
+        statuses(&report.pads),
+        [
+            PadStatus::Ok,
+            PadStatus::Ok,
+            PadStatus::Low,
+            PadStatus::Ok,
+            PadStatus::Ok,
+            PadStatus::Ok,
+            PadStatus::Ok,
+            PadStatus::Ok,
+        ]
+    );
+    assert!(report.pairs.iter().all(|r| r.status == PadStatus::Ok));
+}
+
+#[test]
+fn nothing_coupling_is_low_even_if_uniform() {
+    let pads = measured([2.; SELF_TEST_PADS]);
+    let report = SelfTestReport::new(Gain::X1, &pads, &pads);
+    assert!(report.pads.iter().all(|r| r.status == PadStatus::Low));
+}
+
+#[test]
+fn outliers_and_clipping() {
+    let mut pads = measured([100.; SELF_TEST_PADS]);
+    pads[5].amplitude = 400.;
+    pads[6].clipped = true;
+    let report = SelfTestReport::new(Gain::X32, &pads, &measured([180.; SELF_TEST_PADS]));
+
+    assert_eq!(report.pads[5].status, PadStatus::High);
+    assert_eq!(report.pads[6].status, PadStatus::Clipped);
+    assert!(!report.passed());
+}
+
+#[test]
+fn report_fits_in_a_response() {
+    let report = SelfTestReport::new(
+        Gain::X32,
+        &measured([f32::MAX; SELF_TEST_PADS]),
+        &measured([f32::MAX; SELF_TEST_PADS]),
+    );
+    let response = Response::SelfTest(report);
+    let mut buf = [0u8; MAX_RESPONSE_SIZE];
+    let bytes = response.serialize(&mut buf).unwrap();
+    assert_eq!(Response::deserialize(bytes), Some(response));
+}